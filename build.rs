@@ -1,4 +1,6 @@
 use pelite::resources::version_info::{Language, VersionInfo};
+use std::collections::HashMap;
+use tinyjson::JsonValue;
 
 fn read_pe_version_info<'a>(image: &'a [u8]) -> Option<VersionInfo<'a>> {
     pelite::PeFile::from_bytes(image).ok()?.resources().ok()?.version_info().ok()
@@ -22,6 +24,98 @@ fn detect_hachimi_version() {
     );
 }
 
+fn read_exe_patches_json(name: &str) -> Vec<HashMap<String, JsonValue>> {
+    let path = std::path::Path::new("exe_patches").join(name);
+    let Ok(json_str) = std::fs::read_to_string(&path) else {
+        println!("cargo:warning=exe_patches/{} not found", name);
+        return Vec::new();
+    };
+
+    let JsonValue::Array(entries) = json_str.parse().unwrap_or_else(|_| panic!("valid json in exe_patches/{}", name)) else {
+        panic!("exe_patches/{} must be an array", name);
+    };
+    entries.into_iter().map(|entry| match entry {
+        JsonValue::Object(entry) => entry,
+        _ => panic!("exe_patches/{} entries must be objects", name)
+    }).collect()
+}
+
+fn string_field(entry: &HashMap<String, JsonValue>, name: &str, key: &str) -> String {
+    match entry.get(key) {
+        Some(JsonValue::String(s)) => s.clone(),
+        _ => panic!("exe_patches/{} entry is missing \"{}\"", name, key)
+    }
+}
+
+// Precomputed bsdiff patches for the JP Steam exe, keyed by the hash of the original,
+// and every exe they ever produced
+fn generate_exe_patches() {
+    println!("cargo:rerun-if-changed=exe_patches");
+
+    let out_path = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("exe_patches.rs");
+    let entries = read_exe_patches_json("index.json");
+    // without patches JP Steam installs are refused outright, that can't ship
+    println!("cargo:rerun-if-env-changed=ALLOW_NO_EXE_PATCHES");
+    if entries.is_empty() && std::env::var("PROFILE").as_deref() == Ok("release") && std::env::var("ALLOW_NO_EXE_PATCHES").is_err() {
+        panic!(
+            "exe_patches/index.json has no patches, JP Steam installs would never work. \
+            Add one with examples/make_exe_patch.rs, or set ALLOW_NO_EXE_PATCHES=1 for a local build."
+        );
+    }
+
+    let mut code = String::from("pub const EXE_PATCHES: &[ExePatch] = &[\n");
+    // (build, source_sha256, sha256)
+    let mut patched = Vec::new();
+    for entry in &entries {
+        let field = |key: &str| string_field(entry, "index.json", key);
+        let product_version = match entry.get("product_version") {
+            Some(JsonValue::String(s)) => format!("Some({:?})", s),
+            _ => "None".to_owned()
+        };
+        let timestamp = match entry.get("timestamp") {
+            Some(JsonValue::Number(n)) => format!("Some({})", *n as u32),
+            _ => "None".to_owned()
+        };
+
+        let patch_path = std::fs::canonicalize(std::path::Path::new("exe_patches").join(field("patch")))
+            .expect("patch file listed in exe_patches/index.json");
+        code += &format!(
+            "    ExePatch {{ build: {:?}, product_version: {}, timestamp: {}, source_sha256: {:?}, sha256: {:?}, patch: include_bytes!({:?}) }},\n",
+            field("build"),
+            product_version,
+            timestamp,
+            field("source_sha256").to_ascii_lowercase(),
+            field("sha256").to_ascii_lowercase(),
+            patch_path
+        );
+        patched.push((field("build"), field("source_sha256").to_ascii_lowercase(), field("sha256").to_ascii_lowercase()));
+    }
+    code += "];\n";
+
+    let mut patched_exes: Vec<(String, String, String)> = read_exe_patches_json("patched.json").iter().map(|entry| {
+        let field = |key: &str| string_field(entry, "patched.json", key);
+        (field("build"), field("source_sha256").to_ascii_lowercase(), field("sha256").to_ascii_lowercase())
+    }).collect();
+    for (build, source_sha256, sha256) in patched {
+        if !patched_exes.iter().any(|(_, _, s)| *s == sha256) {
+            // it'd be forgotten once the patch is dropped from the index
+            println!("cargo:warning=output of the {} patch ({}) is missing from exe_patches/patched.json", build, sha256);
+            patched_exes.push((build, source_sha256, sha256));
+        }
+    }
+
+    code += "pub const PATCHED_EXES: &[PatchedExe] = &[\n";
+    for (build, source_sha256, sha256) in &patched_exes {
+        code += &format!(
+            "    PatchedExe {{ build: {:?}, source_sha256: {:?}, sha256: {:?} }},\n",
+            build, source_sha256, sha256
+        );
+    }
+    code += "];\n";
+
+    std::fs::write(&out_path, code).unwrap();
+}
+
 fn compile_resources() {
     println!("cargo:rerun-if-changed=assets");
 
@@ -46,6 +140,11 @@ fn main() {
     if std::env::var("CARGO_FEATURE_NET_INSTALL").is_err() {
         detect_hachimi_version();
     }
+    else {
+        // minisign public key downloads have to be signed with, read with option_env!
+        println!("cargo:rerun-if-env-changed=HACHIMI_MINISIGN_PUBKEY");
+    }
 
+    generate_exe_patches();
     compile_resources();
 }
\ No newline at end of file
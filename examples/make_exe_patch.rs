@@ -0,0 +1,97 @@
+// Creates an entry for exe_patches/index.json and one for exe_patches/patched.json from an original and a patched exe
+// Usage: cargo run --example make_exe_patch -- [--write] <build name> <original exe> <patched exe>
+// --write adds them to both files instead of printing them
+use std::{collections::HashMap, path::Path};
+use pelite::resources::version_info::Language;
+use sha2::{Digest, Sha256};
+use tinyjson::JsonValue;
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Adds the entry unless one with the same value for `key` is already there
+fn add_entry(file: &str, key: &str, entry: Vec<(&str, JsonValue)>) {
+    let path = Path::new("exe_patches").join(file);
+    let mut entries = match std::fs::read_to_string(&path) {
+        Ok(s) => match s.parse().expect("valid json") {
+            JsonValue::Array(entries) => entries,
+            _ => panic!("exe_patches/{} must be an array", file)
+        },
+        Err(_) => Vec::new()
+    };
+
+    let entry: HashMap<String, JsonValue> = entry.into_iter().map(|(k, v)| (k.to_owned(), v)).collect();
+    let exists = entries.iter().any(|e| match e {
+        JsonValue::Object(e) => e.get(key) == entry.get(key),
+        _ => false
+    });
+    if exists {
+        println!("exe_patches/{} already has this {}", file, key);
+        return;
+    }
+
+    entries.push(JsonValue::Object(entry));
+    std::fs::write(&path, JsonValue::Array(entries).format().unwrap() + "\n").expect("writable exe_patches dir");
+    println!("added to exe_patches/{}", file);
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let write = args.first().is_some_and(|a| a == "--write");
+    if write {
+        args.remove(0);
+    }
+    let [build, original_path, patched_path] = args.as_slice() else {
+        eprintln!("Usage: make_exe_patch [--write] <build name> <original exe> <patched exe>");
+        std::process::exit(1);
+    };
+
+    let original = std::fs::read(original_path).expect("readable original exe");
+    let patched = std::fs::read(patched_path).expect("readable patched exe");
+
+    let mut patch = Vec::new();
+    bsdiff::diff(&original, &patched, &mut patch).unwrap();
+
+    // make sure it round-trips before handing it out
+    let mut output = Vec::new();
+    bsdiff::patch(&original, &mut patch.as_slice(), &mut output).unwrap();
+    assert_eq!(output, patched);
+
+    let pe = pelite::PeFile::from_bytes(&original).expect("original exe is a PE file");
+    let timestamp = pe.file_header().TimeDateStamp;
+    let product_version = pe.resources().ok()
+        .and_then(|r| r.version_info().ok())
+        .and_then(|v| v.value(Language { lang_id: 0x0000, charset_id: 0x04b0 }, "ProductVersion"));
+
+    let source_sha256 = sha256_hex(&original);
+    let patch_name = format!("{}.bsdiff", &source_sha256[..16]);
+    std::fs::write(Path::new("exe_patches").join(&patch_name), &patch).expect("writable exe_patches dir");
+
+    let sha256 = sha256_hex(&patched);
+    let s = |s: &str| JsonValue::String(s.to_owned());
+    let mut index_entry = vec![("build", s(build))];
+    if let Some(product_version) = product_version {
+        index_entry.push(("product_version", s(&product_version)));
+    }
+    index_entry.push(("timestamp", JsonValue::Number(timestamp as f64)));
+    index_entry.push(("source_sha256", s(&source_sha256)));
+    index_entry.push(("patch", s(&patch_name)));
+    index_entry.push(("sha256", s(&sha256)));
+    // stays in patched.json for good, even after the patch is dropped
+    let patched_entry = vec![("build", s(build)), ("source_sha256", s(&source_sha256)), ("sha256", s(&sha256))];
+
+    if write {
+        add_entry("index.json", "source_sha256", index_entry);
+        add_entry("patched.json", "sha256", patched_entry);
+        return;
+    }
+
+    let print = |entry: Vec<(&str, JsonValue)>| {
+        let object = JsonValue::Object(entry.into_iter().map(|(k, v)| (k.to_owned(), v)).collect());
+        println!("{}", object.format().unwrap());
+    };
+    print(index_entry);
+    println!();
+    print(patched_entry);
+}
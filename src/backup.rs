@@ -0,0 +1,227 @@
+use std::path::{Path, PathBuf};
+use tinyjson::JsonValue;
+use crate::{installer::Error, utils};
+
+const BACKUPS_DIR: &str = "backups";
+const META_FILE: &str = "backup.json";
+
+// One version of a file we replaced, kept in <data dir>/backups/<id>/
+pub struct BackupEntry {
+    pub id: String,
+    // where the file came from and goes back to
+    pub path: PathBuf,
+    // the copy in the store
+    pub file: PathBuf,
+    pub sha256: String,
+    pub size: u64,
+    // game build label at the time of the backup, or the file's own version
+    pub build: Option<String>,
+    // buildid from the Steam app manifest, if the game is a Steam install
+    pub steam_build_id: Option<u64>,
+    pub created: u64
+}
+
+impl BackupEntry {
+    pub fn dir(&self) -> &Path {
+        self.file.parent().unwrap_or(&self.file)
+    }
+
+    pub fn meta_path(&self) -> PathBuf {
+        self.dir().join(META_FILE)
+    }
+
+    // Made for a different game build than the one that's installed now
+    pub fn is_stale(&self, current_steam_build_id: Option<u64>) -> bool {
+        matches!((self.steam_build_id, current_steam_build_id), (Some(a), Some(b)) if a != b)
+    }
+
+    pub fn verify(&self) -> Result<(), Error> {
+        let size = std::fs::metadata(&self.file)?.len();
+        if size != self.size || utils::sha256_file(&self.file)? != self.sha256 {
+            return Err(Error::FileChanged(self.file.clone()));
+        }
+        Ok(())
+    }
+
+    fn to_json(&self, root: &Path) -> JsonValue {
+        let path_value = |p: &Path| JsonValue::String(
+            p.strip_prefix(root).unwrap_or(p).to_string_lossy().into_owned()
+        );
+        let fields = vec![
+            ("id", JsonValue::String(self.id.clone())),
+            ("path", path_value(&self.path)),
+            ("file", path_value(&self.file)),
+            ("sha256", JsonValue::String(self.sha256.clone())),
+            ("size", JsonValue::Number(self.size as f64)),
+            ("build", self.build.clone().map(JsonValue::String).unwrap_or(JsonValue::Null)),
+            ("steam_build_id", self.steam_build_id.map(|v| JsonValue::Number(v as f64)).unwrap_or(JsonValue::Null)),
+            ("created", JsonValue::Number(self.created as f64))
+        ];
+        JsonValue::Object(fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+    }
+
+    fn from_json(value: &JsonValue, root: &Path) -> Option<BackupEntry> {
+        let JsonValue::Object(obj) = value else {
+            return None;
+        };
+        let get_str = |key: &str| match obj.get(key) {
+            Some(JsonValue::String(s)) => Some(s.clone()),
+            _ => None
+        };
+        let get_num = |key: &str| match obj.get(key) {
+            Some(JsonValue::Number(n)) => Some(*n as u64),
+            _ => None
+        };
+
+        Some(BackupEntry {
+            id: get_str("id")?,
+            path: root.join(get_str("path")?),
+            file: root.join(get_str("file")?),
+            sha256: get_str("sha256")?,
+            size: get_num("size")?,
+            build: get_str("build"),
+            steam_build_id: get_num("steam_build_id"),
+            created: get_num("created").unwrap_or(0)
+        })
+    }
+}
+
+pub struct BackupStore {
+    dir: PathBuf,
+    // paths in the metadata are relative to this, same as the receipt
+    root: PathBuf
+}
+
+impl BackupStore {
+    pub fn new(data_dir: &Path) -> BackupStore {
+        BackupStore {
+            dir: data_dir.join(BACKUPS_DIR),
+            root: data_dir.parent().unwrap_or(data_dir).to_owned()
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Oldest first. Entries with unreadable metadata are left out, prune still gets rid of them.
+    pub fn list(&self) -> Result<Vec<BackupEntry>, Error> {
+        let read_dir = match std::fs::read_dir(&self.dir) {
+            Ok(d) => d,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into())
+        };
+
+        let mut entries = Vec::new();
+        for dir_entry in read_dir {
+            let Ok(meta_str) = std::fs::read_to_string(dir_entry?.path().join(META_FILE)) else {
+                continue;
+            };
+            let Ok(value) = meta_str.parse::<JsonValue>() else {
+                continue;
+            };
+            if let Some(entry) = BackupEntry::from_json(&value, &self.root) {
+                entries.push(entry);
+            }
+        }
+        entries.sort_by_key(|e| e.created);
+
+        Ok(entries)
+    }
+
+    pub fn find(&self, id: &str) -> Result<Option<BackupEntry>, Error> {
+        Ok(self.list()?.into_iter().find(|e| e.id == id))
+    }
+
+    pub fn find_by_hash(&self, path: &Path, sha256: &str) -> Result<Option<BackupEntry>, Error> {
+        Ok(self.list()?.into_iter().find(|e| e.path == path && e.sha256 == sha256))
+    }
+
+    // The store entry that holds this backup file, if it's in the store at all
+    pub fn find_by_file(&self, file: &Path) -> Result<Option<BackupEntry>, Error> {
+        Ok(self.list()?.into_iter().find(|e| e.file == file))
+    }
+
+    // Every dir in the store, including ones without valid metadata
+    pub fn entry_dirs(&self) -> Result<Vec<PathBuf>, Error> {
+        let read_dir = match std::fs::read_dir(&self.dir) {
+            Ok(d) => d,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into())
+        };
+        let mut dirs = Vec::new();
+        for dir_entry in read_dir {
+            let path = dir_entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            }
+        }
+        Ok(dirs)
+    }
+
+    // A new entry for `path`, nothing is written until the plan runs
+    pub fn prepare(
+        &self, path: &Path, sha256: String, size: u64, build: Option<String>, steam_build_id: Option<u64>
+    ) -> BackupEntry {
+        let created = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        // same file with the same contents always ends up in the same place, two files that
+        // happen to have the same bytes still get an entry (and metadata) each
+        let rel_path = path.strip_prefix(&self.root).unwrap_or(path);
+        let id = utils::sha256_hex(format!("{}\n{}", rel_path.display(), sha256).as_bytes())[..16].to_owned();
+        let file_name = path.file_name().map(|n| n.to_owned()).unwrap_or_else(|| "file".into());
+
+        BackupEntry {
+            file: self.dir.join(&id).join(file_name),
+            id,
+            path: path.to_owned(),
+            sha256,
+            size,
+            build,
+            steam_build_id,
+            created
+        }
+    }
+
+    pub fn meta_json(&self, entry: &BackupEntry) -> String {
+        // only strings and numbers in here so this can't fail
+        entry.to_json(&self.root).format().unwrap_or_default()
+    }
+
+    pub fn entry_json(&self, entry: &BackupEntry) -> JsonValue {
+        entry.to_json(&self.root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn same_contents_at_different_paths_get_their_own_entry() {
+        let tmp = TempDir::new();
+        let store = BackupStore::new(&tmp.path().join("hachimi"));
+        let sha256 = utils::sha256_hex(b"same bytes");
+        let a = store.prepare(&tmp.path().join("a.dll"), sha256.clone(), 10, None, None);
+        let b = store.prepare(&tmp.path().join("b.dll"), sha256.clone(), 10, None, None);
+        assert_ne!(a.id, b.id);
+        assert_ne!(a.dir(), b.dir());
+
+        // and the same file with the same contents lands in the same place again
+        let again = store.prepare(&tmp.path().join("a.dll"), sha256, 10, None, Some(1));
+        assert_eq!(a.id, again.id);
+    }
+
+    #[test]
+    fn only_a_different_steam_build_is_stale() {
+        let tmp = TempDir::new();
+        let store = BackupStore::new(&tmp.path().join("hachimi"));
+        let entry = store.prepare(&tmp.path().join("a.dll"), utils::sha256_hex(b"a"), 1, None, Some(1));
+        assert!(!entry.is_stale(None));
+        assert!(!entry.is_stale(Some(1)));
+        assert!(entry.is_stale(Some(2)));
+    }
+}
@@ -0,0 +1,208 @@
+use std::{path::{Path, PathBuf}, time::SystemTime};
+use bytes::Bytes;
+use tinyjson::JsonValue;
+use crate::{
+    installer::Error,
+    platform::{KnownFolder, Platform},
+    release::{self, Downloaded, Release, Sidecar},
+    utils,
+    version::Version
+};
+
+// Under %LOCALAPPDATA%
+const CACHE_DIR: &str = r"hachimi_installer\cache";
+
+// One downloaded DLL, kept in <cache dir>/<tag>-<hash>/ with its sidecar next to it
+pub struct CacheEntry {
+    pub dir: PathBuf,
+    pub file: PathBuf,
+    pub sidecar: Sidecar,
+    pub modified: SystemTime
+}
+
+impl CacheEntry {
+    pub fn sha256(&self) -> Option<String> {
+        release::parse_digest(&self.sidecar.digest).ok()
+    }
+
+    // Checked against the size and hash it was stored with, a damaged copy is never used
+    pub fn load(&self) -> Result<Downloaded, Error> {
+        let bytes = std::fs::read(&self.file)?;
+        if bytes.len() as u64 != self.sidecar.size || self.sha256() != Some(utils::sha256_hex(&bytes)) {
+            return Err(Error::FileChanged(self.file.clone()));
+        }
+        Ok(Downloaded { bytes: Bytes::from(bytes), sidecar: self.sidecar.clone() })
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let fields = vec![
+            ("tag", JsonValue::String(self.sidecar.tag.clone())),
+            ("asset_id", self.sidecar.asset_id.map(|id| JsonValue::Number(id as f64)).unwrap_or(JsonValue::Null)),
+            ("size", JsonValue::Number(self.sidecar.size as f64)),
+            ("digest", JsonValue::String(self.sidecar.digest.clone())),
+            ("file", JsonValue::String(self.file.to_string_lossy().into_owned()))
+        ];
+        JsonValue::Object(fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+    }
+}
+
+#[derive(Clone)]
+pub struct DownloadCache {
+    dir: PathBuf
+}
+
+impl DownloadCache {
+    pub fn new(dir: &Path) -> DownloadCache {
+        DownloadCache { dir: dir.to_owned() }
+    }
+
+    pub fn default_dir(platform: &Platform) -> Option<PathBuf> {
+        Some(platform.known_folders.get(KnownFolder::LocalAppData)?.join(CACHE_DIR))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Oldest version first. Entries without a readable sidecar are left out, clear still gets rid of them.
+    pub fn list(&self) -> Result<Vec<CacheEntry>, Error> {
+        let mut entries = Vec::new();
+        for dir in self.entry_dirs()? {
+            let file = dir.join(release::DLL_ASSET);
+            let Ok(sidecar_str) = std::fs::read_to_string(Sidecar::path_for(&file)) else {
+                continue;
+            };
+            let Some(sidecar) = Sidecar::from_json(&sidecar_str) else {
+                continue;
+            };
+            let Ok(modified) = std::fs::metadata(&file).and_then(|m| m.modified()) else {
+                continue;
+            };
+            entries.push(CacheEntry { dir, file, sidecar, modified });
+        }
+        // tags that aren't versions go first, same versions by when they were downloaded
+        entries.sort_by(|a, b| {
+            Version::parse(&a.sidecar.tag).cmp(&Version::parse(&b.sidecar.tag))
+                .then(a.modified.cmp(&b.modified))
+        });
+
+        Ok(entries)
+    }
+
+    // The cached copy of this release; the tag has to match, and the digest too if the release has one
+    pub fn find(&self, release: &Release) -> Result<Option<CacheEntry>, Error> {
+        let digest = release.dll.digest.as_deref().and_then(|d| release::parse_digest(d).ok());
+        Ok(self.list()?.into_iter().rev().find(|e| {
+            e.sidecar.tag == release.tag && (digest.is_none() || e.sha256() == digest)
+        }))
+    }
+
+    // Newest version that still loads, for when the release source can't be reached
+    pub fn newest(&self) -> Result<Option<Downloaded>, Error> {
+        Ok(self.list()?.iter().rev().find_map(|e| e.load().ok()))
+    }
+
+    // Only ever called with a download that passed the integrity check
+    pub fn store(&self, download: &Downloaded) -> Result<CacheEntry, Error> {
+        let sha256 = utils::sha256_hex(&download.bytes);
+        // the tag ends up in a path, keep it to what's safe in one
+        let tag: String = download.sidecar.tag.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        let dir = self.dir.join(format!("{}-{}", tag, &sha256[..16]));
+        std::fs::create_dir_all(&dir)?;
+
+        // the sidecar goes last, without it the entry doesn't count
+        let file = dir.join(release::DLL_ASSET);
+        let tmp_path = file.with_extension("tmp");
+        std::fs::write(&tmp_path, &download.bytes)?;
+        std::fs::rename(&tmp_path, &file)?;
+        let sidecar = Sidecar { digest: format!("sha256:{}", sha256), ..download.sidecar.clone() };
+        std::fs::write(Sidecar::path_for(&file), sidecar.to_json())?;
+
+        let modified = std::fs::metadata(&file)?.modified()?;
+        Ok(CacheEntry { dir, file, sidecar, modified })
+    }
+
+    // Every dir in the cache, including ones without a valid sidecar
+    pub fn entry_dirs(&self) -> Result<Vec<PathBuf>, Error> {
+        let read_dir = match std::fs::read_dir(&self.dir) {
+            Ok(d) => d,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into())
+        };
+        let mut dirs = Vec::new();
+        for dir_entry in read_dir {
+            let path = dir_entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            }
+        }
+        Ok(dirs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{release::{Asset, AssetLocation}, test_utils::TempDir};
+
+    fn download(tag: &str, bytes: &'static [u8]) -> Downloaded {
+        let sidecar = Sidecar {
+            tag: tag.to_owned(),
+            asset_id: Some(1),
+            size: bytes.len() as u64,
+            digest: format!("sha256:{}", utils::sha256_hex(bytes))
+        };
+        Downloaded { bytes: Bytes::from_static(bytes), sidecar }
+    }
+
+    fn release(tag: &str, digest: Option<String>) -> Release {
+        Release {
+            tag: tag.to_owned(),
+            dll: Asset { id: None, size: None, digest, location: AssetLocation::Url(String::new()) },
+            sha256: None,
+            signature: None
+        }
+    }
+
+    #[test]
+    fn finds_releases_by_tag_and_digest() {
+        let tmp = TempDir::new();
+        let cache = DownloadCache::new(tmp.path());
+        let entry = cache.store(&download("v1.0.0", b"hachimi 1.0.0")).unwrap();
+        assert_eq!(entry.load().unwrap().bytes, &b"hachimi 1.0.0"[..]);
+
+        let digest = format!("sha256:{}", utils::sha256_hex(b"hachimi 1.0.0"));
+        assert!(cache.find(&release("v1.0.0", Some(digest))).unwrap().is_some());
+        // no digest in the release info, the tag has to do
+        assert!(cache.find(&release("v1.0.0", None)).unwrap().is_some());
+        assert!(cache.find(&release("v1.0.0", Some(format!("sha256:{}", utils::sha256_hex(b"other"))))).unwrap().is_none());
+        assert!(cache.find(&release("v1.1.0", None)).unwrap().is_none());
+    }
+
+    #[test]
+    fn falls_back_to_the_newest_intact_copy() {
+        let tmp = TempDir::new();
+        let cache = DownloadCache::new(tmp.path());
+        cache.store(&download("v1.1.0", b"hachimi 1.1.0")).unwrap();
+        let newest = cache.store(&download("v1.2.0", b"hachimi 1.2.0")).unwrap();
+        cache.store(&download("v1.0.0", b"hachimi 1.0.0")).unwrap();
+        let tags: Vec<_> = cache.list().unwrap().into_iter().map(|e| e.sidecar.tag).collect();
+        assert_eq!(tags, ["v1.0.0", "v1.1.0", "v1.2.0"]);
+        assert_eq!(cache.newest().unwrap().unwrap().sidecar.tag, "v1.2.0");
+
+        std::fs::write(&newest.file, b"damaged").unwrap();
+        assert!(newest.load().is_err());
+        assert_eq!(cache.newest().unwrap().unwrap().sidecar.tag, "v1.1.0");
+    }
+
+    #[test]
+    fn keeps_tags_inside_the_cache_dir() {
+        let tmp = TempDir::new();
+        let cache = DownloadCache::new(&tmp.path().join("cache"));
+        let entry = cache.store(&download(r"..\..\evil", b"hachimi")).unwrap();
+        assert_eq!(entry.dir.parent(), Some(cache.dir()));
+        assert_eq!(cache.entry_dirs().unwrap(), [entry.dir]);
+    }
+}
@@ -1,99 +1,577 @@
-use std::path::{Path, PathBuf};
+use std::{collections::HashMap, path::{Path, PathBuf}, rc::Rc};
+#[cfg(feature = "net_install")]
+use std::sync::Arc;
+use tinyjson::JsonValue;
 
 use crate::i18n::{t};
 use windows::{
-    core::{w, HSTRING},
+    core::HSTRING,
     Win32::UI::{
         Shell::ShellExecuteW,
-        WindowsAndMessaging::{MessageBoxW, IDCANCEL, MB_ICONERROR, MB_ICONINFORMATION, MB_OK, MB_OKCANCEL, SW_NORMAL}
+        WindowsAndMessaging::SW_NORMAL
     }
 };
 
-use crate::{installer::{self, Installer, Target}, utils};
+#[cfg(feature = "net_install")]
+use crate::{cache::DownloadCache, download::{Downloader, Progress}, release::{self, ReleaseSource}};
+use crate::{
+    console,
+    installer::{self, Installer, Target, UpdateCheck, UpdateState},
+    journal::Operation,
+    plan::Step,
+    platform::{
+        self, prompt::{ConsolePrompt, PolicyPrompt}, windows::{ParentWindow, WindowsElevation},
+        Answer, Buttons, Level, Platform, Prompt
+    },
+    status::Status,
+    utils,
+    verify
+};
 
 #[derive(Default)]
 struct Args {
     command: Option<Command>,
+    // --help, for the subcommand given before it if any
+    help: bool,
+    console: bool,
+    quiet: bool,
     install_dir: Option<PathBuf>,
     target: Option<String>,
     explicit_target: Option<Target>,
     sleep: Option<u64>,
     prompt_for_game_exit: bool,
     launch_game: bool,
+    // everything after --, the whole game command line for `launch`
     game_args: Vec<String>,
+    update: bool,
     pre_install: bool,
     post_install: bool,
-    enable_dotlocal: bool
+    enable_dotlocal: bool,
+    disable_dotlocal: bool,
+    // value from before the install, requested by uninstall
+    restore_dotlocal: Option<u32>,
+    dry_run: bool,
+    json: bool,
+    force: bool,
+    // --assume-yes/--assume-no
+    assume: Option<Answer>,
+    answers: Option<PathBuf>,
+    #[cfg(feature = "net_install")]
+    release_source: Option<Arc<dyn ReleaseSource>>,
+    #[cfg(feature = "net_install")]
+    cache_dir: Option<PathBuf>
 }
 
 enum Command {
     Install,
-    Uninstall
+    Uninstall,
+    Status,
+    CheckUpdate,
+    Verify,
+    Repair,
+    Ensure,
+    Launch,
+    Backups(Option<BackupsCommand>),
+    #[cfg(feature = "net_install")]
+    Cache(Option<CacheCommand>)
+}
+
+enum BackupsCommand {
+    List,
+    Restore(String),
+    Prune
+}
+
+#[cfg(feature = "net_install")]
+#[derive(Clone, Copy)]
+enum CacheCommand {
+    List,
+    Clear
+}
+
+// Everything that can be wrong with a command line, each with its own exit code
+pub enum ArgError {
+    UnknownArgument(String),
+    MissingValue(String),
+    InvalidValue(String, String),
+    // --switch=value
+    UnexpectedValue(String),
+    UnknownCommand(String),
+    MissingCommand(String),
+    UnknownTarget
+}
+
+impl ArgError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ArgError::UnknownArgument(_) => 128,
+            ArgError::MissingValue(_) => 129,
+            ArgError::InvalidValue(..) => 130,
+            ArgError::UnexpectedValue(_) => 131,
+            ArgError::UnknownCommand(_) => 132,
+            ArgError::MissingCommand(_) => 133,
+            ArgError::UnknownTarget => 134
+        }
+    }
+}
+
+impl std::fmt::Display for ArgError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgError::UnknownArgument(arg) => write!(f, "{}", t!("cli.unknown_argument", arg = arg)),
+            ArgError::MissingValue(arg) => write!(f, "{}", t!("cli.missing_value", arg = arg)),
+            ArgError::InvalidValue(arg, value) => write!(f, "{}", t!("cli.invalid_value", arg = arg, value = value)),
+            ArgError::UnexpectedValue(arg) => write!(f, "{}", t!("cli.unexpected_value", arg = arg)),
+            ArgError::UnknownCommand(command) => write!(f, "{}", t!("cli.unknown_command", command = command)),
+            ArgError::MissingCommand(command) => write!(f, "{}", t!("cli.missing_command", command = command)),
+            ArgError::UnknownTarget => write!(f, "{}", t!("cli.failed_determine_target"))
+        }
+    }
+}
+
+const HELP: &str = "\
+Usage: hachimi_installer.exe [OPTIONS] <SUBCOMMAND>
+
+Without any arguments the GUI is started.
+
+Subcommands:
+  install      Install Hachimi
+  uninstall    Uninstall Hachimi and restore the original files
+  status       Show what's installed where, for every platform
+  check-update Compare the installed Hachimi with the one this installer has
+  verify       Check the installed files against the ones this installer has
+  repair       Put back missing or changed files and clean up after interrupted runs
+  ensure       Install or fix only what's missing, does nothing if everything matches
+  launch       Ensure Hachimi is in place, then run the game command given after --
+  backups      Manage the backups of replaced files
+  cache        Manage the downloaded Hachimi builds, for download builds only
+
+Run `hachimi_installer.exe <SUBCOMMAND> --help` for the options of each subcommand.";
+
+const TARGET_OPTIONS: &str = "\
+  --install-dir <path>        Game install directory, detected if not given
+  --target <filename|path>    Install target, relative to the install dir unless absolute
+  --platform <platform>       dmm, steam-jp, steam-global or komoe
+  --explicit-target <dll>     Target DLL name, for the install method only";
+
+const COMMON_OPTIONS: &str = "\
+  --console                   Open a console window if not started from one
+  -q, --quiet                 Only print errors
+  --assume-yes                Answer yes to every question
+  --assume-no                 Answer no to every question
+  --answers <file>            JSON file mapping prompt IDs to answers
+  --release-source <source>   Where to download Hachimi from, for download builds only:
+                              github[:<owner>/<repo>[@<api base>]], index:<url> or local:<dir>
+  --cache-dir <dir>           Where downloads are kept, for download builds only;
+                              %LOCALAPPDATA%\\hachimi_installer\\cache if not given
+  -h, --help                  Show this help";
+
+const INSTALL_OPTIONS: &str = "\
+  --sleep <milliseconds>      Wait before starting
+  --prompt-for-game-exit      Wait for the game to be closed first
+  --pre-install               Also run pre-install tasks
+  --post-install              Also run post-install tasks
+  --launch-game               Start the game afterwards, with the arguments after --
+  --dry-run                   Print what would be done without doing it
+  --json                      Print the --dry-run plan as JSON
+  --force                     Install on an unknown game build";
+
+const UNINSTALL_OPTIONS: &str = "\
+  --sleep <milliseconds>      Wait before starting
+  --prompt-for-game-exit      Wait for the game to be closed first
+  --launch-game               Start the game afterwards, with the arguments after --
+  --dry-run                   Print what would be done without doing it
+  --json                      Print the --dry-run plan as JSON
+  --force                     Delete the target even if it isn't Hachimi, skip stale backups";
+
+const STATUS_USAGE: &str = "\
+Usage: hachimi_installer.exe status [OPTIONS]
+
+Options:
+  --json                      Print the status as JSON
+  --install-dir <path>        Report this directory instead of the detected one
+  --platform <platform>       Platform of --install-dir, detected from the exe if not given";
+
+const CHECK_UPDATE_USAGE: &str = "\
+Usage: hachimi_installer.exe check-update [OPTIONS]
+
+Exits with 0 if up to date, 100 if there's an upgrade, 101 if the installed
+version is newer, 102 if Hachimi isn't installed and 103 if the versions
+can't be compared.
+
+Options:
+  --json                      Print the result as JSON";
+
+const VERIFY_USAGE: &str = "\
+Usage: hachimi_installer.exe verify [OPTIONS]
+
+Reports each file of the target as ok, missing, modified or foreign.
+Exits with 0 if everything is ok and 110 otherwise.
+
+Options:
+  --json                      Print the report as JSON";
+
+const REPAIR_USAGE: &str = "\
+Usage: hachimi_installer.exe repair [OPTIONS]
+
+Rewrites missing or modified Hachimi files, patches the game exe again if
+it was restored, and removes leftovers from interrupted runs.
+
+Options:
+  --dry-run                   Print what would be done without doing it
+  --json                      Print the --dry-run plan as JSON";
+
+const ENSURE_USAGE: &str = "\
+Usage: hachimi_installer.exe ensure [OPTIONS]
+
+Compares the install with the desired state and only performs the missing
+steps: backing up a restored game exe, writing the DLL and patching the exe.
+A Hachimi that's newer than the one this installer has is kept. Does nothing
+if everything already matches, so it's safe to run at every logon.
+
+Options:
+  --force                     Continue even if the game build is not supported
+  --dry-run                   Print what would be done without doing it
+  --json                      Print the --dry-run plan as JSON";
+
+const LAUNCH_USAGE: &str = "\
+Usage: hachimi_installer.exe launch [OPTIONS] -- <game exe> [game args...]
+
+Meant as a Steam launch option: `\"C:\\path\\to\\hachimi_installer.exe\" launch -- %command%`
+Installs the DLL and patches the exe if needed like `ensure`, then runs the
+game, waits for it and exits with its exit code. The install dir and platform
+are taken from the game exe unless given. If something can't be fixed the
+error is shown and the game is started anyway.
+
+Options:
+  --update                    Also replace an older Hachimi with the one this installer has
+  --force                     Continue even if the game build is not supported
+  --dry-run                   Print what would be done without doing it or starting the game
+  --json                      Print the --dry-run plan as JSON";
+
+const BACKUPS_USAGE: &str = "\
+Usage: hachimi_installer.exe backups <list|restore <id>|prune> [OPTIONS]
+
+  list                        List the backups
+  restore <id>                Copy a backup back to its original location
+  prune                       Delete the backups the current install doesn't need
+
+Options:
+  --json                      Print the list as JSON
+  --force                     Allow restoring a backup made for another game build";
+
+#[cfg(feature = "net_install")]
+const CACHE_USAGE: &str = "\
+Usage: hachimi_installer.exe cache <list|clear> [OPTIONS]
+
+Every downloaded hachimi.dll is kept, by tag and hash. An unchanged release
+is installed from there instead of downloading it again, and the newest one
+is used when the release source can't be reached.
+
+  list                        List the cached downloads, oldest version first
+  clear                       Delete all of them
+
+Options:
+  --cache-dir <dir>           Cache to manage instead of the default one
+  --json                      Print the list as JSON";
+
+fn print_help(command: Option<&Command>) {
+    match command {
+        None => println!("{}", HELP),
+        Some(Command::Install) => println!(
+            "Usage: hachimi_installer.exe install [OPTIONS] [-- <game args>]\n\nOptions:\n{}\n{}\n{}",
+            TARGET_OPTIONS, INSTALL_OPTIONS, COMMON_OPTIONS
+        ),
+        Some(Command::Uninstall) => println!(
+            "Usage: hachimi_installer.exe uninstall [OPTIONS] [-- <game args>]\n\nOptions:\n{}\n{}\n{}",
+            TARGET_OPTIONS, UNINSTALL_OPTIONS, COMMON_OPTIONS
+        ),
+        Some(Command::Status) => println!("{}", STATUS_USAGE),
+        Some(Command::Launch) => println!("{}\n{}\n{}", LAUNCH_USAGE, TARGET_OPTIONS, COMMON_OPTIONS),
+        Some(Command::Ensure) => println!("{}\n{}\n{}", ENSURE_USAGE, TARGET_OPTIONS, COMMON_OPTIONS),
+        Some(Command::Repair) => println!("{}\n{}\n{}", REPAIR_USAGE, TARGET_OPTIONS, COMMON_OPTIONS),
+        Some(Command::Verify) => println!("{}\n{}\n{}", VERIFY_USAGE, TARGET_OPTIONS, COMMON_OPTIONS),
+        Some(Command::CheckUpdate) => println!("{}\n{}\n{}", CHECK_UPDATE_USAGE, TARGET_OPTIONS, COMMON_OPTIONS),
+        Some(Command::Backups(_)) => println!("{}\n{}\n{}", BACKUPS_USAGE, TARGET_OPTIONS, COMMON_OPTIONS),
+        #[cfg(feature = "net_install")]
+        Some(Command::Cache(_)) => println!("{}", CACHE_USAGE)
+    }
 }
 
-#[inline]
-fn require_next_arg(args: &mut std::env::Args) -> String {
-    args.next().unwrap_or_else(|| std::process::exit(128))
+// Value of `name`, either from --name=value or the next argument
+fn take_value(
+    name: &str, inline_value: &mut Option<String>, iter: &mut impl Iterator<Item = String>
+) -> Result<String, ArgError> {
+    inline_value.take()
+        .or_else(|| iter.next())
+        .ok_or_else(|| ArgError::MissingValue(name.to_owned()))
 }
 
 impl Args {
-    fn parse() -> Args {
-        let mut args = Args::default();
+    fn parse() -> Result<Args, ArgError> {
+        Self::parse_from(std::env::args().skip(1))
+    }
 
-        let mut iter = std::env::args();
-        iter.next();
+    fn parse_from(mut iter: impl Iterator<Item = String>) -> Result<Args, ArgError> {
+        let mut args = Args::default();
 
         let mut in_game_args = false;
-        loop {
-            let Some(arg) = iter.next() else {
-                break;
-            };
-
+        while let Some(arg) = iter.next() {
             if in_game_args {
                 args.game_args.push(arg);
                 continue;
             }
 
-            match arg.as_str() {
+            let (name, mut inline_value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name.to_owned(), Some(value.to_owned())),
+                _ => (arg, None)
+            };
+
+            match name.as_str() {
                 "install" => args.command = Some(Command::Install),
                 "uninstall" => args.command = Some(Command::Uninstall),
+                "status" => args.command = Some(Command::Status),
+                "check-update" => args.command = Some(Command::CheckUpdate),
+                "verify" => args.command = Some(Command::Verify),
+                "repair" => args.command = Some(Command::Repair),
+                "ensure" => args.command = Some(Command::Ensure),
+                "launch" => args.command = Some(Command::Launch),
+                "backups" => {
+                    // `backups --help` is fine without the subcommand
+                    let command = match iter.next() {
+                        Some(s) if s == "list" => Some(BackupsCommand::List),
+                        Some(s) if s == "restore" => match iter.next() {
+                            // asking for help, not for a backup called --help
+                            Some(id) if id == "--help" || id == "-h" => {
+                                args.help = true;
+                                None
+                            },
+                            // ids are hex, anything like an option means the id was left out
+                            Some(id) if !id.starts_with('-') => Some(BackupsCommand::Restore(id)),
+                            _ => return Err(ArgError::MissingValue("backups restore".to_owned()))
+                        },
+                        Some(s) if s == "prune" => Some(BackupsCommand::Prune),
+                        Some(s) if s == "--help" || s == "-h" => {
+                            args.help = true;
+                            None
+                        },
+                        Some(s) => return Err(ArgError::UnknownCommand(format!("backups {}", s))),
+                        None => None
+                    };
+                    args.command = Some(Command::Backups(command));
+                },
+                #[cfg(feature = "net_install")]
+                "cache" => {
+                    let command = match iter.next() {
+                        Some(s) if s == "list" => Some(CacheCommand::List),
+                        Some(s) if s == "clear" => Some(CacheCommand::Clear),
+                        Some(s) if s == "--help" || s == "-h" => {
+                            args.help = true;
+                            None
+                        },
+                        Some(s) => return Err(ArgError::UnknownCommand(format!("cache {}", s))),
+                        None => None
+                    };
+                    args.command = Some(Command::Cache(command));
+                },
 
-
-                "--install-dir" => args.install_dir = Some(require_next_arg(&mut iter).into()),
-                "--target" => args.target = Some(require_next_arg(&mut iter)),
+                "--install-dir" => args.install_dir = Some(take_value(&name, &mut inline_value, &mut iter)?.into()),
+                "--target" => args.target = Some(take_value(&name, &mut inline_value, &mut iter)?),
                 "--explicit-target" => {
-                    let dll_name = require_next_arg(&mut iter);
+                    let dll_name = take_value(&name, &mut inline_value, &mut iter)?;
                     args.explicit_target = Some(*Target::VALUES.iter()
-                        .filter(|t| t.dll_name() == dll_name)
-                        .next()
-                        .unwrap_or_else(|| std::process::exit(128))
+                        .find(|t| t.dll_name().eq_ignore_ascii_case(&dll_name))
+                        .ok_or(ArgError::InvalidValue(name.clone(), dll_name))?
                     );
                 },
-                "--sleep" => args.sleep = Some(require_next_arg(&mut iter).parse().unwrap_or_else(|_| std::process::exit(128))),
+                "--platform" => {
+                    let id = take_value(&name, &mut inline_value, &mut iter)?;
+                    args.explicit_target = Some(Target::from_id(&id).ok_or(ArgError::InvalidValue(name.clone(), id))?);
+                },
+                "--sleep" => {
+                    let sleep = take_value(&name, &mut inline_value, &mut iter)?;
+                    args.sleep = Some(sleep.parse().map_err(|_| ArgError::InvalidValue(name.clone(), sleep))?);
+                },
+                "--answers" => args.answers = Some(take_value(&name, &mut inline_value, &mut iter)?.into()),
+                #[cfg(feature = "net_install")]
+                "--release-source" => {
+                    let spec = take_value(&name, &mut inline_value, &mut iter)?;
+                    args.release_source = Some(release::from_spec(&spec).ok_or(ArgError::InvalidValue(name.clone(), spec))?);
+                },
+                #[cfg(feature = "net_install")]
+                "--cache-dir" => args.cache_dir = Some(take_value(&name, &mut inline_value, &mut iter)?.into()),
                 "--prompt-for-game-exit" => args.prompt_for_game_exit = true,
                 "--launch-game" => args.launch_game = true,
                 "--pre-install" => args.pre_install = true,
                 "--post-install" => args.post_install = true,
+                "--dry-run" => args.dry_run = true,
+                "--json" => args.json = true,
+                "--force" => args.force = true,
+                "--update" => args.update = true,
+                "--assume-yes" => args.assume = Some(Answer::Yes),
+                "--assume-no" => args.assume = Some(Answer::No),
                 "--enable-dotlocal" => args.enable_dotlocal = true,
+                "--disable-dotlocal" => args.disable_dotlocal = true,
+                "--restore-dotlocal" => {
+                    let data = take_value(&name, &mut inline_value, &mut iter)?;
+                    args.restore_dotlocal = Some(data.parse().map_err(|_| ArgError::InvalidValue(name.clone(), data))?);
+                },
+                "--help" | "-h" => args.help = true,
+                "--console" => args.console = true,
+                "--quiet" | "-q" => args.quiet = true,
                 "--" => in_game_args = true,
 
-                _ => {
-                    // Invalid argument
-                    std::process::exit(128);
+                _ => return Err(ArgError::UnknownArgument(name))
+            }
+
+            // only options that take a value get to use --name=value
+            if inline_value.is_some() {
+                return Err(ArgError::UnexpectedValue(name));
+            }
+        }
+
+        if !args.help {
+            if let Some(Command::Backups(None)) = args.command {
+                return Err(ArgError::MissingCommand("backups".to_owned()));
+            }
+            #[cfg(feature = "net_install")]
+            if let Some(Command::Cache(None)) = args.command {
+                return Err(ArgError::MissingCommand("cache".to_owned()));
+            }
+            if let Some(Command::Launch) = args.command {
+                if args.game_args.is_empty() {
+                    return Err(ArgError::MissingValue("launch --".to_owned()));
                 }
             }
         }
 
-        args
+        Ok(args)
+    }
+}
+
+// Goes to stdout unless --quiet, errors go through the prompt instead
+fn progress(quiet: bool, message: &str) {
+    if !quiet {
+        println!("{}", message);
     }
 }
 
+fn show_error(platform: &Platform, e: &installer::Error) {
+    platform.notify("error", &e.to_string(), &t!("cli.installer_title"), Level::Error);
+}
+
+// Non-interactive answers first, then whoever can actually ask
+fn setup_prompt(
+    platform: &mut Platform, parent: ParentWindow, args: &Args, has_console: bool
+) -> Result<(), installer::Error> {
+    let answers = match &args.answers {
+        Some(path) => PolicyPrompt::load_answers(path)
+            .map_err(|e| installer::Error::InvalidAnswersFile(path.clone(), e))?,
+        None => HashMap::new()
+    };
+
+    // the elevated instance has to follow the same policy
+    let mut elevated_args = Vec::new();
+    match args.assume {
+        Some(Answer::Yes) => elevated_args.push("--assume-yes".to_owned()),
+        Some(_) => elevated_args.push("--assume-no".to_owned()),
+        None => {}
+    }
+    if let Some(path) = &args.answers {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.clone());
+        elevated_args.push("--answers".to_owned());
+        elevated_args.push(path.to_string_lossy().into_owned());
+    }
+    platform.elevation = Box::new(WindowsElevation { parent, extra_args: elevated_args });
+
+    // dialogs are only for when there's nowhere to write to
+    let console_prompt = Box::new(ConsolePrompt { quiet: args.quiet });
+    let fallback: Box<dyn Prompt> = if has_console {
+        console_prompt
+    }
+    else {
+        std::mem::replace(&mut platform.prompt, console_prompt)
+    };
+    platform.prompt = Box::new(PolicyPrompt {
+        answers,
+        default: args.assume,
+        fallback,
+        quiet: args.quiet
+    });
+
+    Ok(())
+}
+
 pub fn run() -> Result<bool, installer::Error> {
-    let mut args = Args::parse();
+    // no arguments means GUI, which doesn't want a console
+    let has_console = std::env::args().len() > 1 && console::attach(false);
+    let mut args = match Args::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, t!("cli.see_help"));
+            std::process::exit(e.exit_code());
+        }
+    };
+    let has_console = has_console || (args.console && console::attach(true));
+    if args.help {
+        print_help(args.command.as_ref());
+        return Ok(true);
+    }
+
+    let parent = ParentWindow::default();
+    let mut platform = platform::windows::platform(parent.clone());
+    if let Err(e) = setup_prompt(&mut platform, parent, &args, has_console) {
+        show_error(&platform, &e);
+        return Err(e);
+    }
+    let platform = Rc::new(platform);
 
     // Handle --enable-dotlocal (runs elevated)
     if args.enable_dotlocal {
-        installer::enable_dotlocal();
+        installer::enable_dotlocal(&platform);
+        return Ok(true);
+    }
+
+    // Handle --disable-dotlocal (runs elevated, requested by uninstall)
+    if args.disable_dotlocal {
+        installer::disable_dotlocal(&platform);
+        return Ok(true);
+    }
+
+    // Handle --restore-dotlocal (runs elevated, requested by uninstall)
+    if let Some(previous) = args.restore_dotlocal {
+        installer::restore_dotlocal(&platform, Some(previous));
+        return Ok(true);
+    }
+
+    if let Some(Command::Status) = args.command {
+        // only an explicit --install-dir is reported as manual, the rest is detected
+        let manual = match &args.install_dir {
+            Some(dir) => {
+                let Some(target) = args.explicit_target.or_else(|| installer::detect_target_from_path(dir)) else {
+                    let e = ArgError::UnknownTarget;
+                    platform.notify("error", &e.to_string(), &t!("cli.installer_title"), Level::Error);
+                    std::process::exit(e.exit_code());
+                };
+                Some((target, dir.as_path()))
+            },
+            None => None
+        };
+        let status = Status::read(&platform, manual);
+        println!("{}", if args.json { status.to_json() } else { status.to_text() });
+        return Ok(true);
+    }
+
+    // the cache isn't tied to any game install either
+    #[cfg(feature = "net_install")]
+    if let Some(Command::Cache(Some(command))) = args.command {
+        let cache = args.cache_dir.as_deref()
+            .map(DownloadCache::new)
+            .or_else(|| DownloadCache::default_dir(&platform).map(|dir| DownloadCache::new(&dir)));
+        if let Err(e) = run_cache(cache, command, args.json, args.quiet) {
+            show_error(&platform, &e);
+            return Err(e);
+        }
         return Ok(true);
     }
 
@@ -103,18 +581,28 @@ pub fn run() -> Result<bool, installer::Error> {
         }
 
         if args.prompt_for_game_exit {
-            while utils::is_game_running() {
-                unsafe {
-                    let res = MessageBoxW(
-                        None,
-                        &HSTRING::from(t!("cli.game_running")),
-                        &HSTRING::from(t!("cli.installer_title")),
-                        MB_ICONINFORMATION | MB_OKCANCEL
-                    );
-                    if res == IDCANCEL {
-                        return Ok(true);
-                    }
+            while platform.is_game_running() {
+                let res = platform.prompt.ask(
+                    "game_running",
+                    &t!("cli.game_running"),
+                    &t!("cli.installer_title"),
+                    Level::Info,
+                    Buttons::OkCancel
+                );
+                if res != Answer::Yes {
+                    return Ok(true);
                 }
+                // don't spin when the answer comes from --assume-yes
+                std::thread::sleep(std::time::Duration::from_secs(1));
+            }
+        }
+
+        // Steam passes the full path to the game exe
+        if let Command::Launch = command {
+            if args.install_dir.is_none() {
+                args.install_dir = Path::new(&args.game_args[0]).parent()
+                    .filter(|p| p.is_dir())
+                    .map(|p| p.to_owned());
             }
         }
 
@@ -140,41 +628,161 @@ pub fn run() -> Result<bool, installer::Error> {
                 }
             }
             None
-        }).unwrap_or_else(|| {
-            unsafe {
-                MessageBoxW(
-                    None,
-                    &HSTRING::from(t!("cli.failed_determine_target")),
-                    &HSTRING::from(t!("cli.installer_title")),
-                    MB_ICONERROR | MB_OK
-                );
-            }
-            std::process::exit(128);
+        })
+        // managing backups doesn't need a target dll, just the game, and neither does launch
+        .or_else(|| match command {
+            Command::Backups(_) | Command::Launch => args.install_dir.as_deref().and_then(installer::detect_target_from_path),
+            _ => None
         });
+        let Some(explicit_target) = explicit_target else {
+            let e = ArgError::UnknownTarget;
+            platform.notify("error", &e.to_string(), &t!("cli.installer_title"), Level::Error);
+            if let Command::Launch = command {
+                return run_game(&platform, &args.game_args);
+            }
+            std::process::exit(e.exit_code());
+        };
+
+        let mut installer = Installer::custom(platform.clone(), args.install_dir, explicit_target, args.target);
+        installer.force = args.force;
+        #[cfg(feature = "net_install")]
+        if let Some(dir) = &args.cache_dir {
+            installer.cache = Some(DownloadCache::new(dir));
+        }
+
+        #[cfg(feature = "net_install")]
+        {
+            // on stderr, one line that keeps getting rewritten; stdout may be --json
+            if !args.quiet {
+                installer.downloader = Arc::new(Downloader {
+                    progress: Some(Arc::new(|progress: &Progress| {
+                        // padded to wipe whatever a longer line left behind
+                        let line = format!("\r{:<50}", progress.to_string());
+                        if progress.done { eprintln!("{}", line) } else { eprint!("{}", line) }
+                    })),
+                    ..Downloader::default()
+                });
+            }
 
-        let installer = Installer::custom(args.install_dir, explicit_target, args.target);
-        let res = match command {
-            Command::Install => {
-                let mut res = Ok(());
-                if args.pre_install {
-                    res = res.and_then(|_| installer.pre_install());
+            // --release-source wins over HACHIMI_RELEASE_SOURCE
+            let res = match args.release_source.take() {
+                Some(source) => Ok(source),
+                None => release::configured()
+            }.and_then(|source| {
+                installer.release_source = source;
+                match command {
+                    Command::Install | Command::Repair | Command::Ensure | Command::Launch | Command::Verify => {
+                        installer.fetch_payload()
+                    },
+                    _ => Ok(())
                 }
-                res = res.and_then(|_| installer.install());
-                if args.post_install {
-                    res = res.and_then(|_| installer.post_install());
+            });
+            if let Err(e) = res {
+                show_error(&platform, &e);
+                return match command {
+                    Command::Launch if !args.dry_run => run_game(&platform, &args.game_args),
+                    _ => Err(e)
+                };
+            }
+        }
+
+        let operation = match command {
+            Command::Install => Operation::Install {
+                pre_install: args.pre_install,
+                post_install: args.post_install
+            },
+            Command::Uninstall => Operation::Uninstall,
+            Command::Repair => Operation::Repair,
+            Command::Ensure => Operation::Ensure { update: true },
+            Command::Launch => Operation::Ensure { update: args.update },
+            Command::Status => unreachable!(),
+            Command::CheckUpdate => {
+                let check = installer.check_update().inspect_err(|e| show_error(&platform, e))?;
+                print_update_check(&check, args.json);
+                std::process::exit(check.state.exit_code());
+            },
+            Command::Verify => {
+                let report = verify::verify(&installer).inspect_err(|e| show_error(&platform, e))?;
+                println!("{}", if args.json { report.to_json() } else { report.to_text() });
+                std::process::exit(report.exit_code());
+            },
+            // Args::parse only lets this through with --help
+            Command::Backups(None) => unreachable!(),
+            Command::Backups(Some(command)) => {
+                if let Err(e) = run_backups(&installer, command, args.json, args.quiet) {
+                    show_error(&platform, &e);
+                    return Err(e);
                 }
-                res
+                return Ok(true);
             },
-            Command::Uninstall => installer.uninstall()
+            // handled before there's an installer
+            #[cfg(feature = "net_install")]
+            Command::Cache(_) => unreachable!()
         };
-        if let Err(e) = res {
-            unsafe { MessageBoxW(None, &HSTRING::from(e.to_string()), w!("Hachimi Installer"), MB_ICONERROR | MB_OK); }
-            return Err(e);
+        if args.dry_run {
+            // only show what would happen, don't touch anything
+            let plan = installer.plan(operation).inspect_err(|e| show_error(&platform, e))?;
+            println!("{}", if args.json { plan.to_json() } else { plan.to_text() });
+            return Ok(true);
+        }
+
+        if let Operation::Install { .. } = operation {
+            match installer.confirm_downgrade() {
+                Ok(true) => {},
+                // user chose to keep the newer version
+                Ok(false) => return Ok(true),
+                Err(e) => {
+                    show_error(&platform, &e);
+                    return Err(e);
+                }
+            }
+        }
+
+        let dll = installer.target.dll_name();
+        let dir = installer.install_dir.as_deref().map(|p| p.display().to_string()).unwrap_or_default();
+        progress(args.quiet, &match operation {
+            Operation::Install { .. } => t!("cli.installing", dll = dll, dir = dir),
+            Operation::Uninstall => t!("cli.uninstalling", dll = dll, dir = dir),
+            Operation::Repair => t!("cli.repairing", dll = dll, dir = dir),
+            Operation::Ensure { .. } => t!("cli.ensuring", dll = dll, dir = dir),
+            Operation::Restore | Operation::Prune => unreachable!()
+        });
+
+        let res = match installer.recover_interrupted() {
+            Ok(true) => installer.plan(operation),
+            // user chose to leave the interrupted operation alone
+            Ok(false) => return match command {
+                Command::Launch => run_game(&platform, &args.game_args),
+                _ => Ok(true)
+            },
+            Err(e) => Err(e)
+        };
+        let res = res.and_then(|plan| {
+            // ensure and repair come up empty when everything's already in place
+            if plan.steps.is_empty() {
+                return Ok(false);
+            }
+            installer.run_plan(&plan).map(|_| true)
+        });
+        match res {
+            Ok(true) => progress(args.quiet, &t!("cli.done")),
+            Ok(false) => progress(args.quiet, &t!("cli.nothing_to_do")),
+            // the game still gets started, just without whatever couldn't be fixed
+            Err(e) if matches!(command, Command::Launch) => show_error(&platform, &e),
+            Err(e) => {
+                show_error(&platform, &e);
+                return Err(e);
+            }
+        }
+
+        if let Command::Launch = command {
+            return run_game(&platform, &args.game_args);
         }
 
         if args.launch_game {
+            progress(args.quiet, &t!("cli.launching_game"));
             let game_dir = installer.install_dir.unwrap();
-            let exe_path = game_dir.join("umamusume.exe");
+            let exe_path = game_dir.join(installer.target.exe_name());
             unsafe {
                 ShellExecuteW(
                     None,
@@ -192,4 +800,196 @@ pub fn run() -> Result<bool, installer::Error> {
     else {
         Ok(false)
     }
-}
\ No newline at end of file
+}
+
+// Runs the game command line from `launch` and exits with its exit code, so Steam sees how the game exited
+fn run_game(platform: &Platform, command_line: &[String]) -> Result<bool, installer::Error> {
+    let Some((exe, args)) = command_line.split_first() else {
+        return Ok(true);
+    };
+    match std::process::Command::new(exe).args(args).status() {
+        Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+        Err(e) => {
+            let e = installer::Error::from(e);
+            show_error(platform, &e);
+            Err(e)
+        }
+    }
+}
+
+fn print_update_check(check: &UpdateCheck, json: bool) {
+    if json {
+        let fields = vec![
+            ("state", JsonValue::String(check.state.id().to_owned())),
+            ("installed", check.installed.clone().map(JsonValue::String).unwrap_or(JsonValue::Null)),
+            ("staged", JsonValue::String(check.staged.clone()))
+        ];
+        let obj = JsonValue::Object(fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect());
+        println!("{}", obj.format().unwrap_or_default());
+        return;
+    }
+
+    match (check.state, &check.installed) {
+        (UpdateState::NotInstalled, _) | (_, None) => println!("not installed, staged {}", check.staged),
+        (state, Some(installed)) => println!("{}: {} -> {}", state.id(), installed, check.staged)
+    }
+}
+
+fn run_backups(installer: &Installer, command: BackupsCommand, json: bool, quiet: bool) -> Result<(), installer::Error> {
+    let store = installer.get_backup_store().ok_or(installer::Error::NoInstallDir)?;
+    let current_build_id = installer.get_current_steam_build_id();
+    let in_use = installer.get_backups_in_use()?;
+
+    match command {
+        BackupsCommand::List => {
+            let entries = store.list()?;
+            if json {
+                let array = entries.iter().map(|e| {
+                    let JsonValue::Object(mut obj) = store.entry_json(e) else {
+                        unreachable!()
+                    };
+                    obj.insert("in_use".to_owned(), JsonValue::Boolean(in_use.contains(&e.file)));
+                    obj.insert("stale".to_owned(), JsonValue::Boolean(e.is_stale(current_build_id)));
+                    JsonValue::Object(obj)
+                }).collect();
+                println!("{}", JsonValue::Array(array).format().unwrap_or_default());
+                return Ok(());
+            }
+
+            if entries.is_empty() {
+                println!("no backups");
+            }
+            for e in &entries {
+                println!(
+                    "{}  {}  {}  steam build {}  {} bytes  {}{}{}",
+                    e.id,
+                    utils::format_unix_date(e.created as u32),
+                    e.build.as_deref().unwrap_or("?"),
+                    e.steam_build_id.map(|v| v.to_string()).unwrap_or_else(|| "?".to_owned()),
+                    e.size,
+                    e.path.display(),
+                    if in_use.contains(&e.file) { " (in use)" } else { "" },
+                    if e.is_stale(current_build_id) { " (stale)" } else { "" }
+                );
+            }
+        },
+        BackupsCommand::Restore(id) => {
+            let entry = store.find(&id)?.ok_or(installer::Error::BackupNotFound(id))?;
+            installer.run_plan(&installer.plan_restore(&entry, current_build_id)?)?;
+            progress(quiet, &format!("restored {} from backup {}", entry.path.display(), entry.id));
+        },
+        BackupsCommand::Prune => {
+            // journaled like everything else, an interrupted prune doesn't leave half an entry behind
+            let plan = installer.plan(Operation::Prune)?;
+            installer.run_plan(&plan)?;
+            for step in &plan.steps {
+                if let Step::RemoveDir { path, only_if_empty: false } = step {
+                    progress(quiet, &format!("removed {}", path.display()));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "net_install")]
+fn run_cache(cache: Option<DownloadCache>, command: CacheCommand, json: bool, quiet: bool) -> Result<(), installer::Error> {
+    let cache = cache.ok_or_else(|| installer::Error::IoError(std::io::Error::new(
+        std::io::ErrorKind::NotFound, "no cache directory, use --cache-dir"
+    )))?;
+
+    match command {
+        CacheCommand::List => {
+            let entries = cache.list()?;
+            if json {
+                let array = entries.iter().map(|e| e.to_json()).collect();
+                println!("{}", JsonValue::Array(array).format().unwrap_or_default());
+                return Ok(());
+            }
+
+            if entries.is_empty() {
+                println!("no cached downloads");
+            }
+            for e in &entries {
+                println!("{}  {}  {} bytes  {}", e.sidecar.tag, e.sidecar.digest, e.sidecar.size, e.file.display());
+            }
+        },
+        CacheCommand::Clear => {
+            for dir in cache.entry_dirs()? {
+                std::fs::remove_dir_all(&dir)?;
+                progress(quiet, &format!("removed {}", dir.display()));
+            }
+            _ = std::fs::remove_dir(cache.dir());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, ArgError> {
+        Args::parse_from(args.iter().map(|s| s.to_string()))
+    }
+
+    fn parse_ok(args: &[&str]) -> Args {
+        match parse(args) {
+            Ok(args) => args,
+            Err(e) => panic!("{:?} failed to parse: {}", args, e)
+        }
+    }
+
+    fn exit_code(args: &[&str]) -> i32 {
+        match parse(args) {
+            Ok(_) => panic!("{:?} parsed", args),
+            Err(e) => e.exit_code()
+        }
+    }
+
+    #[test]
+    fn parses_commands_and_options() {
+        let args = parse_ok(&["install", "--install-dir", "C:\\game", "--sleep=5", "-q", "--dry-run"]);
+        assert!(matches!(args.command, Some(Command::Install)));
+        assert_eq!(args.install_dir, Some(PathBuf::from("C:\\game")));
+        assert_eq!(args.sleep, Some(5));
+        assert!(args.quiet && args.dry_run && !args.help);
+
+        let args = parse_ok(&["launch", "--platform", "steam-jp", "--", "game.exe", "--help"]);
+        assert!(matches!(args.command, Some(Command::Launch)));
+        assert!(args.explicit_target == Some(Target::CriManaVpx));
+        // everything after -- belongs to the game
+        assert_eq!(args.game_args, ["game.exe", "--help"]);
+        assert!(!args.help);
+
+        let args = parse_ok(&["backups", "restore", "0123456789abcdef", "--force"]);
+        assert!(matches!(args.command, Some(Command::Backups(Some(BackupsCommand::Restore(ref id)))) if id == "0123456789abcdef"));
+        assert!(args.force);
+        assert_eq!(parse_ok(&["--restore-dotlocal", "0"]).restore_dotlocal, Some(0));
+    }
+
+    #[test]
+    fn help_never_needs_the_rest() {
+        for args in [&["--help"][..], &["install", "-h"], &["backups", "--help"], &["backups", "restore", "--help"], &["launch", "-h"]] {
+            assert!(parse_ok(args).help, "{:?} didn't ask for help", args);
+        }
+        assert!(matches!(parse_ok(&["backups", "restore", "-h"]).command, Some(Command::Backups(None))));
+    }
+
+    #[test]
+    fn usage_errors_have_their_own_exit_codes() {
+        assert_eq!(exit_code(&["--bogus"]), 128);
+        assert_eq!(exit_code(&["install", "--install-dir"]), 129);
+        assert_eq!(exit_code(&["backups", "restore"]), 129);
+        assert_eq!(exit_code(&["backups", "restore", "--force"]), 129);
+        assert_eq!(exit_code(&["launch"]), 129);
+        assert_eq!(exit_code(&["--sleep", "soon"]), 130);
+        assert_eq!(exit_code(&["--platform", "ps5"]), 130);
+        assert_eq!(exit_code(&["--restore-dotlocal", "-1"]), 130);
+        assert_eq!(exit_code(&["--dry-run=yes"]), 131);
+        assert_eq!(exit_code(&["backups", "delete"]), 132);
+        assert_eq!(exit_code(&["backups"]), 133);
+    }
+}
@@ -0,0 +1,23 @@
+use windows::Win32::System::Console::{
+    AllocConsole, AttachConsole, GetStdHandle, ATTACH_PARENT_PROCESS, STD_ERROR_HANDLE, STD_HANDLE
+};
+
+fn has_std_handle(handle: STD_HANDLE) -> bool {
+    unsafe { GetStdHandle(handle) }.is_ok_and(|h| !h.is_invalid())
+}
+
+// The exe is built for the windows subsystem, so it doesn't get a console by itself.
+// Returns true if stdout/stderr go somewhere afterwards.
+pub fn attach(allocate: bool) -> bool {
+    // already redirected to a file or pipe by whoever started us, keep it that way
+    if has_std_handle(STD_ERROR_HANDLE) {
+        return true;
+    }
+
+    unsafe {
+        if AttachConsole(ATTACH_PARENT_PROCESS).is_ok() {
+            return true;
+        }
+        allocate && AllocConsole().is_ok()
+    }
+}
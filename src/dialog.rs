@@ -0,0 +1,72 @@
+use std::path::{Path, PathBuf};
+use crate::i18n::t;
+use windows::{
+    core::HSTRING,
+    Win32::{
+        Foundation::{HWND, RECT},
+        System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER},
+        UI::{
+            Shell::{
+                FileOpenDialog, IFileOpenDialog, IShellItem, SHCreateItemFromParsingName,
+                FOS_FILEMUSTEXIST, FOS_PICKFOLDERS, SIGDN_FILESYSPATH,
+            },
+            WindowsAndMessaging::{GetDesktopWindow, GetWindowRect, SetWindowPos, SWP_NOSIZE},
+        },
+    },
+};
+
+pub trait RECTExt {
+    fn dimensions(&self) -> (i32, i32);
+}
+
+impl RECTExt for RECT {
+    fn dimensions(&self) -> (i32, i32) {
+        (self.right - self.left, self.bottom - self.top)
+    }
+}
+
+pub fn center_window(window: HWND) -> Result<(), windows::core::Error> {
+    let screen = unsafe { GetDesktopWindow() };
+    let mut screen_rect = RECT::default();
+    unsafe { GetWindowRect(screen, &mut screen_rect)? };
+    let (screen_width, screen_height) = screen_rect.dimensions();
+
+    let mut window_rect = RECT::default();
+    unsafe { GetWindowRect(window, &mut window_rect)? };
+    let (window_width, window_height) = window_rect.dimensions();
+
+    let x = screen_rect.left + (screen_width - window_width) / 2;
+    let y = screen_rect.top + (screen_height - window_height) / 2;
+    unsafe { SetWindowPos(window, None, x, y, 0, 0, SWP_NOSIZE)? };
+
+    Ok(())
+}
+
+pub fn open_select_folder_dialog<P: AsRef<Path>>(
+    owner: HWND,
+    default_folder: Option<P>,
+) -> Option<PathBuf> {
+    let dialog: IFileOpenDialog =
+        unsafe { CoCreateInstance(&FileOpenDialog, None, CLSCTX_INPROC_SERVER).ok()? };
+
+    unsafe {
+        dialog.SetTitle(&HSTRING::from(t!("util.select_folder"))).ok()?;
+        dialog
+            .SetOptions(FOS_FILEMUSTEXIST | FOS_PICKFOLDERS)
+            .ok()?;
+
+        if let Some(path) = default_folder {
+            let default_folder_item: IShellItem =
+                SHCreateItemFromParsingName(&HSTRING::from(path.as_ref().to_str().unwrap()), None)
+                    .ok()?;
+            dialog.SetDefaultFolder(&default_folder_item).ok()?;
+        }
+
+        dialog.Show(owner).ok()?
+    }
+
+    let result = unsafe { dialog.GetResult().ok()? };
+    let path = unsafe { result.GetDisplayName(SIGDN_FILESYSPATH).ok()? };
+    let path_str = unsafe { path.to_string().unwrap() };
+    Some(path_str.into())
+}
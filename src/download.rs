@@ -0,0 +1,382 @@
+use std::{
+    fs::OpenOptions,
+    io::{Read, Write},
+    path::PathBuf,
+    sync::{mpsc, Arc},
+    time::{Duration, Instant}
+};
+use bytes::Bytes;
+use reqwest::{blocking::RequestBuilder, header, StatusCode};
+use crate::{installer::Error, utils};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+// without any data coming in; the whole download can take as long as it needs
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+const RETRIES: u32 = 3;
+// doubled after every attempt
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+const CHUNK_SIZE: usize = 64 * 1024;
+
+pub struct Progress {
+    pub downloaded: u64,
+    // None if the server doesn't say
+    pub total: Option<u64>,
+    // bytes per second over the current attempt
+    pub rate: u64,
+    pub done: bool
+}
+
+impl Progress {
+    pub fn percent(&self) -> Option<u64> {
+        self.total.filter(|t| *t > 0).map(|t| self.downloaded * 100 / t)
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{} B", bytes),
+        1024..1048576 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1048576.0)
+    }
+}
+
+impl std::fmt::Display for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", format_size(self.downloaded))?;
+        if let (Some(total), Some(percent)) = (self.total, self.percent()) {
+            write!(f, " / {} ({}%)", format_size(total), percent)?;
+        }
+        write!(f, ", {}/s", format_size(self.rate))
+    }
+}
+
+pub type ProgressCallback = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+// What the request thread sends back, the response first
+enum Event {
+    Response(StatusCode, Option<u64>),
+    Chunk(Vec<u8>),
+    End
+}
+
+// Runs the request on its own thread, so waiting for the next bit of it can time out without
+// a timeout on the whole request. `range` lets a 416 through for the caller to handle.
+fn stream(request: RequestBuilder, range: bool) -> mpsc::Receiver<Result<Event, Error>> {
+    let (tx, rx) = mpsc::sync_channel(4);
+    std::thread::spawn(move || {
+        let mut resp = match request.send() {
+            Ok(resp) => resp,
+            Err(e) => {
+                _ = tx.send(Err(e.into()));
+                return;
+            }
+        };
+        let status = resp.status();
+        if !(range && status == StatusCode::RANGE_NOT_SATISFIABLE) {
+            if let Err(e) = resp.error_for_status_ref() {
+                _ = tx.send(Err(e.into()));
+                return;
+            }
+        }
+        if tx.send(Ok(Event::Response(status, resp.content_length()))).is_err() {
+            return;
+        }
+
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        loop {
+            // a failed send means the other end gave up on us
+            let sent = match resp.read(&mut buf) {
+                Ok(0) => {
+                    _ = tx.send(Ok(Event::End));
+                    return;
+                },
+                Ok(read) => tx.send(Ok(Event::Chunk(buf[..read].to_vec()))),
+                Err(e) => {
+                    _ = tx.send(Err(e.into()));
+                    return;
+                }
+            };
+            if sent.is_err() {
+                return;
+            }
+        }
+    });
+    rx
+}
+
+// Timeouts, retries with backoff and resuming into a partial file, for everything net_install fetches
+pub struct Downloader {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    pub retries: u32,
+    pub retry_delay: Duration,
+    // where unfinished downloads are kept until the next attempt
+    pub partial_dir: PathBuf,
+    pub progress: Option<ProgressCallback>
+}
+
+impl Default for Downloader {
+    fn default() -> Self {
+        Downloader {
+            connect_timeout: CONNECT_TIMEOUT,
+            read_timeout: READ_TIMEOUT,
+            retries: RETRIES,
+            retry_delay: RETRY_DELAY,
+            partial_dir: std::env::temp_dir().join("hachimi_installer"),
+            progress: None
+        }
+    }
+}
+
+// Network trouble, as opposed to a bad URL or a full disk
+fn is_retryable(e: &Error) -> bool {
+    match e {
+        Error::ReqwestError(e) => {
+            e.is_timeout() || e.is_connect() || e.is_body() || e.is_request() ||
+                e.status().is_some_and(|s| s.is_server_error() || s == StatusCode::TOO_MANY_REQUESTS)
+        },
+        // reading the response body ends up here
+        Error::IoError(e) => matches!(
+            e.kind(),
+            std::io::ErrorKind::TimedOut | std::io::ErrorKind::ConnectionReset |
+            std::io::ErrorKind::ConnectionAborted | std::io::ErrorKind::UnexpectedEof
+        ) || e.get_ref().is_some_and(|inner| inner.is::<reqwest::Error>()),
+        _ => false
+    }
+}
+
+impl Downloader {
+    fn client(&self) -> Result<reqwest::blocking::Client, Error> {
+        Ok(reqwest::blocking::Client::builder()
+            .user_agent("hachimi-installer")
+            .connect_timeout(self.connect_timeout)
+            // the idle timeout is done in next_event, and keepalive gets rid of dead connections
+            .timeout(None::<Duration>)
+            .tcp_keepalive(self.read_timeout)
+            .build()?)
+    }
+
+    fn next_event(&self, events: &mpsc::Receiver<Result<Event, Error>>) -> Result<Event, Error> {
+        match events.recv_timeout(self.read_timeout) {
+            Ok(event) => event,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into()),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into())
+        }
+    }
+
+    fn response(&self, events: &mpsc::Receiver<Result<Event, Error>>) -> Result<(StatusCode, Option<u64>), Error> {
+        match self.next_event(events)? {
+            Event::Response(status, len) => Ok((status, len)),
+            _ => Err(std::io::Error::from(std::io::ErrorKind::InvalidData).into())
+        }
+    }
+
+    fn retry<T>(&self, mut attempt: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+        let mut retries = 0;
+        loop {
+            match attempt() {
+                Err(e) if retries < self.retries && is_retryable(&e) => {
+                    std::thread::sleep(self.retry_delay * 2u32.pow(retries));
+                    retries += 1;
+                },
+                res => return res
+            }
+        }
+    }
+
+    // Small responses like release info, retried but not resumed
+    pub fn get_text(&self, url: &str) -> Result<String, Error> {
+        self.retry(|| {
+            let events = stream(self.client()?.get(url), false);
+            self.response(&events)?;
+            let mut body = Vec::new();
+            while let Event::Chunk(chunk) = self.next_event(&events)? {
+                body.extend_from_slice(&chunk);
+            }
+            Ok(String::from_utf8_lossy(&body).into_owned())
+        })
+    }
+
+    pub fn get(&self, url: &str, accept: Option<&str>) -> Result<Bytes, Error> {
+        let res = self.retry(|| self.try_get(url, accept, true));
+        if res.is_err() {
+            // out of retries, don't leave it lying around in temp forever
+            _ = std::fs::remove_file(self.partial_path(url));
+            _ = std::fs::remove_dir(&self.partial_dir);
+        }
+        res
+    }
+
+    fn partial_path(&self, url: &str) -> PathBuf {
+        let hash = utils::sha256_hex(url.as_bytes());
+        self.partial_dir.join(format!("{}.part", &hash[..16]))
+    }
+
+    fn report(&self, progress: Progress) {
+        if let Some(callback) = &self.progress {
+            callback(&progress);
+        }
+    }
+
+    // `resume` is off for the one retry after a 416
+    fn try_get(&self, url: &str, accept: Option<&str>, resume: bool) -> Result<Bytes, Error> {
+        std::fs::create_dir_all(&self.partial_dir)?;
+        let partial_path = self.partial_path(url);
+        let resume_from = if resume { std::fs::metadata(&partial_path).map(|m| m.len()).unwrap_or(0) } else { 0 };
+
+        let mut request = self.client()?.get(url);
+        if let Some(accept) = accept {
+            request = request.header(header::ACCEPT, accept);
+        }
+        if resume_from > 0 {
+            request = request.header(header::RANGE, format!("bytes={}-", resume_from));
+        }
+        let events = stream(request, resume_from > 0);
+        let (status, content_length) = self.response(&events)?;
+        if status == StatusCode::RANGE_NOT_SATISFIABLE {
+            // the file changed since, or the partial one is bogus; this time there's nothing to resume,
+            // and without a Range another 416 is an error like any other
+            std::fs::remove_file(&partial_path)?;
+            return self.try_get(url, accept, false);
+        }
+
+        // a server that ignores Range sends the whole thing again
+        let resumed = resume_from > 0 && status == StatusCode::PARTIAL_CONTENT;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(&partial_path)?;
+        let start = if resumed { resume_from } else { 0 };
+        let total = content_length.map(|len| len + start);
+
+        let mut downloaded = start;
+        let started = Instant::now();
+        let mut last_report = started;
+        let rate = |downloaded: u64| {
+            let elapsed = started.elapsed().as_secs_f64();
+            if elapsed > 0.0 { ((downloaded - start) as f64 / elapsed) as u64 } else { 0 }
+        };
+
+        while let Event::Chunk(chunk) = self.next_event(&events)? {
+            file.write_all(&chunk)?;
+            downloaded += chunk.len() as u64;
+
+            if last_report.elapsed() >= PROGRESS_INTERVAL {
+                last_report = Instant::now();
+                self.report(Progress { downloaded, total, rate: rate(downloaded), done: false });
+            }
+        }
+        file.flush()?;
+        drop(file);
+
+        // cut off, the partial file stays for the next attempt
+        if total.is_some_and(|t| downloaded < t) {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        self.report(Progress { downloaded, total, rate: rate(downloaded), done: true });
+
+        let bytes = std::fs::read(&partial_path)?;
+        std::fs::remove_file(&partial_path)?;
+        Ok(Bytes::from(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use super::*;
+    use crate::test_utils::{response, serve_with, Request, TempDir};
+
+    fn downloader(tmp: &TempDir) -> Downloader {
+        Downloader {
+            retries: 1,
+            retry_delay: Duration::ZERO,
+            partial_dir: tmp.path().join("partial"),
+            ..Default::default()
+        }
+    }
+
+    fn body() -> Vec<u8> {
+        (0..=255).cycle().take(1000).collect()
+    }
+
+    // Serves body() through `handler`, keeping the Range of every request
+    fn serve(handler: impl Fn(&Request, &[u8]) -> Vec<u8> + Send + 'static) -> (String, Arc<Mutex<Vec<Option<u64>>>>) {
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let seen = ranges.clone();
+        let base = serve_with(move |request| {
+            seen.lock().unwrap().push(request.range_start);
+            handler(request, &body())
+        });
+        (format!("{}/hachimi.dll", base), ranges)
+    }
+
+    // claims the whole body but the connection drops halfway
+    fn cut_off(body: &[u8]) -> Vec<u8> {
+        let mut res = response("200 OK", &[], body);
+        res.truncate(res.len() - body.len() / 2);
+        res
+    }
+
+    #[test]
+    fn resumes_after_a_cut_off() {
+        let tmp = TempDir::new();
+        let (url, ranges) = serve(|request, body| match request.range_start {
+            None => cut_off(body),
+            Some(start) => response(
+                "206 Partial Content",
+                &[format!("Content-Range: bytes {}-{}/{}", start, body.len() - 1, body.len())],
+                &body[start as usize..]
+            )
+        });
+
+        let downloader = downloader(&tmp);
+        assert_eq!(downloader.get(&url, None).unwrap(), body());
+        assert_eq!(*ranges.lock().unwrap(), [None, Some(500)]);
+        assert!(!downloader.partial_path(&url).exists());
+    }
+
+    #[test]
+    fn starts_over_once_on_416() {
+        let tmp = TempDir::new();
+        let (url, ranges) = serve(|request, body| match request.range_start {
+            None => response("200 OK", &[], body),
+            Some(_) => response("416 Range Not Satisfiable", &[], &[])
+        });
+        let downloader = downloader(&tmp);
+        std::fs::create_dir_all(&downloader.partial_dir).unwrap();
+        std::fs::write(downloader.partial_path(&url), vec![0; 2000]).unwrap();
+
+        assert_eq!(downloader.get(&url, None).unwrap(), body());
+        assert_eq!(*ranges.lock().unwrap(), [Some(2000), None]);
+    }
+
+    #[test]
+    fn gives_up_on_416_without_range() {
+        let tmp = TempDir::new();
+        let (url, ranges) = serve(|_, _| response("416 Range Not Satisfiable", &[], &[]));
+        let downloader = downloader(&tmp);
+        std::fs::create_dir_all(&downloader.partial_dir).unwrap();
+        std::fs::write(downloader.partial_path(&url), b"stale").unwrap();
+
+        assert!(downloader.get(&url, None).is_err());
+        // one retry without Range, and a 416 isn't worth retrying after that
+        assert_eq!(*ranges.lock().unwrap(), [Some(5), None]);
+    }
+
+    #[test]
+    fn removes_the_partial_file_once_out_of_retries() {
+        let tmp = TempDir::new();
+        // every attempt gets cut off, resumed or not
+        let (url, ranges) = serve(|request, body| cut_off(&body[request.range_start.unwrap_or(0) as usize..]));
+        let downloader = downloader(&tmp);
+
+        assert!(downloader.get(&url, None).is_err());
+        assert_eq!(ranges.lock().unwrap().len(), 2);
+        assert!(!downloader.partial_path(&url).exists());
+        assert!(!downloader.partial_dir.exists());
+    }
+}
@@ -0,0 +1,32 @@
+// Patches for known builds of the JP Steam exe, generated by build.rs from exe_patches/index.json
+pub struct ExePatch {
+    pub build: &'static str,
+    pub product_version: Option<&'static str>,
+    pub timestamp: Option<u32>,
+    pub source_sha256: &'static str,
+    pub sha256: &'static str,
+    pub patch: &'static [u8]
+}
+
+// An exe one of our patches produced. These stay in exe_patches/patched.json after their patch
+// is dropped from the index, so an exe we patched is never taken for an original.
+pub struct PatchedExe {
+    pub build: &'static str,
+    pub source_sha256: &'static str,
+    pub sha256: &'static str
+}
+
+include!(concat!(env!("OUT_DIR"), "/exe_patches.rs"));
+
+pub fn find_patch(source_sha256: &str) -> Option<&'static ExePatch> {
+    EXE_PATCHES.iter().find(|p| p.source_sha256 == source_sha256)
+}
+
+// An exe with this hash is already patched, whether or not its patch is still around
+pub fn find_patched(sha256: &str) -> Option<&'static PatchedExe> {
+    PATCHED_EXES.iter().find(|p| p.sha256 == sha256)
+}
+
+pub fn find_by_version(product_version: &str, timestamp: u32) -> Option<&'static ExePatch> {
+    EXE_PATCHES.iter().find(|p| p.product_version == Some(product_version) && p.timestamp == Some(timestamp))
+}
@@ -0,0 +1,175 @@
+use std::path::Path;
+use crate::{exe_patch::{self, ExePatch, PatchedExe}, i18n::t, installer::Installer, utils};
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum ExeState {
+    // matches a known original build
+    Vanilla,
+    // matches the output of one of our patches
+    Patched,
+    // anything else, could be a game update or a modified exe
+    Unknown
+}
+
+impl ExeState {
+    pub fn id(&self) -> &'static str {
+        match self {
+            ExeState::Vanilla => "vanilla",
+            ExeState::Patched => "patched",
+            ExeState::Unknown => "unknown"
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            ExeState::Vanilla => t!("game.exe_vanilla"),
+            ExeState::Patched => t!("game.exe_patched"),
+            ExeState::Unknown => t!("game.exe_unknown")
+        }
+    }
+}
+
+// What we can tell about the JP Steam exe without running it
+pub struct GameExeInfo {
+    pub sha256: String,
+    pub product_version: Option<String>,
+    pub timestamp: Option<u32>,
+    // known build this exe is, or was patched from, if there's a patch for it
+    pub build: Option<&'static ExePatch>,
+    // set if this is the output of one of our patches
+    pub patched: Option<&'static PatchedExe>
+}
+
+impl GameExeInfo {
+    pub fn read(path: &Path) -> std::io::Result<GameExeInfo> {
+        let bytes = std::fs::read(path)?;
+        let sha256 = utils::sha256_hex(&bytes);
+        let timestamp = pelite::PeFile::from_bytes(&bytes).ok()
+            .map(|pe| pe.file_header().TimeDateStamp);
+        let product_version = utils::read_pe_version_info(&bytes)
+            .and_then(|v| v.value(Installer::LANG_NEUTRAL_UNICODE, "ProductVersion"));
+
+        let patched = exe_patch::find_patched(&sha256);
+        let build = exe_patch::find_patch(patched.map(|p| p.source_sha256).unwrap_or(sha256.as_str()));
+
+        Ok(GameExeInfo { sha256, product_version, timestamp, build, patched })
+    }
+
+    pub fn state(&self) -> ExeState {
+        if self.patched.is_some() {
+            ExeState::Patched
+        }
+        else if self.build.is_some() {
+            ExeState::Vanilla
+        }
+        else {
+            ExeState::Unknown
+        }
+    }
+
+    // an exe we patched before needs nothing else, even without the patch for it
+    pub fn is_supported(&self) -> bool {
+        self.state() != ExeState::Unknown
+    }
+
+    pub fn build_name(&self) -> Option<&'static str> {
+        self.patched.map(|p| p.build).or(self.build.map(|b| b.build))
+    }
+
+    // Hash of the original this exe is, or was patched from
+    pub fn source_sha256(&self) -> Option<&str> {
+        match self.state() {
+            ExeState::Patched => self.patched.map(|p| p.source_sha256),
+            ExeState::Vanilla => Some(&self.sha256),
+            ExeState::Unknown => None
+        }
+    }
+
+    pub fn label(&self) -> String {
+        if let Some(build) = self.build_name() {
+            return build.to_owned();
+        }
+
+        // same version and timestamp as a known build but different contents
+        let known = self.product_version.as_deref()
+            .zip(self.timestamp)
+            .and_then(|(version, timestamp)| exe_patch::find_by_version(version, timestamp));
+        if let Some(build) = known {
+            return format!("{} (modified, sha256 {})", build.build, &self.sha256[..16]);
+        }
+
+        format!(
+            "{} ({}, sha256 {})",
+            self.product_version.as_deref().unwrap_or("?"),
+            self.timestamp.map(utils::format_unix_date).unwrap_or_else(|| "?".to_owned()),
+            &self.sha256[..16]
+        )
+    }
+
+    pub fn describe(&self) -> String {
+        let build = if self.is_supported() {
+            t!("game.build_supported", build = self.label())
+        }
+        else {
+            t!("game.build_unsupported", build = self.label())
+        };
+        format!("{} ({})", build, self.state().label())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn unknown_exe_is_unsupported() {
+        let tmp = TempDir::new();
+        let path = tmp.path().join("UmamusumePrettyDerby_Jpn.exe");
+        std::fs::write(&path, b"not a PE file").unwrap();
+
+        let info = GameExeInfo::read(&path).unwrap();
+        assert_eq!(info.sha256, utils::sha256_hex(b"not a PE file"));
+        assert!(info.product_version.is_none() && info.timestamp.is_none());
+        assert!(info.state() == ExeState::Unknown);
+        assert!(!info.is_supported());
+        assert_eq!(info.label(), format!("? (?, sha256 {})", &info.sha256[..16]));
+        assert!(info.describe().starts_with(&t!("game.build_unsupported", build = info.label())));
+
+        assert!(GameExeInfo::read(&tmp.path().join("missing.exe")).is_err());
+    }
+
+    static BUILD: ExePatch = ExePatch {
+        build: "1.0.0",
+        product_version: Some("1.0.0"),
+        timestamp: Some(0),
+        source_sha256: "original",
+        sha256: "patched",
+        patch: &[]
+    };
+    static PATCHED: PatchedExe = PatchedExe { build: "0.9.0", source_sha256: "older original", sha256: "older patched" };
+
+    fn info(sha256: &str, build: Option<&'static ExePatch>, patched: Option<&'static PatchedExe>) -> GameExeInfo {
+        GameExeInfo { sha256: sha256.to_owned(), product_version: None, timestamp: None, build, patched }
+    }
+
+    #[test]
+    fn backs_up_only_known_originals() {
+        let vanilla = info("original", Some(&BUILD), None);
+        assert!(vanilla.state() == ExeState::Vanilla);
+        assert_eq!(vanilla.source_sha256(), Some("original"));
+        assert_eq!(vanilla.build_name(), Some("1.0.0"));
+
+        // patched by us, even after its patch is gone from the index
+        let patched = info("older patched", None, Some(&PATCHED));
+        assert!(patched.state() == ExeState::Patched);
+        assert!(patched.is_supported());
+        assert_eq!(patched.source_sha256(), Some("older original"));
+        assert_eq!(patched.build_name(), Some("0.9.0"));
+
+        let unknown = info("0123456789abcdef0123", None, None);
+        assert!(unknown.state() == ExeState::Unknown);
+        assert_eq!(unknown.source_sha256(), None);
+        assert_eq!(unknown.build_name(), None);
+    }
+}
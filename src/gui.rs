@@ -1,19 +1,22 @@
-use crate::{installer::{self, Installer}, resource::*, utils};
+use std::{path::Path, rc::Rc};
 #[cfg(feature = "net_install")]
-use std::thread;
+use std::sync::Arc;
+use crate::{
+    dialog, game::GameExeInfo, installer::{self, Installer}, journal::Operation,
+    platform::{self, windows::ParentWindow, Answer, Buttons, Level}, resource::*
+};
 #[cfg(feature = "net_install")]
-use tinyjson::JsonValue;
+use crate::{download::{Downloader, Progress}, release};
 use crate::i18n::{self, SUPPORTED_LOCALES, t};
 use windows::{core::{HSTRING}, Win32::{
     Foundation::{HWND, LPARAM, WPARAM},
     System::LibraryLoader::GetModuleHandleW,
     UI::{Input::KeyboardAndMouse::EnableWindow, WindowsAndMessaging::{
         CreateDialogParamW, DestroyIcon, DispatchMessageW, GetDlgItem, GetMessageW,
-        GetWindowLongPtrW, LoadIconW, MessageBoxW, PostQuitMessage, SendMessageW,
+        GetWindowLongPtrW, LoadIconW, PostQuitMessage, SendMessageW,
         SetWindowLongPtrW,SetWindowTextW, ShowWindow, TranslateMessage,
         CBN_SELCHANGE, CB_ADDSTRING, CB_DELETESTRING, CB_GETCURSEL, CB_INSERTSTRING, CB_SETCURSEL,
-        GWLP_USERDATA, ICON_BIG, IDOK, IDYES, MB_ICONERROR, MB_ICONINFORMATION, MB_ICONWARNING,
-        MB_OK, MB_OKCANCEL, MB_YESNO, MSG, SW_SHOW, WM_CLOSE, WM_COMMAND, WM_INITDIALOG, WM_SETICON
+        GWLP_USERDATA, ICON_BIG, MSG, SW_SHOW, WM_CLOSE, WM_COMMAND, WM_INITDIALOG, WM_SETICON
     }},
 }};
 
@@ -26,66 +29,55 @@ fn localize_controls(dialog: HWND) {
         _ = SetWindowTextW(GetDlgItem(dialog, IDC_UNINSTALL).unwrap(), &HSTRING::from(t!("gui.uninstall")));
         _ = SetWindowTextW(GetDlgItem(dialog, IDC_INSTALL_PATH_BROWSE).unwrap(), &HSTRING::from(t!("gui.browse")));
         _ = SetWindowTextW(GetDlgItem(dialog, IDC_LANGUAGE_LABEL).unwrap(), &HSTRING::from(t!("gui.msg_language")));
-        _ = SetWindowTextW(GetDlgItem(dialog, IDC_PACKAGED_VER).unwrap(), &HSTRING::from(t!("gui.packaged_ver", ver = env!("HACHIMI_VERSION"))));
+        #[cfg(not(feature = "net_install"))]
+        let packaged_ver = t!("gui.packaged_ver", ver = env!("HACHIMI_VERSION"));
+        // no packaged DLL, it's whatever release was fetched
+        #[cfg(feature = "net_install")]
+        let packaged_ver = packaged_ver_label(get_installer(dialog));
+        _ = SetWindowTextW(GetDlgItem(dialog, IDC_PACKAGED_VER).unwrap(), &HSTRING::from(packaged_ver));
         _ = SetWindowTextW(GetDlgItem(dialog, IDC_INSTALL_LOCATION).unwrap(), &HSTRING::from(t!("gui.install_location")));
         _ = SetWindowTextW(GetDlgItem(dialog, IDC_TARGRT).unwrap(), &HSTRING::from(t!("gui.target")));
     }
 }
 
 pub fn run() -> Result<(), windows::core::Error> {
-    let mut installer = Box::new(Installer::default());
+    let parent = ParentWindow::default();
+    let platform = Rc::new(platform::windows::platform(parent.clone()));
+    let mut installer = Box::new(Installer::new(platform));
 
     #[cfg(feature = "net_install")]
     {
-        // fetch version synchronously before creating the GUI
-        let client = reqwest::blocking::Client::builder()
-            .user_agent("hachimi-installer")
-            .build()
-            .unwrap();
-
-        let get_version = || -> Result<String, Box<dyn std::error::Error>> {
-            let resp_text = client.get("https://api.github.com/repos/kairusds/Hachimi-Edge/releases/latest").send()?.text()?;
-            let json: JsonValue = resp_text.parse()?;
-
-            // stole from lead
-            let JsonValue::Object(root_obj) = json else {
-                return Err("GitHub API response was not a JSON object".into());
-            };
-
-            let JsonValue::String(tag_name) = &root_obj["tag_name"] else {
-                return Err("tag_name field not found or not a string in API response".into());
-            };
-
-            Ok(tag_name.clone())
-        };
-
-        match get_version() {
-            Ok(version) => {
-                *installer.hachimi_version.lock().unwrap() = Some(version);
+        match release::configured() {
+            Ok(source) => installer.release_source = source,
+            Err(e) => {
+                installer.platform.notify("error", &e.to_string(), "Error", Level::Error);
+                return Ok(());
             }
+        }
+
+        // fetch version synchronously before creating the GUI, the DLL comes once it's up;
+        // offline, the newest cached one will do
+        match installer.fetch_release().map(|_| ()).or_else(|e| installer.use_newest_cached(e)) {
+            Ok(_) => {},
             Err(_) => {
-                unsafe { MessageBoxW(None, w!("Unable to get Hachimi version from GitHub. Are you online?"), w!("Error"), MB_ICONERROR | MB_OK) };
+                let message = format!(
+                    "Unable to get the latest Hachimi release from {}. Are you online?",
+                    installer.release_source.describe()
+                );
+                installer.platform.notify("error", &message, "Error", Level::Error);
                 return Ok(());
             }
         }
     }
-
-    #[cfg(feature = "net_install")]
-    {
-        let dll_handle = installer.hachimi_dll.clone();
-        thread::spawn(move || {
-            let result = reqwest::blocking::get("https://github.com/kairusds/Hachimi-Edge/releases/latest/download/hachimi.dll")
-                .and_then(|resp| resp.bytes());
-            *dll_handle.lock().unwrap() = Some(result);
-        });
-    }
     let instance = unsafe { GetModuleHandleW(None)? };
     let dialog = unsafe {
         CreateDialogParamW(instance, IDD_MAIN, None, Some(dlg_proc), LPARAM(installer.as_mut() as *mut _ as _))
     }?;
-    utils::center_window(dialog)?;
+    dialog::center_window(dialog)?;
     unsafe { _ = ShowWindow(dialog, SW_SHOW) };
-    *installer.hwnd.lock().unwrap() = Some(dialog);
+    parent.set(dialog);
+    #[cfg(feature = "net_install")]
+    start_download(dialog);
 
     let mut message = MSG::default();
     unsafe {
@@ -102,12 +94,72 @@ fn get_installer(dialog: HWND) -> &'static mut Installer {
     unsafe { (GetWindowLongPtrW(dialog, GWLP_USERDATA) as *mut Installer).as_mut().unwrap() }
 }
 
+#[cfg(feature = "net_install")]
+fn packaged_ver_label(installer: &Installer) -> String {
+    let version_str = installer.hachimi_version.lock().unwrap()
+        .as_deref()
+        .unwrap_or("Error")
+        .to_string();
+    t!("gui.packaged_ver", ver = version_str)
+}
+
+// Progress goes next to the staged version, from the download thread
+#[cfg(feature = "net_install")]
+fn start_download(dialog: HWND) {
+    let installer = get_installer(dialog);
+    // HWND isn't Send
+    let label_static = unsafe { GetDlgItem(dialog, IDC_PACKAGED_VER).unwrap() }.0 as isize;
+    let set_label = move |text: String| unsafe {
+        _ = SetWindowTextW(HWND(label_static as _), &HSTRING::from(text));
+    };
+
+    let label = packaged_ver_label(installer);
+    let progress_label = label.clone();
+    installer.downloader = Arc::new(Downloader {
+        progress: Some(Arc::new(move |progress: &Progress| set_label(format!("{} ({})", progress_label, progress)))),
+        ..Downloader::default()
+    });
+    installer.start_download(move |ok| {
+        set_label(if ok { label } else { format!("{} (download failed)", label) })
+    });
+}
+
+// Whether the DLL is there to install, offers to download it again if it failed
+#[cfg(feature = "net_install")]
+fn check_download(dialog: HWND) -> bool {
+    let installer = get_installer(dialog);
+    if installer.hachimi_dll.lock().unwrap().is_none() {
+        installer.platform.notify(
+            "download_pending",
+            "Hachimi is still being downloaded, try again once it's done.",
+            &t!("gui.install"),
+            Level::Info
+        );
+        return false;
+    }
+    let Some(error) = installer.download_error() else {
+        return true;
+    };
+
+    let res = installer.platform.prompt.ask(
+        "download_retry",
+        &format!("{}\n\nDownload it again?", error),
+        &t!("gui.error"),
+        Level::Error,
+        Buttons::YesNo
+    );
+    if res == Answer::Yes {
+        start_download(dialog);
+    }
+    false
+}
+
 fn update_target(dialog: HWND, target_combo: HWND, index: usize) {
     let installer = get_installer(dialog);
     let target = installer::Target::VALUES[index];
 
     if installer.install_dir.is_none() {
-        installer.install_dir = Installer::detect_install_dir(target);
+        installer.install_dir = Installer::detect_install_dir(&installer.platform, target);
     }
     let install_path_edit = unsafe { GetDlgItem(dialog, IDC_INSTALL_PATH).unwrap() };
     if let Some(path) = &installer.install_dir {
@@ -125,9 +177,17 @@ fn update_target(dialog: HWND, target_combo: HWND, index: usize) {
         "None".to_owned()
     };
 
+    // the game exe is only touched on JP Steam, say what state it's in
+    let mut label = t!("gui.installed", ver = label);
+    if target == installer::Target::CriManaVpx {
+        if let Some(Ok(info)) = installer.get_orig_exe_path().map(|p| GameExeInfo::read(&p)) {
+            label = t!("gui.installed_exe_state", installed = label, state = info.state().label());
+        }
+    }
+
     let installed_static = unsafe { GetDlgItem(dialog, IDC_INSTALLED).unwrap() };
     unsafe {
-        _ = SetWindowTextW(installed_static, &HSTRING::from(t!("gui.installed", ver = label)));
+        _ = SetWindowTextW(installed_static, &HSTRING::from(label));
         _ = EnableWindow(GetDlgItem(dialog, IDC_UNINSTALL).unwrap(), installed);
     }
         
@@ -142,6 +202,49 @@ fn update_target(dialog: HWND, target_combo: HWND, index: usize) {
     installer.target = target;
 }
 
+// Offers to resume/revert an interrupted journal, false if it's still pending
+fn recover_interrupted(dialog: HWND) -> bool {
+    let installer = get_installer(dialog);
+    let res = installer.recover_interrupted();
+    update_target(dialog, unsafe { GetDlgItem(dialog, IDC_TARGET).unwrap() }, installer.target as _);
+
+    match res {
+        Ok(recovered) => recovered,
+        Err(e) => {
+            installer.platform.notify("error", &e.to_string(), &t!("gui.error"), Level::Error);
+            false
+        }
+    }
+}
+
+// The backups of files uninstall couldn't put back live in there too, keep those
+fn delete_data_dir(installer: &Installer, hachimi_dir: &Path) -> Result<(), installer::Error> {
+    let Some(store) = installer.get_backup_store().filter(|s| !s.entry_dirs().unwrap_or_default().is_empty()) else {
+        std::fs::remove_dir_all(hachimi_dir)?;
+        return Ok(());
+    };
+
+    for entry in std::fs::read_dir(hachimi_dir)? {
+        let path = entry?.path();
+        if path == store.dir() {
+            continue;
+        }
+        if path.is_dir() {
+            std::fs::remove_dir_all(&path)?;
+        }
+        else {
+            std::fs::remove_file(&path)?;
+        }
+    }
+    installer.platform.notify(
+        "backups_kept",
+        &t!("gui.backups_kept", dir = store.dir().display()),
+        &t!("gui.uninstall"),
+        Level::Info
+    );
+    Ok(())
+}
+
 extern "system" fn dlg_proc(dialog: HWND, message: u32, wparam: WPARAM, lparam: LPARAM) -> isize {
     match message {
         WM_INITDIALOG => {
@@ -179,12 +282,10 @@ extern "system" fn dlg_proc(dialog: HWND, message: u32, wparam: WPARAM, lparam:
             }
             #[cfg(feature = "net_install")]
             {
-                let version_str = installer.hachimi_version.lock().unwrap()
-                    .as_deref()
-                    .unwrap_or("Error")
-                    .to_string();
-                let packaged_ver_static = GetDlgItem(dialog, IDC_PACKAGED_VER).unwrap();
-                _ = SetWindowTextW(packaged_ver_static, &HSTRING::from(t!("gui.packaged_ver", version_str)));
+                unsafe {
+                    let packaged_ver_static = GetDlgItem(dialog, IDC_PACKAGED_VER).unwrap();
+                    _ = SetWindowTextW(packaged_ver_static, &HSTRING::from(packaged_ver_label(installer)));
+                }
             }
 
             localize_controls(dialog);
@@ -210,10 +311,10 @@ extern "system" fn dlg_proc(dialog: HWND, message: u32, wparam: WPARAM, lparam:
             unsafe {
                 let target_combo = GetDlgItem(dialog, IDC_TARGET).unwrap();
                 let detected_targets = [
-                    installer::detect_dmm_install_dir().is_some(),
+                    installer::detect_dmm_install_dir(&installer.platform).is_some(),
                     installer::detect_steam_install_dir(installer::JP_STEAM_ID).is_some(),
                     installer::detect_steam_install_dir(installer::GLOBAL_STEAM_ID).is_some(),
-                    installer::detect_komoe_install_dir().is_some(),
+                    installer::detect_komoe_install_dir(&installer.platform).is_some(),
                 ];
                 let installed_count = detected_targets.iter().filter(|&&x| x).count();
 
@@ -224,7 +325,7 @@ extern "system" fn dlg_proc(dialog: HWND, message: u32, wparam: WPARAM, lparam:
                     );
                 }
 
-                let default_target_enum = installer::Target::default();
+                let default_target_enum = installer::Target::detect(&installer.platform);
                 let default_target_idx = installer::Target::VALUES.iter().position(|&t| t == default_target_enum).unwrap_or(0);
 
                 update_target(dialog, target_combo, default_target_idx);
@@ -236,14 +337,15 @@ extern "system" fn dlg_proc(dialog: HWND, message: u32, wparam: WPARAM, lparam:
 
             // Show notice if install dir is not detected
             if installer.install_dir.is_none() {
-                unsafe {
-                    MessageBoxW(
-                        dialog,
-                        &HSTRING::from(t!("gui.warning_no_dir")),
-                        &HSTRING::from(t!("gui.warning")),
-                        MB_ICONWARNING | MB_OK
-                    );
-                }
+                installer.platform.notify(
+                    "no_install_dir", &t!("gui.warning_no_dir"), &t!("gui.warning"), Level::Warning
+                );
+            }
+
+            // Pick up after an install/uninstall that didn't get to finish; with a download
+            // that's only once it's there, so it waits for the first click on Install/Uninstall
+            if installer.install_dir.is_some() && !cfg!(feature = "net_install") {
+                recover_interrupted(dialog);
             }
 
             // Show notice for multiple installs
@@ -278,7 +380,7 @@ extern "system" fn dlg_proc(dialog: HWND, message: u32, wparam: WPARAM, lparam:
                 }
                 IDC_INSTALL_PATH_BROWSE => {
                     let installer = get_installer(dialog);
-                    let Some(path) = utils::open_select_folder_dialog(
+                    let Some(path) = dialog::open_select_folder_dialog(
                         dialog,
                         installer.install_dir.as_ref().filter(|p| p.is_dir())
                     ) else {
@@ -311,84 +413,121 @@ extern "system" fn dlg_proc(dialog: HWND, message: u32, wparam: WPARAM, lparam:
 
                 IDC_INSTALL => {
                     let installer = get_installer(dialog);
+                    #[cfg(feature = "net_install")]
+                    if !check_download(dialog) {
+                        return 0;
+                    }
+                    if !recover_interrupted(dialog) {
+                        return 0;
+                    }
                     if let Some(target) = installer.get_hachimi_installed_target() {
                         if target != installer.target {
-                            unsafe {
-                                MessageBoxW(
-                                    dialog,
-                                    &HSTRING::from(t!("gui.already_installed", dll = target.dll_name())),
-                                    &HSTRING::from(t!("gui.error")),
-
-                                    MB_ICONERROR | MB_OK
-                                );
-                            }
+                            installer.platform.notify(
+                                "already_installed",
+                                &t!("gui.already_installed", dll = target.dll_name()),
+                                &t!("gui.error"),
+                                Level::Error
+                            );
                             return 0;
                         }
                     }
                     if installer.is_current_target_installed() {
-                        let res = unsafe { MessageBoxW(
-                            dialog,
-                            &HSTRING::from(t!("gui.replace_confirm", dll = installer.target.dll_name())),
-                            &HSTRING::from(t!("gui.install")),
-                            MB_ICONINFORMATION | MB_OKCANCEL
-                        ) };
-                        if res != IDOK {
+                        let res = installer.platform.prompt.ask(
+                            "replace_confirm",
+                            &t!("gui.replace_confirm", dll = installer.target.dll_name()),
+                            &t!("gui.install"),
+                            Level::Info,
+                            Buttons::OkCancel
+                        );
+                        if res != Answer::Yes {
                             return 0;
                         }
                     }
-                    // failed pre install no longer catastrophic, just warn
-                    if installer.pre_install().is_err() {
-                        unsafe {
-                            MessageBoxW(
-                                dialog,
-                                &HSTRING::from(t!("gui.warning_no_backup")),
-                                &HSTRING::from(t!("gui.warning")),
-                                MB_ICONWARNING | MB_OK
+                    // let the user decide whether to try an unknown game build anyway
+                    installer.force = false;
+                    if let Some(Ok(info)) = installer.get_orig_exe_path().map(|p| GameExeInfo::read(&p)) {
+                        if !info.is_supported() {
+                            let res = installer.platform.prompt.ask(
+                                "unsupported_build",
+                                &t!("gui.unsupported_build_confirm", build = info.label()),
+                                &t!("gui.warning"),
+                                Level::Warning,
+                                Buttons::YesNo
                             );
+                            if res != Answer::Yes {
+                                return 0;
+                            }
+                            installer.force = true;
                         }
                     }
-                    match installer.install()
-                        .and_then(|_| installer.post_install())
-                    {
-                        Ok(_) => unsafe {
-                            MessageBoxW(dialog, &HSTRING::from(t!("gui.msg_install_ok")),  
-                                              &HSTRING::from(t!("gui.title")), MB_ICONINFORMATION | MB_OK);
-                        },
-                        Err(e) => unsafe {
-                            MessageBoxW(dialog, &HSTRING::from(t!("gui.msg_install_fail", err = e.to_string())),
-                                              &HSTRING::from(t!("gui.title")), MB_ICONERROR | MB_OK);
+                    installer.allow_downgrade = false;
+                    match installer.confirm_downgrade() {
+                        Ok(true) => {},
+                        Ok(false) => return 0,
+                        Err(e) => {
+                            installer.platform.notify("error", &e.to_string(), &t!("gui.error"), Level::Error);
+                            return 0;
                         }
                     }
+                    // same plan --dry-run shows, a failed backup stops the install like it does there
+                    let res = installer.plan(Operation::Install { pre_install: true, post_install: true })
+                        .and_then(|plan| installer.run_plan(&plan));
+                    match res {
+                        Ok(_) => installer.platform.notify(
+                            "install_ok", &t!("gui.msg_install_ok"), &t!("gui.title"), Level::Info
+                        ),
+                        Err(e) => installer.platform.notify(
+                            "error", &t!("gui.msg_install_fail", err = e.to_string()), &t!("gui.title"), Level::Error
+                        )
+                    }
                     update_target(dialog, unsafe { GetDlgItem(dialog, IDC_TARGET).unwrap() }, installer.target as _);
                 }
 
                 IDC_UNINSTALL => {
                     let installer = get_installer(dialog);
-                    let res = unsafe { MessageBoxW(
-                        dialog,
-                        &HSTRING::from(t!("gui.delete_confirm", dll = installer.target.dll_name())),
-                        &HSTRING::from(t!("gui.uninstall")),
-                        MB_ICONINFORMATION | MB_OKCANCEL
-                    ) };
-                    if res == IDOK {
+                    if !recover_interrupted(dialog) {
+                        return 0;
+                    }
+                    let res = installer.platform.prompt.ask(
+                        "delete_confirm",
+                        &t!("gui.delete_confirm", dll = installer.target.dll_name()),
+                        &t!("gui.uninstall"),
+                        Level::Info,
+                        Buttons::OkCancel
+                    );
+                    if res == Answer::Yes {
                         let version_info_opt = installer.get_target_version_info(installer.target);
-                        if let Err(e) = installer.uninstall() {
+                        installer.force = false;
+                        let mut result = installer.run(Operation::Uninstall);
+                        // the game was updated since the backup was made, or the file isn't ours,
+                        // let the user decide instead of guessing
+                        let confirm = match &result {
+                            Err(installer::Error::StaleBackup(..)) => Some(("stale_backup", t!("gui.stale_backup_confirm"))),
+                            Err(installer::Error::NotHachimi(_)) => Some(("not_hachimi", t!("gui.not_hachimi_confirm"))),
+                            _ => None
+                        };
+                        if let (Some((id, confirm)), Err(e)) = (confirm, &result) {
+                            let res = installer.platform.prompt.ask(
+                                id,
+                                &format!("{}\n\n{}", e, confirm),
+                                &t!("gui.warning"),
+                                Level::Warning,
+                                Buttons::YesNo
+                            );
+                            if res != Answer::Yes {
+                                return 0;
+                            }
+                            installer.force = true;
+                            result = installer.run(Operation::Uninstall);
+                            installer.force = false;
+                        }
+                        if let Err(e) = result {
                             // fall through but clarify danger
                             // only interrupt if error is not FailedToRestore
                             if matches!(e, installer::Error::FailedToRestore) {
-                                unsafe { MessageBoxW(
-                                    dialog,
-                                    &HSTRING::from(e.to_string()), 
-                                    &HSTRING::from(t!("gui.warning")), 
-                                    MB_ICONWARNING | MB_OK
-                                ) };
+                                installer.platform.notify("restore_failed", &e.to_string(), &t!("gui.warning"), Level::Warning);
                             } else {
-                                unsafe { MessageBoxW(
-                                    dialog, 
-                                    &HSTRING::from(e.to_string()), 
-                                    &HSTRING::from(t!("gui.error")), 
-                                    MB_ICONERROR | MB_OK
-                                ) };
+                                installer.platform.notify("error", &e.to_string(), &t!("gui.error"), Level::Error);
                                 return 0;
                             };
                         }
@@ -406,22 +545,17 @@ extern "system" fn dlg_proc(dialog: HWND, message: u32, wparam: WPARAM, lparam:
                             };
 
                             if metadata.is_dir() {
-                                let res = unsafe { MessageBoxW(
-                                    dialog,
-                                    &HSTRING::from(t!("gui.delete_data_dir")),
-                                    &HSTRING::from(t!("gui.uninstall")),
-
-                                    MB_ICONINFORMATION | MB_YESNO
-                                ) };
-
-                                if res == IDYES {
-                                    if let Err(e) = std::fs::remove_dir_all(&hachimi_dir) {
-                                        unsafe { MessageBoxW(
-                                            dialog, 
-                                            &HSTRING::from(e.to_string()), 
-                                            &HSTRING::from(t!("gui.error")), 
-                                            MB_ICONERROR | MB_OK
-                                        ) };
+                                let res = installer.platform.prompt.ask(
+                                    "delete_data_dir",
+                                    &t!("gui.delete_data_dir"),
+                                    &t!("gui.uninstall"),
+                                    Level::Info,
+                                    Buttons::YesNo
+                                );
+
+                                if res == Answer::Yes {
+                                    if let Err(e) = delete_data_dir(installer, &hachimi_dir) {
+                                        installer.platform.notify("error", &e.to_string(), &t!("gui.error"), Level::Error);
                                         return 0;
                                     }
                                 }
@@ -1,6 +1,9 @@
 use once_cell::sync::Lazy;
+#[cfg(windows)]
 use windows::Win32::Globalization::GetUserDefaultLocaleName;
+#[cfg(windows)]
 use std::ffi::OsString;
+#[cfg(windows)]
 use std::os::windows::ffi::OsStringExt;
 
 rust_i18n::i18n!("locales");
@@ -26,18 +29,7 @@ pub fn init_locale() {
         set_locale(&lang); return;
     }
 
-    let mut buf = [0u16; 85];
-    let len = unsafe { GetUserDefaultLocaleName(&mut buf) } as usize; // 返回包含 '\0'
-    let win_locale = if len > 1 {
-        OsString::from_wide(&buf[..len - 1]).to_string_lossy().into_owned()
-    } else {
-        String::new()
-    };
-    let _win_locale = if len > 0 {
-        OsString::from_wide(&buf[..len as usize - 1]).to_string_lossy().into_owned()
-    } else {
-        String::new()
-    };
+    let win_locale = system_locale();
 
     let code = SUPPORTED_LOCALES
         .iter()
@@ -46,4 +38,21 @@ pub fn init_locale() {
         .unwrap_or("en-US");
 
     set_locale(code);
+}
+
+#[cfg(windows)]
+fn system_locale() -> String {
+    let mut buf = [0u16; 85];
+    let len = unsafe { GetUserDefaultLocaleName(&mut buf) } as usize; // 返回包含 '\0'
+    if len > 1 {
+        OsString::from_wide(&buf[..len - 1]).to_string_lossy().into_owned()
+    } else {
+        String::new()
+    }
+}
+
+// e.g. zh_CN.UTF-8
+#[cfg(not(windows))]
+fn system_locale() -> String {
+    std::env::var("LANG").unwrap_or_default().split('.').next().unwrap_or_default().replace('_', "-")
 }
\ No newline at end of file
@@ -1,117 +1,115 @@
-use std::{env, fs::File, io::Write, path::{Path, PathBuf}};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+#[cfg(feature = "net_install")]
 use std::sync::{Arc, Mutex};
 use pelite::resources::version_info::Language;
-use registry::Hive;
 use tinyjson::JsonValue;
 use crate::i18n::t;
-use windows::{core::HSTRING, Win32::{Foundation::HWND, UI::{Shell::{FOLDERID_RoamingAppData, SHGetKnownFolderPath, KF_FLAG_DEFAULT, ShellExecuteW}, WindowsAndMessaging::{MessageBoxW, IDOK, MB_ICONERROR, MB_ICONINFORMATION, MB_OK, MB_OKCANCEL, SW_SHOWNORMAL}}}};
-#[cfg(feature = "net_install")]
-use bytes::Bytes;
 use steamlocate::SteamDir;
-use bsdiff;
 use crate::utils::{self};
+use crate::journal::{Operation, Transaction};
+use crate::receipt::{Receipt, RECEIPT_FILE};
+use crate::plan::{Plan, Source, Step};
+use crate::game::{ExeState, GameExeInfo};
+use crate::backup::{BackupEntry, BackupStore};
+use crate::platform::{Answer, Buttons, KnownFolder, Level, Platform, RegistryError};
+use crate::verify::{self, FileKind, FileState, VerifyReport};
+use crate::version::Version;
+#[cfg(feature = "net_install")]
+use crate::release::{self, Downloaded, GitHubSource, IntegrityError, Release, ReleaseSource, Sidecar};
+#[cfg(feature = "net_install")]
+use crate::{cache::DownloadCache, download::Downloader};
 
 #[cfg(feature = "net_install")]
-type DownloadResult = Result<Bytes, reqwest::Error>;
+type DownloadResult = Result<Downloaded, Error>;
 
 pub const GLOBAL_STEAM_ID: u32 = 3224770;
 pub const JP_STEAM_ID: u32 = 3564400;
 
-const KOMOE_REG_KEY: &str = r"Software\komoemumamusume";
-const DEVOVERRIDE_KEY: &str = r"SOFTWARE\Microsoft\Windows NT\CurrentVersion\Image File Execution Options";
+pub(crate) const KOMOE_REG_KEY: &str = r"HKCU\Software\komoemumamusume";
+pub(crate) const DEVOVERRIDE_REG_PATH: &str = r"HKLM\SOFTWARE\Microsoft\Windows NT\CurrentVersion\Image File Execution Options";
+pub(crate) const DEVOVERRIDE_VALUE: &str = "DevOverrideEnable";
 
 // separate out read check cus it doesnt require admin privileges
-pub fn is_dotlocal_enabled() -> bool {
-    match Hive::LocalMachine.open(DEVOVERRIDE_KEY, registry::Security::Read) {
-        Ok(regkey) => {
-            regkey.value("DevOverrideEnable")
-                .ok()
-                .map(|v| match v {
-                    registry::Data::U32(v) => v != 0,
-                    _ => false
-                })
-                .unwrap_or(false)
-        },
-        Err(_) => false
-    }
+pub fn is_dotlocal_enabled(platform: &Platform) -> bool {
+    get_dotlocal_value(platform).is_some_and(|v| v != 0)
+}
+
+fn get_dotlocal_value(platform: &Platform) -> Option<u32> {
+    platform.registry.get_u32(DEVOVERRIDE_REG_PATH, DEVOVERRIDE_VALUE)
 }
 
 // enable dotlocal in registry, run as admin required
-pub fn enable_dotlocal() {
-    match Hive::LocalMachine.open(DEVOVERRIDE_KEY, registry::Security::Read | registry::Security::SetValue) {
-        Ok(regkey) => {
-            match regkey.set_value("DevOverrideEnable", &registry::Data::U32(1)) {
-                Ok(_) => {
-                    unsafe {
-                        MessageBoxW(
-                            None,
-                            &HSTRING::from(t!("installer.restart_to_apply")),
-                            &HSTRING::from(t!("installer.dll_redirection_enabled")),
-                            MB_ICONINFORMATION | MB_OK
-                        );
-                    }
-                },
-                Err(e) => {
-                    unsafe {
-                        MessageBoxW(
-                            None,
-                            &HSTRING::from(t!("installer.failed_enable_dotlocal", error = e)),
-                            &HSTRING::from(t!("installer.warning")),
-                            MB_ICONERROR | MB_OK
-                        );
-                    }
-                }
-            }
-        },
-        Err(e) => {
-            unsafe {
-                MessageBoxW(
-                    None,
-                    &HSTRING::from(t!("installer.failed_open_ifeo", error = e)),
-                    &HSTRING::from(t!("installer.warning")),
-                    MB_ICONERROR | MB_OK
-                );
-            }
-        }
+pub fn enable_dotlocal(platform: &Platform) {
+    match platform.registry.set_u32(DEVOVERRIDE_REG_PATH, DEVOVERRIDE_VALUE, 1) {
+        Ok(_) => platform.notify(
+            "dotlocal_enabled",
+            &t!("installer.restart_to_apply"),
+            &t!("installer.dll_redirection_enabled"),
+            Level::Info
+        ),
+        Err(RegistryError::OpenKey(e)) => platform.notify(
+            "dotlocal_enable_failed",
+            &t!("installer.failed_open_ifeo", error = e),
+            &t!("installer.warning"),
+            Level::Error
+        ),
+        Err(RegistryError::Value(e)) => platform.notify(
+            "dotlocal_enable_failed",
+            &t!("installer.failed_enable_dotlocal", error = e),
+            &t!("installer.warning"),
+            Level::Error
+        )
     }
 }
 
-fn request_dotlocal_elevation(hwnd: Option<&HWND>) -> bool {
-    let exe_path = env::current_exe().ok();
-    let Some(exe_path) = exe_path else {
-        return false;
-    };
+// disable dotlocal again on uninstall, run as admin required
+pub fn disable_dotlocal(platform: &Platform) {
+    restore_dotlocal(platform, None);
+}
 
-    let result = unsafe {
-        ShellExecuteW(
-            hwnd.map(|h| *h).unwrap_or_default(),
-            &HSTRING::from("runas"),
-            &HSTRING::from(exe_path.to_string_lossy().as_ref()),
-            &HSTRING::from("--enable-dotlocal"),
-            None,
-            SW_SHOWNORMAL
-        )
+// put back the value from before the install, None deletes it, run as admin required
+pub fn restore_dotlocal(platform: &Platform, previous: Option<u32>) {
+    let res = match previous {
+        Some(data) => platform.registry.set_u32(DEVOVERRIDE_REG_PATH, DEVOVERRIDE_VALUE, data),
+        None => platform.registry.delete_value(DEVOVERRIDE_REG_PATH, DEVOVERRIDE_VALUE)
     };
-
-    // ShellExecuteW returns a value > 32 on success
-    result.0 as usize > 32
+    let msg = match res {
+        Ok(_) => return,
+        Err(RegistryError::OpenKey(e)) => t!("installer.failed_open_ifeo", error = e),
+        Err(RegistryError::Value(e)) => t!("installer.failed_disable_dotlocal", error = e)
+    };
+    platform.notify("dotlocal_disable_failed", &msg, &t!("installer.warning"), Level::Error);
 }
 
 pub struct Installer {
     pub install_dir: Option<PathBuf>,
     pub target: Target,
     pub custom_target: Option<String>,
-    pub hwnd: Arc<Mutex<Option<HWND>>>,
+    // install even if the game build isn't known to work
+    pub force: bool,
+    // install over a newer Hachimi, --force implies it
+    pub allow_downgrade: bool,
+    // not Send, the Windows prompts hold on to the dialog's HWND
+    pub platform: Rc<Platform>,
+    #[cfg(feature = "net_install")]
+    pub release_source: Arc<dyn ReleaseSource>,
+    #[cfg(feature = "net_install")]
+    pub downloader: Arc<Downloader>,
+    // None if there's nowhere to keep it
+    #[cfg(feature = "net_install")]
+    pub cache: Option<DownloadCache>,
+    // kept around so a failed download can be retried
+    #[cfg(feature = "net_install")]
+    pub release: Arc<Mutex<Option<Release>>>,
     #[cfg(feature = "net_install")]
     pub hachimi_dll: Arc<Mutex<Option<DownloadResult>>>,
     #[cfg(feature = "net_install")]
     pub hachimi_version: Arc<Mutex<Option<String>>>
 }
 
-pub fn detect_dmm_install_dir() -> Option<PathBuf> {
-    let app_data_dir_wstr = unsafe { SHGetKnownFolderPath(&FOLDERID_RoamingAppData, KF_FLAG_DEFAULT, None).ok()? };
-    let app_data_dir_str = unsafe { app_data_dir_wstr.to_string().ok()? };
-    let app_data_dir = Path::new(&app_data_dir_str);
+pub fn detect_dmm_install_dir(platform: &Platform) -> Option<PathBuf> {
+    let app_data_dir = platform.known_folders.get(KnownFolder::RoamingAppData)?;
     let mut dmm_config_path = app_data_dir.join("dmmgameplayer5");
     dmm_config_path.push("dmmgame.cnf");
 
@@ -153,17 +151,8 @@ pub fn detect_dmm_install_dir() -> Option<PathBuf> {
     None
 }
 
-pub fn detect_komoe_install_dir() -> Option<PathBuf> {
-    match Hive::CurrentUser.open(KOMOE_REG_KEY, registry::Security::Read) {
-        Ok(regkey) => {
-            match regkey.value("GameInstallPath") {
-                // REG_SZ
-                Ok(registry::Data::String(v)) => Some(PathBuf::from(v.to_os_string())),
-                _ => None
-            }
-        },
-        Err(_) => None
-    }
+pub fn detect_komoe_install_dir(platform: &Platform) -> Option<PathBuf> {
+    platform.registry.get_string(KOMOE_REG_KEY, "GameInstallPath").map(PathBuf::from)
 }
 
 pub fn detect_steam_install_dir(app_id: u32) -> Option<PathBuf> {
@@ -176,33 +165,65 @@ pub fn detect_steam_install_dir(app_id: u32) -> Option<PathBuf> {
     None
 }
 
-pub fn detect_target_from_path(path: &Path) -> Option<Target> {
-    if path.join("umamusume.exe").exists() {
-        return Some(Target::UnityPlayer);
-    }
+// buildid from the app manifest, changes with every game update
+pub fn get_steam_build_id(app_id: u32) -> Option<(PathBuf, u64)> {
+    let steam_dir = SteamDir::locate().ok()?;
+    let (app, lib) = steam_dir.find_app(app_id).ok()??;
+    Some((lib.resolve_app_dir(&app), app.build_id?))
+}
 
-    if path.join("UmamusumePrettyDerby_Jpn.exe").exists() {
-        return Some(Target::CriManaVpx);
-    }
+pub fn detect_target_from_path(path: &Path) -> Option<Target> {
+    Target::VALUES.iter().find(|t| path.join(t.exe_name()).exists()).copied()
+}
 
-    if path.join("UmamusumePrettyDerby.exe").exists() {
-        return Some(Target::CriManaVpxGlobal);
-    }
+impl Installer {
+    // Whatever install is detected first
+    pub fn new(platform: Rc<Platform>) -> Installer {
+        let install_dir = Target::VALUES.iter()
+            .find_map(|t| Self::detect_install_dir(&platform, *t));
+        #[cfg(feature = "net_install")]
+        let cache = DownloadCache::default_dir(&platform).map(|dir| DownloadCache::new(&dir));
 
-    if path.join("komoeumamusume.exe").exists() {
-        return Some(Target::CriManaVpxKomoe);
+        Installer {
+            install_dir,
+            target: Target::detect(&platform),
+            custom_target: None,
+            force: false,
+            allow_downgrade: false,
+            platform,
+            #[cfg(feature = "net_install")]
+            release_source: Arc::new(GitHubSource::default()),
+            #[cfg(feature = "net_install")]
+            downloader: Arc::new(Downloader::default()),
+            #[cfg(feature = "net_install")]
+            cache,
+            #[cfg(feature = "net_install")]
+            release: Arc::new(Mutex::new(None)),
+            #[cfg(feature = "net_install")]
+            hachimi_dll: Arc::new(Mutex::new(None)),
+            #[cfg(feature = "net_install")]
+            hachimi_version: Arc::new(Mutex::new(None))
+        }
     }
 
-    None
-}
-
-impl Installer {
-    pub fn custom(install_dir: Option<PathBuf>, target: Target, custom_target: Option<String>) -> Installer {
+    pub fn custom(platform: Rc<Platform>, install_dir: Option<PathBuf>, target: Target, custom_target: Option<String>) -> Installer {
+        #[cfg(feature = "net_install")]
+        let cache = DownloadCache::default_dir(&platform).map(|dir| DownloadCache::new(&dir));
         Installer {
-            install_dir: install_dir.or_else(|| Self::detect_install_dir(target)),
+            install_dir: install_dir.or_else(|| Self::detect_install_dir(&platform, target)),
             target,
             custom_target,
-            hwnd: Arc::new(Mutex::new(None)),
+            force: false,
+            allow_downgrade: false,
+            platform,
+            #[cfg(feature = "net_install")]
+            release_source: Arc::new(GitHubSource::default()),
+            #[cfg(feature = "net_install")]
+            downloader: Arc::new(Downloader::default()),
+            #[cfg(feature = "net_install")]
+            cache,
+            #[cfg(feature = "net_install")]
+            release: Arc::new(Mutex::new(None)),
             #[cfg(feature = "net_install")]
             hachimi_dll: Arc::new(Mutex::new(None)),
             #[cfg(feature = "net_install")]
@@ -210,12 +231,12 @@ impl Installer {
         }
     }
 
-    pub fn detect_install_dir(target: Target) -> Option<PathBuf> {
+    pub fn detect_install_dir(platform: &Platform, target: Target) -> Option<PathBuf> {
         match target {
-            Target::UnityPlayer => detect_dmm_install_dir(),
+            Target::UnityPlayer => detect_dmm_install_dir(platform),
             Target::CriManaVpx => detect_steam_install_dir(JP_STEAM_ID),
             Target::CriManaVpxGlobal => detect_steam_install_dir(GLOBAL_STEAM_ID),
-            Target::CriManaVpxKomoe => detect_komoe_install_dir(),
+            Target::CriManaVpxKomoe => detect_komoe_install_dir(platform),
         }
     }
 
@@ -241,10 +262,13 @@ impl Installer {
         })
     }
 
-    const LANG_NEUTRAL_UNICODE: Language = Language { lang_id: 0x0000, charset_id: 0x04b0 };
+    pub const LANG_NEUTRAL_UNICODE: Language = Language { lang_id: 0x0000, charset_id: 0x04b0 };
     pub fn get_target_version_info(&self, target: Target) -> Option<TargetVersionInfo> {
-        let path = self.get_target_path(target)?;
-        let map = pelite::FileMap::open(&path).ok()?;
+        Self::read_target_version_info(&self.get_target_path(target)?)
+    }
+
+    pub fn read_target_version_info(path: &Path) -> Option<TargetVersionInfo> {
+        let map = pelite::FileMap::open(path).ok()?;
 
         // File exists, so return empty version info if we can't read it
         let Some(version_info) = utils::read_pe_version_info(map.as_ref()) else {
@@ -301,42 +325,548 @@ impl Installer {
         None
     }
 
-    pub fn pre_install(&self) -> Result<(), Error> {
-        match self.target {
-            Target::CriManaVpx => {
-                //something exe idk
-                let orig_exe = self.get_orig_exe_path().ok_or(Error::NoInstallDir)?;
-                let backup_exe = self.get_backup_exe_path().ok_or(Error::NoInstallDir)?;
+    pub fn get_data_dir(&self) -> Option<PathBuf> {
+        let install_dir = self.install_dir.as_ref()?;
+        // an absolute --target ends up as the install dir, keep our data next to it
+        let base = if install_dir.is_file() { install_dir.parent()? } else { install_dir.as_path() };
+        Some(base.join("hachimi"))
+    }
+
+    pub fn begin(&self, operation: Operation) -> Result<Transaction, Error> {
+        let install_dir = self.install_dir.clone().ok_or(Error::NoInstallDir)?;
+        let data_dir = self.get_data_dir().ok_or(Error::NoInstallDir)?;
+        if Transaction::exists(&data_dir) {
+            return Err(Error::TransactionPending);
+        }
+
+        Transaction::begin(&data_dir, install_dir, operation, self.target, self.custom_target.clone())
+    }
+
+    pub fn run(&self, operation: Operation) -> Result<(), Error> {
+        let plan = self.plan(operation)?;
+        self.run_plan(&plan)
+    }
+
+    pub fn run_plan(&self, plan: &Plan) -> Result<(), Error> {
+        let mut tx = self.begin(plan.operation)?;
+        let result = self.execute_plan(plan, &mut tx);
+        self.finish(result, tx)
+    }
 
-                // back up exe if not existing, don't overwrite if it's already there
-                if !backup_exe.exists() {
-                    std::fs::copy(&orig_exe, &backup_exe)?;
+    pub fn plan(&self, operation: Operation) -> Result<Plan, Error> {
+        let mut plan = Plan::new(operation, self.target, self.install_dir.clone());
+        match operation {
+            Operation::Install { pre_install, post_install } => {
+                if let Some(info) = self.get_game_exe_info()? {
+                    plan.notes.push(info.describe());
+                    if !info.is_supported() && !self.force {
+                        return Err(Error::UnsupportedGameBuild(info.label()));
+                    }
+                }
+
+                if pre_install {
+                    match self.pre_install(&mut plan) {
+                        // --force still installs on an unknown build, just without a backup
+                        Err(Error::UnknownBackupSource(_)) if self.force => plan.notes.push(t!("game.exe_not_backed_up")),
+                        res => res?
+                    }
+                }
+                self.install(&mut plan)?;
+                if post_install {
+                    self.post_install(&mut plan)?;
+                }
+
+                let data_dir = self.get_data_dir().ok_or(Error::NoInstallDir)?;
+                plan.push(Step::WriteReceipt {
+                    dest: data_dir.join(RECEIPT_FILE),
+                    payload_version: self.get_payload_version()
+                });
+            },
+            Operation::Uninstall => self.uninstall(&mut plan)?,
+            // which backup isn't known here, see plan_restore
+            Operation::Restore => {},
+            Operation::Prune => self.prune(&mut plan)?,
+            Operation::Repair | Operation::Ensure { .. } => {
+                match operation {
+                    Operation::Ensure { update } => self.ensure(&mut plan, update)?,
+                    _ => self.repair(&mut plan)?
+                }
+                if !plan.steps.is_empty() {
+                    let data_dir = self.get_data_dir().ok_or(Error::NoInstallDir)?;
+                    plan.push(Step::WriteReceipt {
+                        dest: data_dir.join(RECEIPT_FILE),
+                        payload_version: self.get_payload_version()
+                    });
                 }
             }
-            _ => {}
+        }
+
+        Ok(plan)
+    }
+
+    pub fn execute_plan(&self, plan: &Plan, tx: &mut Transaction) -> Result<(), Error> {
+        let mut restored = true;
+
+        for step in &plan.steps {
+            match step {
+                Step::CreateDir { path } => {
+                    tx.create_dir_all(path)?;
+                    tx.receipt.add_dir(path);
+                },
+                Step::WriteFile { source, dest, .. } => {
+                    tx.write_file(dest, &source.bytes)?;
+                    tx.receipt.add_file(dest, &source.bytes);
+                },
+                Step::KeepFile { path, sha256 } => tx.receipt.add_file_sha256(path, sha256.clone()),
+                Step::Backup { source, backup, exists } => {
+                    // back up if not existing, don't overwrite if it's already there
+                    if !exists {
+                        let store = self.get_backup_store().ok_or(Error::NoInstallDir)?;
+                        tx.create_dir_all(backup.dir())?;
+                        tx.copy_file(source, &backup.file)?;
+                        if utils::sha256_file(&backup.file)? != backup.sha256 {
+                            return Err(Error::FileChanged(source.clone()));
+                        }
+                        tx.write_file(&backup.meta_path(), store.meta_json(backup).as_bytes())?;
+                    }
+                    tx.receipt.add_backup(&backup.path, &backup.file, backup.sha256.clone());
+                },
+                Step::PatchExe { source, dest, source_sha256, patch, sha256 } => {
+                    let exe_bytes = std::fs::read(source)?;
+                    if utils::sha256_hex(&exe_bytes) != *source_sha256 {
+                        return Err(Error::FileChanged(source.clone()));
+                    }
+
+                    let mut patched_bytes = Vec::new();
+                    bsdiff::patch(&exe_bytes, &mut &patch[..], &mut patched_bytes)?;
+                    if utils::sha256_hex(&patched_bytes) != *sha256 {
+                        return Err(Error::FileChanged(dest.clone()));
+                    }
+
+                    // Write tmpfile before overwriting shim EXE
+                    // atomic replace so game dont break if patch fails
+                    let tmp_path = dest.with_extension("exe.tmp");
+                    tx.write_file(&tmp_path, &patched_bytes)?;
+                    tx.rename(&tmp_path, dest)?;
+                    tx.receipt.add_file(dest, &patched_bytes);
+                },
+                // already gone is as good as removed, e.g. when running again after an interruption
+                Step::Remove { path } => {
+                    if path.is_file() {
+                        tx.remove_file(path)?;
+                    }
+                },
+                Step::RemoveDir { path, only_if_empty } => {
+                    if *only_if_empty {
+                        tx.remove_empty_dir(path)?;
+                    }
+                    else if path.is_dir() {
+                        tx.remove_dir_all(path)?;
+                    }
+                },
+                Step::Restore { backup, dest, sha256, keep_backup } => {
+                    // keep going, everything else should still be undone
+                    if backup.is_file() {
+                        // never put back something other than what was backed up
+                        if sha256.as_ref().is_some_and(|h| utils::sha256_file(backup).ok().as_ref() != Some(h)) {
+                            return Err(Error::FileChanged(backup.clone()));
+                        }
+                        if *keep_backup {
+                            tx.copy_file(backup, dest)?;
+                        }
+                        else {
+                            tx.rename(backup, dest)?;
+                        }
+                    }
+                    else {
+                        restored = false;
+                    }
+                },
+                Step::SetRegistryValue { key, value, data } => self.set_registry_value(tx, key, value, *data),
+                Step::WriteReceipt { dest, payload_version } => self.write_receipt(tx, dest, payload_version.clone())?
+            }
+        }
+
+        if restored { Ok(()) } else { Err(Error::FailedToRestore) }
+    }
+
+    pub fn finish(&self, result: Result<(), Error>, tx: Transaction) -> Result<(), Error> {
+        match result {
+            Ok(_) => tx.commit(),
+            // a missing backup shouldn't bring back the files that were already removed
+            Err(Error::FailedToRestore) => {
+                tx.commit()?;
+                Err(Error::FailedToRestore)
+            },
+            Err(e) => {
+                // if the rollback fails too the journal stays around for the next start
+                tx.rollback()?;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn get_receipt(&self) -> Result<Option<Receipt>, Error> {
+        let Some(data_dir) = self.get_data_dir() else {
+            return Ok(None);
         };
+        Receipt::load(&data_dir)
+    }
+
+    fn write_receipt(&self, tx: &mut Transaction, dest: &Path, payload_version: Option<String>) -> Result<(), Error> {
+        let data_dir = self.get_data_dir().ok_or(Error::NoInstallDir)?;
+
+        let mut newer = Receipt::new(self.target, self.custom_target.clone());
+        std::mem::swap(&mut newer, &mut tx.receipt);
+        newer.payload_version = payload_version;
+
+        let receipt = match Receipt::load(&data_dir) {
+            Ok(Some(mut receipt)) => {
+                receipt.merge(newer);
+                receipt
+            },
+            // unreadable receipt, ours is the best we've got
+            Ok(None) | Err(Error::InvalidReceipt) => newer,
+            Err(e) => return Err(e)
+        };
+
+        let receipt_json = receipt.to_json(&data_dir)?;
+        tx.write_file(dest, receipt_json.as_bytes())
+    }
 
+    // DevOverrideEnable is the only value we touch, and it needs elevation either way
+    fn set_registry_value(&self, tx: &mut Transaction, key: &str, value: &str, data: Option<u32>) {
+        if key != DEVOVERRIDE_REG_PATH || value != DEVOVERRIDE_VALUE {
+            return;
+        }
+
+        let platform = &self.platform;
+        // uninstall puts back whatever the receipt says was there before
+        if tx.operation == Operation::Uninstall {
+            let res = platform.prompt.ask(
+                "dotlocal_revert", &t!("installer.dotlocal_revert"), &t!("installer.uninstall"), Level::Info, Buttons::YesNo
+            );
+            if res == Answer::Yes {
+                platform.elevation.run_elevated(&match data {
+                    Some(data) => format!("--restore-dotlocal {}", data),
+                    None => "--disable-dotlocal".to_owned()
+                });
+            }
+        }
+        else {
+            let res = platform.prompt.ask(
+                "dotlocal_enable", &t!("installer.dotlocal_not_enabled"), &t!("installer.install"), Level::Info, Buttons::OkCancel
+            );
+            if res == Answer::Yes {
+                // Request elevation to enable DotLocal
+                let previous = get_dotlocal_value(platform);
+                platform.elevation.run_elevated("--enable-dotlocal");
+                // the elevated instance can fail or be declined, only a value that actually changed is ours to undo
+                if is_dotlocal_enabled(platform) {
+                    tx.receipt.add_registry_change(key, value, previous);
+                }
+            }
+        }
+    }
+
+    pub fn get_payload_version(&self) -> Option<String> {
+        #[cfg(feature = "net_install")]
+        {
+            self.hachimi_version.lock().unwrap().clone()
+        }
+        #[cfg(not(feature = "net_install"))]
+        {
+            Some(env!("HACHIMI_VERSION").to_owned())
+        }
+    }
+
+    // Latest release from the release source, its tag becomes the payload version
+    #[cfg(feature = "net_install")]
+    pub fn fetch_release(&self) -> Result<Release, Error> {
+        let release = self.release_source.latest(&self.downloader)?;
+        *self.hachimi_version.lock().unwrap() = Some(release.tag.clone());
+        *self.release.lock().unwrap() = Some(release.clone());
+        Ok(release)
+    }
+
+    // Same as start_download, but waits for it
+    #[cfg(feature = "net_install")]
+    pub fn fetch_payload(&self) -> Result<(), Error> {
+        let release = match self.fetch_release() {
+            Ok(release) => release,
+            Err(e) => return self.use_newest_cached(e)
+        };
+        let download = match self.cached_download(&release) {
+            Some(download) => download,
+            None => match self.release_source.fetch(&self.downloader, &release) {
+                Ok(download) => {
+                    self.store_cached(&download);
+                    download
+                },
+                // a bad download isn't the network's fault, don't paper over it
+                Err(e @ Error::IntegrityFailed(_)) => return Err(e),
+                Err(e) => return self.use_newest_cached(e)
+            }
+        };
+        *self.hachimi_dll.lock().unwrap() = Some(Ok(download));
         Ok(())
     }
 
-    pub fn install(&self) -> Result<(), Error> {
-        let path = self.get_current_target_path().ok_or(Error::NoInstallDir)?;
+    // Unchanged release, no need to download it again
+    #[cfg(feature = "net_install")]
+    fn cached_download(&self, release: &Release) -> Option<Downloaded> {
+        self.cache.as_ref()?.find(release).ok()??.load().ok()
+    }
+
+    // Only a cache, failing to write it doesn't stop anything
+    #[cfg(feature = "net_install")]
+    fn store_cached(&self, download: &Downloaded) {
+        if let Some(cache) = &self.cache {
+            _ = cache.store(download);
+        }
+    }
+
+    // The release source can't be reached, go with the newest cached DLL if there is one
+    #[cfg(feature = "net_install")]
+    pub fn use_newest_cached(&self, e: Error) -> Result<(), Error> {
+        let Some(download) = self.cache.as_ref().and_then(|c| c.newest().ok().flatten()) else {
+            return Err(e);
+        };
+        let message = format!(
+            "Unable to reach {}: {}\nUsing the cached Hachimi {} instead, it may not be the latest.",
+            self.release_source.describe(), e, download.sidecar.tag
+        );
+        self.platform.notify("offline_cache", &message, &t!("installer.warning"), Level::Warning);
+
+        *self.hachimi_version.lock().unwrap() = Some(download.sidecar.tag.clone());
+        *self.hachimi_dll.lock().unwrap() = Some(Ok(download));
+        Ok(())
+    }
+
+    // Downloads the fetched release in the background, again if it failed before.
+    // `done` is called from the download thread with whether it worked
+    #[cfg(feature = "net_install")]
+    pub fn start_download(&self, done: impl FnOnce(bool) + Send + 'static) {
+        let Some(release) = self.release.lock().unwrap().clone() else {
+            return;
+        };
+        if let Some(download) = self.cached_download(&release) {
+            *self.hachimi_dll.lock().unwrap() = Some(Ok(download));
+            done(true);
+            return;
+        }
+        *self.hachimi_dll.lock().unwrap() = None;
+
+        let source = self.release_source.clone();
+        let downloader = self.downloader.clone();
+        let cache = self.cache.clone();
+        let hachimi_dll = self.hachimi_dll.clone();
+        std::thread::spawn(move || {
+            let result = source.fetch(&downloader, &release);
+            if let (Ok(download), Some(cache)) = (&result, &cache) {
+                _ = cache.store(download);
+            }
+            let ok = result.is_ok();
+            *hachimi_dll.lock().unwrap() = Some(result);
+            done(ok);
+        });
+    }
 
+    // Why the background download failed, if it did
+    #[cfg(feature = "net_install")]
+    pub fn download_error(&self) -> Option<String> {
+        match self.hachimi_dll.lock().unwrap().as_ref() {
+            Some(Err(e)) => Some(e.to_string()),
+            _ => None
+        }
+    }
+
+    // Tag, asset and digest of the downloaded DLL, once it's there
+    #[cfg(feature = "net_install")]
+    pub fn get_release_sidecar(&self) -> Option<Sidecar> {
+        self.hachimi_dll.lock().unwrap().as_ref()?.as_ref().ok().map(|d| d.sidecar.clone())
+    }
+
+    // Installed Hachimi against the one we'd install
+    pub fn check_update(&self) -> Result<UpdateCheck, Error> {
+        #[cfg(feature = "net_install")]
+        {
+            let mut version = self.hachimi_version.lock().unwrap();
+            if version.is_none() {
+                *version = Some(self.release_source.latest(&self.downloader)?.tag);
+            }
+        }
+        let staged = self.get_payload_version().unwrap_or_default();
+
+        let installed = self.get_current_target_path()
+            .and_then(|p| Self::read_target_version_info(&p))
+            .filter(|v| v.is_hachimi())
+            .map(|v| v.version.unwrap_or_default());
+
+        let state = match &installed {
+            None => UpdateState::NotInstalled,
+            Some(installed) => match (Version::parse(&staged), Version::parse(installed)) {
+                (Some(staged), Some(installed)) => match staged.cmp(&installed) {
+                    std::cmp::Ordering::Greater => UpdateState::Upgrade,
+                    std::cmp::Ordering::Less => UpdateState::Downgrade,
+                    std::cmp::Ordering::Equal => UpdateState::UpToDate
+                },
+                _ => UpdateState::Unknown
+            }
+        };
+
+        Ok(UpdateCheck { installed, staged, state })
+    }
+
+    // Asks before putting an older Hachimi over a newer one, false if the answer was no
+    pub fn confirm_downgrade(&mut self) -> Result<bool, Error> {
+        let check = self.check_update()?;
+        if check.state != UpdateState::Downgrade || self.force || self.allow_downgrade {
+            return Ok(true);
+        }
+
+        let res = self.platform.prompt.ask(
+            "downgrade",
+            &t!("installer.downgrade_confirm", installed = check.installed.unwrap_or_default(), staged = check.staged),
+            &t!("installer.warning"),
+            Level::Warning,
+            Buttons::YesNo
+        );
+        self.allow_downgrade = res == Answer::Yes;
+        Ok(self.allow_downgrade)
+    }
+
+    pub fn get_interrupted_transaction(&self) -> Result<Option<Transaction>, Error> {
+        let Some(data_dir) = self.get_data_dir() else {
+            return Ok(None);
+        };
+        Transaction::load(&data_dir)
+    }
+
+    // Returns false if there's still an interrupted transaction pending afterwards
+    pub fn recover_interrupted(&self) -> Result<bool, Error> {
+        let Some(tx) = self.get_interrupted_transaction()? else {
+            return Ok(true);
+        };
+
+        let operation = match tx.operation {
+            Operation::Install { .. } => t!("installer.operation_install"),
+            Operation::Uninstall => t!("installer.operation_uninstall"),
+            Operation::Repair => t!("installer.operation_repair"),
+            Operation::Ensure { .. } => t!("installer.operation_ensure"),
+            Operation::Restore => t!("installer.operation_restore"),
+            Operation::Prune => t!("installer.operation_prune")
+        };
+        let res = self.platform.prompt.ask(
+            "interrupted_transaction",
+            &t!("installer.interrupted_transaction", operation = operation, dll = tx.target.dll_name()),
+            &t!("installer.warning"),
+            Level::Warning,
+            Buttons::YesNoCancel
+        );
+
+        if res == Answer::Yes {
+            // put back what the interrupted run did and start over; planning on top of a half done
+            // run would take its leftovers for the game's own files (and on uninstall the receipt may
+            // be gone already), a failure still leaves everything as it was before
+            let installer = self.for_transaction(&tx);
+            let interrupted = tx.operation;
+            tx.rollback()?;
+            // the journal doesn't say which backup it was, undoing it is the only way to finish
+            if interrupted != Operation::Restore {
+                installer.run(interrupted)?;
+            }
+            Ok(true)
+        }
+        else if res == Answer::No {
+            tx.rollback()?;
+            Ok(true)
+        }
+        else {
+            Ok(false)
+        }
+    }
+
+    // Where the interrupted run was, with everything else (settings, payload) from self
+    fn for_transaction(&self, tx: &Transaction) -> Installer {
+        Installer {
+            install_dir: Some(tx.install_dir.clone()),
+            target: tx.target,
+            custom_target: tx.custom_target.clone(),
+            force: self.force,
+            allow_downgrade: self.allow_downgrade,
+            platform: self.platform.clone(),
+            #[cfg(feature = "net_install")]
+            release_source: self.release_source.clone(),
+            #[cfg(feature = "net_install")]
+            downloader: self.downloader.clone(),
+            #[cfg(feature = "net_install")]
+            cache: self.cache.clone(),
+            #[cfg(feature = "net_install")]
+            release: self.release.clone(),
+            #[cfg(feature = "net_install")]
+            hachimi_dll: self.hachimi_dll.clone(),
+            #[cfg(feature = "net_install")]
+            hachimi_version: self.hachimi_version.clone()
+        }
+    }
+
+    pub fn pre_install(&self, plan: &mut Plan) -> Result<(), Error> {
+        // only the JP Steam exe gets patched
+        if self.target != Target::CriManaVpx {
+            return Ok(());
+        }
+
+        let orig_exe = self.get_orig_exe_path().ok_or(Error::NoInstallDir)?;
+        let store = self.get_backup_store().ok_or(Error::NoInstallDir)?;
+        let info = GameExeInfo::read(&orig_exe)?;
+
+        // an exe we already patched needs the backup of the original it came from
+        let Some(sha256) = info.source_sha256().map(str::to_owned) else {
+            // could be an original we don't know as well as one patched by something else,
+            // a backup of it might never bring back the real thing
+            return Err(Error::UnknownBackupSource(orig_exe));
+        };
+        if let Some(backup) = store.find_by_hash(&orig_exe, &sha256)? {
+            plan.push(Step::Backup { source: orig_exe, backup, exists: true });
+            return Ok(());
+        }
+
+        // older installers kept a single backup next to the exe, adopt it if it's the right one
+        let legacy_exe = self.get_legacy_backup_exe_path().ok_or(Error::NoInstallDir)?;
+        let source = if info.state() == ExeState::Vanilla {
+            orig_exe.clone()
+        }
+        else if legacy_exe.is_file() && utils::sha256_file(&legacy_exe)? == sha256 {
+            legacy_exe
+        }
+        else {
+            // a backup of a patched exe can never bring back the original
+            return Err(Error::PatchedBackupSource(orig_exe));
+        };
+
+        let size = std::fs::metadata(&source)?.len();
+        let backup = store.prepare(
+            &orig_exe, sha256, size, info.build_name().map(str::to_owned), self.get_current_steam_build_id()
+        );
+        plan.push(Step::Backup { source, backup, exists: false });
+
+        Ok(())
+    }
+
+    // The Hachimi dll this installer ships (or downloaded)
+    pub fn get_hachimi_dll(&self) -> Result<Vec<u8>, Error> {
         let mod_dll: Vec<u8>;
 
         #[cfg(feature = "net_install")]
         {
-            // download started in background, wait for it to complete by lock & check
-            // use `take()` to move the value out of the Option, leaving None in its place
-            // `reqwest::Error` doesn't implement `Clone` lol
+            // the download runs in the background and stays in place, every caller gets the same bytes
             let guard = self.hachimi_dll.lock().unwrap();
             match guard.as_ref() {
-                Some(Ok(bytes)) => {
-                    // `Bytes` is cheap to clone (atomic reference count).
-                    mod_dll = bytes.clone().into();
+                Some(Ok(download)) => {
+                    mod_dll = download.bytes.to_vec();
                 }
                 Some(Err(_)) => {
-                    // generic
                     return Err(Error::DownloadFailed);
                 }
                 None => {
@@ -353,106 +883,371 @@ impl Installer {
             mod_dll = include_bytes!("../hachimi.dll").to_vec();
         }
 
-        std::fs::create_dir_all(path.parent().unwrap())?;
-        let mut file = File::create(&path)?;
-        file.write_all(&mod_dll)?;
+        Ok(mod_dll)
+    }
+
+    // Cellar goes in the .local dir as apphelp.dll, DMM only
+    pub fn get_cellar_dll() -> Vec<u8> {
+        #[cfg(feature = "compress_bin")]
+        {
+            include_bytes_zstd!("cellar.dll", 19)
+        }
+        #[cfg(not(feature = "compress_bin"))]
+        {
+            include_bytes!("../cellar.dll").to_vec()
+        }
+    }
+
+    pub fn get_cellar_path(&self) -> Option<PathBuf> {
+        Some(self.install_dir.as_ref()?.join("umamusume.exe.local").join("apphelp.dll"))
+    }
+
+    pub fn install(&self, plan: &mut Plan) -> Result<(), Error> {
+        let path = self.get_current_target_path().ok_or(Error::NoInstallDir)?;
+        let mod_dll = self.get_hachimi_dll()?;
+        let sha256 = utils::sha256_hex(&mod_dll);
+
+        let installed = Self::read_target_version_info(&path).filter(|v| v.is_hachimi());
+        if let Some(installed) = &installed {
+            // same version and same bytes, rewriting it would only bump the mtime
+            let payload_version = self.get_payload_version().as_deref().and_then(Version::parse);
+            if installed.parsed_version() == payload_version && utils::sha256_file(&path)? == sha256 {
+                plan.notes.push(t!("installer.already_up_to_date", dll = self.target.dll_name()));
+                plan.push(Step::KeepFile { path: path.clone(), sha256 });
+                #[cfg(feature = "net_install")]
+                self.push_sidecar(plan, &path);
+                return Ok(());
+            }
+
+            if let (Some(installed), Some(payload)) = (installed.parsed_version(), payload_version) {
+                if payload < installed && !self.force && !self.allow_downgrade {
+                    return Err(Error::Downgrade(installed.to_string(), payload.to_string()));
+                }
+            }
+        }
+
+        // the .local dir only exists for us, so it's always ours to clean up later
+        let parent = path.parent().unwrap();
+        if TargetType::from(self.target) == TargetType::DotLocal || !parent.is_dir() {
+            plan.push(Step::CreateDir { path: parent.to_owned() });
+        }
+
+        // whatever sits there on a direct target and isn't Hachimi is probably a game file, keep it for uninstall
+        if TargetType::from(self.target) == TargetType::Direct {
+            let version_info = Self::read_target_version_info(&path);
+            if path.is_file() && !version_info.as_ref().is_some_and(|v| v.is_hachimi()) {
+                let store = self.get_backup_store().ok_or(Error::NoInstallDir)?;
+                let sha256 = utils::sha256_file(&path)?;
+                let existing = store.find_by_hash(&path, &sha256)?;
+                let exists = existing.is_some();
+                let backup = match existing {
+                    Some(backup) => backup,
+                    None => store.prepare(
+                        &path, sha256, std::fs::metadata(&path)?.len(),
+                        version_info.and_then(|v| v.version), self.get_current_steam_build_id()
+                    )
+                };
+                plan.push(Step::Backup { source: path.clone(), backup, exists });
+            }
+        }
+
+        plan.push(Step::WriteFile {
+            sha256,
+            source: Source { name: "hachimi.dll".to_owned(), bytes: mod_dll },
+            dest: path.clone()
+        });
+        #[cfg(feature = "net_install")]
+        self.push_sidecar(plan, &path);
 
         Ok(())
     }
 
+    // Which release the DLL came from, written with it and removed with it on uninstall
+    #[cfg(feature = "net_install")]
+    fn push_sidecar(&self, plan: &mut Plan, dll_path: &Path) {
+        let Some(sidecar) = self.get_release_sidecar() else {
+            return;
+        };
+        let bytes = sidecar.to_json().into_bytes();
+        plan.push(Step::WriteFile {
+            sha256: utils::sha256_hex(&bytes),
+            source: Source { name: "release.json".to_owned(), bytes },
+            dest: Sidecar::path_for(dll_path)
+        });
+    }
+
     // no .local redirection necessary on steam client, so dropped that, wheee
     // greetz to uma on mac / linux
-    pub fn post_install(&self) -> Result<(), Error> {
+    pub fn post_install(&self, plan: &mut Plan) -> Result<(), Error> {
         match self.target {
             Target::UnityPlayer => {
                 // Install Cellar
-                let path = self.install_dir.as_ref()
-                    .ok_or_else(|| Error::NoInstallDir)?
-                    .join("umamusume.exe.local")
-                    .join("apphelp.dll");
-                std::fs::create_dir_all(path.parent().unwrap())?;
-                let mut file = File::create(&path)?;
-
-                #[cfg(feature = "compress_bin")]
-                file.write(&include_bytes_zstd!("cellar.dll", 19))?;
+                let path = self.get_cellar_path().ok_or(Error::NoInstallDir)?;
+                let cellar_bytes = Self::get_cellar_dll();
 
-                #[cfg(not(feature = "compress_bin"))]
-                file.write(include_bytes!("../cellar.dll"))?;
+                plan.push(Step::CreateDir { path: path.parent().unwrap().to_owned() });
+                plan.push(Step::WriteFile {
+                    sha256: utils::sha256_hex(&cellar_bytes),
+                    source: Source { name: "cellar.dll".to_owned(), bytes: cellar_bytes },
+                    dest: path
+                });
 
                 // Check for DLL redirection
-                if !is_dotlocal_enabled() {
-                    let res = unsafe {
-                        MessageBoxW(
-                            self.hwnd.lock().unwrap().as_ref(),
-                            &HSTRING::from(t!("installer.dotlocal_not_enabled")),
-                            &HSTRING::from(t!("installer.install")),
-                            MB_ICONINFORMATION | MB_OKCANCEL
-                        )
-                    };
-                    if res == IDOK {
-                        // Request elevation to enable DotLocal
-                        request_dotlocal_elevation(self.hwnd.lock().unwrap().as_ref());
-                    }
+                if !is_dotlocal_enabled(&self.platform) {
+                    plan.push(Step::SetRegistryValue {
+                        key: DEVOVERRIDE_REG_PATH.to_owned(),
+                        value: DEVOVERRIDE_VALUE.to_owned(),
+                        data: Some(1)
+                    });
                 }
             },
             Target::CriManaVpx => {
                 // compatibility: delete dotlocal DLL redir if exists
-                if self.install_dir.as_ref().unwrap().join("UmamusumePrettyDerby_Jpn.exe.local").exists() {
-                    std::fs::remove_dir_all(self.install_dir.as_ref().unwrap().join("UmamusumePrettyDerby_Jpn.exe.local"))?;
+                let dotlocal_dir = self.install_dir.as_ref().ok_or(Error::NoInstallDir)?.join("UmamusumePrettyDerby_Jpn.exe.local");
+                if dotlocal_dir.exists() {
+                    plan.push(Step::RemoveDir { path: dotlocal_dir, only_if_empty: false });
                 }
 
                 let exe_path = self.get_orig_exe_path().ok_or(Error::NoInstallDir)?;
+                let info = GameExeInfo::read(&exe_path)?;
+
+                match info.build {
+                    // already patched (reinstall/update), nothing left to do
+                    _ if info.patched.is_some() => {},
+                    Some(build) => plan.push(Step::PatchExe {
+                        source: exe_path.clone(),
+                        dest: exe_path,
+                        source_sha256: info.sha256,
+                        patch: build.patch,
+                        sha256: build.sha256.to_owned()
+                    }),
+                    // only reachable with --force, there's no patch for this build
+                    None if self.force => plan.notes.push(t!("game.exe_not_patched")),
+                    None => return Err(Error::UnsupportedGameBuild(info.label()))
+                }
+            }
+            // cri_mana_vpx install on global doesn't require bin patch
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    // Puts back whatever verify finds missing or changed, on top of the regular install steps
+    pub fn repair(&self, plan: &mut Plan) -> Result<(), Error> {
+        let report = verify::verify(self)?;
+        if report.get(FileKind::HachimiDll).is_some_and(|f| f.state != FileState::Ok) {
+            self.install(plan)?;
+        }
+        self.repair_game_files(plan, &report)?;
+
+        if plan.steps.is_empty() {
+            plan.notes.push(t!("installer.nothing_to_repair"));
+        }
+
+        Ok(())
+    }
+
+    // Like repair, but only brings things up to the desired state: a newer Hachimi
+    // (e.g. one that updated itself) is left alone, and nothing at all is planned if it all matches.
+    // Without `update` an older Hachimi is kept as well.
+    pub fn ensure(&self, plan: &mut Plan, update: bool) -> Result<(), Error> {
+        let report = verify::verify(self)?;
+        let Some(dll) = report.get(FileKind::HachimiDll) else {
+            return Ok(());
+        };
 
-                // just use stdlib here cuz binary is so small
-                let exe_bytes = std::fs::read(&exe_path)?;
-                #[cfg(feature = "compress_bin")]
-                let modded_bytes: &[u8] = &include_bytes_zstd!("FunnyHoney.exe", 19);
-                #[cfg(not(feature = "compress_bin"))]
-                let modded_bytes: &[u8] = include_bytes!("../FunnyHoney.exe");
-                let mut patch = Vec::new(); {
-                    bsdiff::diff(&exe_bytes, &modded_bytes, &mut patch)?;
+        let write_dll = match dll.state {
+            FileState::Ok => false,
+            FileState::Missing | FileState::Foreign => true,
+            FileState::Modified if !update => false,
+            FileState::Modified => {
+                let installed = Self::read_target_version_info(&dll.path).and_then(|v| v.parsed_version());
+                let payload = self.get_payload_version().as_deref().and_then(Version::parse);
+                match (installed, payload) {
+                    (Some(installed), Some(payload)) => installed < payload,
+                    // can't tell which one is newer, ours at least is known to work
+                    _ => true
                 }
+            }
+        };
+        if write_dll {
+            self.install(plan)?;
+        }
+
+        // a game update the installer doesn't know yet, patching would fail anyway
+        if report.get(FileKind::GameExe).is_some_and(|f| f.state == FileState::Foreign) && !self.force {
+            let label = self.get_game_exe_info()?.map(|i| i.label()).unwrap_or_default();
+            return Err(Error::UnsupportedGameBuild(label));
+        }
+        self.repair_game_files(plan, &report)
+    }
 
-                let mut patched_bytes = Vec::with_capacity(modded_bytes.len()); {
-                    bsdiff::patch(&exe_bytes, &mut patch.as_slice(), &mut patched_bytes)?;
+    fn repair_game_files(&self, plan: &mut Plan, report: &VerifyReport) -> Result<(), Error> {
+        let install_dir = self.install_dir.as_ref().ok_or(Error::NoInstallDir)?;
+        match self.target {
+            Target::UnityPlayer => {
+                let cellar = report.get(FileKind::Cellar).map(|f| f.state);
+                if cellar != Some(FileState::Ok) || !is_dotlocal_enabled(&self.platform) {
+                    self.post_install(plan)?;
+                }
+            },
+            Target::CriManaVpx => {
+                // left behind by a post_install that didn't get to the rename
+                let exe_path = self.get_orig_exe_path().ok_or(Error::NoInstallDir)?;
+                let tmp_path = exe_path.with_extension("exe.tmp");
+                if tmp_path.is_file() {
+                    plan.push(Step::Remove { path: tmp_path });
                 }
-                debug_assert_eq!(modded_bytes, patched_bytes);
 
-                // Write tmpfile before overwriting shim EXE
-                // atomic replace so game dont break if patch fails
-                let mut patched_exe = File::create(&exe_path.with_extension("exe.tmp"))?;
-                patched_exe.write(&patched_bytes)?;
-                std::fs::rename(&exe_path.with_extension("exe.tmp"), &exe_path)?;
-            }
-            // cri_mana_vpx install on global doesn't require bin patch
+                let exe = report.get(FileKind::GameExe).map(|f| f.state);
+                let backup = report.get(FileKind::ExeBackup).map(|f| f.state);
+                if exe == Some(FileState::Modified) {
+                    // Steam put the original back, back it up (never the patched one) and patch it again;
+                    // post_install also takes care of the .local dir
+                    self.pre_install(plan)?;
+                    self.post_install(plan)?;
+                }
+                else {
+                    let dotlocal_dir = install_dir.join("UmamusumePrettyDerby_Jpn.exe.local");
+                    if dotlocal_dir.exists() {
+                        plan.push(Step::RemoveDir { path: dotlocal_dir, only_if_empty: false });
+                    }
+                    if exe == Some(FileState::Foreign) {
+                        plan.notes.push(t!("game.exe_not_patched"));
+                    }
+                    // the exe is still patched, there's nothing to make a backup from
+                    if exe == Some(FileState::Ok) && backup != Some(FileState::Ok) {
+                        plan.notes.push(t!("installer.backup_not_repairable"));
+                    }
+                }
+            },
             _ => {}
         }
 
         Ok(())
     }
 
-    pub fn uninstall(&self) -> Result<(), Error> {
+    pub fn uninstall(&self, plan: &mut Plan) -> Result<(), Error> {
+        match self.get_receipt()? {
+            Some(receipt) if receipt.target == self.target => {
+                self.uninstall_from_receipt(plan, receipt, self.get_current_steam_build_id())
+            },
+            // installed by an older version, fall back to what each target is known to install
+            _ => self.uninstall_legacy(plan)
+        }
+    }
+
+    fn uninstall_from_receipt(&self, plan: &mut Plan, receipt: Receipt, current_build_id: Option<u64>) -> Result<(), Error> {
+        // a backup from before a game update would downgrade the game, check before touching anything
+        let store = self.get_backup_store().ok_or(Error::NoInstallDir)?;
+        let mut restores = Vec::new();
+        let mut skipped = Vec::new();
+        // Steam puts the vanilla exe back on verify or update, it's neither ours to remove nor to restore over
+        let orig_exe = self.get_orig_exe_path();
+        for file in &receipt.files {
+            if Some(&file.path) == orig_exe.as_ref() && file.path.is_file()
+                && utils::sha256_file(&file.path).ok().as_deref() != Some(file.sha256.as_str())
+            {
+                plan.notes.push(t!("backup.exe_changed_skipped", path = file.path.display()));
+                skipped.push(file.path.clone());
+            }
+        }
+        for backup in receipt.backups.iter().rev() {
+            if skipped.contains(&backup.path) {
+                continue;
+            }
+            let entry = store.find_by_file(&backup.backup)?;
+            if let Some(entry) = entry.as_ref().filter(|e| e.is_stale(current_build_id)) {
+                if !self.force {
+                    return Err(Error::StaleBackup(
+                        backup.path.clone(), entry.steam_build_id.unwrap_or(0), current_build_id.unwrap_or(0)
+                    ));
+                }
+                // leave whatever the update put there alone
+                plan.notes.push(t!("backup.stale_skipped", path = backup.path.display()));
+                skipped.push(backup.path.clone());
+                continue;
+            }
+            restores.push((backup, entry));
+        }
+
+        for file in receipt.files.iter().rev() {
+            if file.path.is_file() && !skipped.contains(&file.path) {
+                // only the DLLs can be something else by now, like a game DLL under a wrong --target
+                if file.path.extension().is_some_and(|e| e.eq_ignore_ascii_case("dll")) {
+                    self.check_removable(&file.path, Some(&file.sha256))?;
+                }
+                plan.push(Step::Remove { path: file.path.clone() });
+            }
+        }
+
+        for dir in receipt.dirs.iter().rev() {
+            if dir.is_dir() {
+                plan.push(Step::RemoveDir { path: dir.clone(), only_if_empty: true });
+            }
+        }
+
+        // DevOverrideEnable is machine wide, put back whatever was there before the install
+        for change in &receipt.registry {
+            if self.platform.registry.get_u32(&change.key, &change.value) == change.previous {
+                continue;
+            }
+            plan.push(Step::SetRegistryValue { key: change.key.clone(), value: change.value.clone(), data: change.previous });
+        }
+
+        let data_dir = self.get_data_dir().ok_or(Error::NoInstallDir)?;
+        plan.push(Step::Remove { path: data_dir.join(RECEIPT_FILE) });
+
+        for (backup, entry) in restores {
+            plan.push(Step::Restore {
+                backup: backup.backup.clone(),
+                dest: backup.path.clone(),
+                sha256: Some(backup.sha256.clone()),
+                keep_backup: false
+            });
+            // the file itself is moved back, drop the rest of its store entry
+            if let Some(entry) = entry {
+                plan.push(Step::RemoveDir { path: entry.dir().to_owned(), only_if_empty: false });
+            }
+        }
+        if store.dir().is_dir() {
+            plan.push(Step::RemoveDir { path: store.dir().to_owned(), only_if_empty: true });
+        }
+
+        Ok(())
+    }
+
+    fn uninstall_legacy(&self, plan: &mut Plan) -> Result<(), Error> {
         let path = self.get_current_target_path().ok_or(Error::NoInstallDir)?;
-        std::fs::remove_file(&path)?;
+        let payload_sha256 = self.get_hachimi_dll().ok().map(|dll| utils::sha256_hex(&dll));
+        if path.is_file() {
+            self.check_removable(&path, payload_sha256.as_deref())?;
+            plan.push(Step::Remove { path: path.clone() });
+        }
 
         match self.target {
             Target::UnityPlayer => {
                 let parent = path.parent().unwrap();
 
                 // Also delete Cellar
-                _ = std::fs::remove_file(parent.join("apphelp.dll"));
+                let cellar_path = parent.join("apphelp.dll");
+                if cellar_path.is_file() {
+                    plan.push(Step::Remove { path: cellar_path });
+                }
 
                 // Only remove if its empty
-                _ = std::fs::remove_dir(parent);
+                plan.push(Step::RemoveDir { path: parent.to_owned(), only_if_empty: true });
             },
             Target::CriManaVpx => {
-                let backup_exe = self.get_backup_exe_path().ok_or(Error::NoInstallDir)?;
+                let backup_exe = self.get_legacy_backup_exe_path().ok_or(Error::NoInstallDir)?;
                 let orig_exe = self.get_orig_exe_path().ok_or(Error::NoInstallDir)?;
-                if backup_exe.exists() {
-                    std::fs::rename(&backup_exe, &orig_exe)?;
-                } else {
-                    return Err(Error::FailedToRestore);
-                }
+                plan.push(Step::Restore {
+                    sha256: utils::sha256_file(&backup_exe).ok(),
+                    backup: backup_exe,
+                    dest: orig_exe,
+                    keep_backup: false
+                });
             }
             _ => {}
         }
@@ -460,30 +1255,97 @@ impl Installer {
         Ok(())
     }
 
-    pub fn get_backup_exe_path(&self) -> Option<PathBuf> {
-        Some(self.install_dir.as_ref()?.join("UmamusumePrettyDerby_Jpn.old.exe"))
+    // Don't delete anything that isn't ours unless told to, a wrong --target would take a game file with it
+    fn check_removable(&self, path: &Path, sha256: Option<&str>) -> Result<(), Error> {
+        if self.force {
+            return Ok(());
+        }
+        if sha256.is_some_and(|h| utils::sha256_file(path).is_ok_and(|actual| actual == h)) {
+            return Ok(());
+        }
+        // Hachimi updates itself, so a different hash can still be fine
+        if Self::read_target_version_info(path).is_some_and(|v| v.is_hachimi()) {
+            return Ok(());
+        }
+        Err(Error::NotHachimi(path.to_owned()))
     }
 
-    pub fn get_orig_exe_path(&self) -> Option<PathBuf> {
-        Some(self.install_dir.as_ref()?.join("UmamusumePrettyDerby_Jpn.exe"))
+    // Only the JP Steam exe gets patched, so that's the only one we care about
+    pub fn get_game_exe_info(&self) -> Result<Option<GameExeInfo>, Error> {
+        if self.target != Target::CriManaVpx {
+            return Ok(None);
+        }
+        let exe_path = self.get_orig_exe_path().ok_or(Error::NoInstallDir)?;
+        Ok(Some(GameExeInfo::read(&exe_path)?))
     }
-}
 
-impl Default for Installer {
-    fn default() -> Installer {
-        let install_dir = Target::VALUES.iter()
-            .find_map(|t| Self::detect_install_dir(*t));
+    pub fn get_backup_store(&self) -> Option<BackupStore> {
+        Some(BackupStore::new(&self.get_data_dir()?))
+    }
 
-        Installer {
-            install_dir,
-            target: Target::default(),
-            custom_target: None,
-            hwnd: Arc::new(Mutex::new(None)),
-            #[cfg(feature = "net_install")]
-            hachimi_dll: Arc::new(Mutex::new(None)),
-            #[cfg(feature = "net_install")]
-            hachimi_version: Arc::new(Mutex::new(None))
+    // Copies a backup over the file it was made from, the backup stays in the store
+    pub fn plan_restore(&self, entry: &BackupEntry, current_build_id: Option<u64>) -> Result<Plan, Error> {
+        if entry.is_stale(current_build_id) && !self.force {
+            return Err(Error::StaleBackup(
+                entry.path.clone(), entry.steam_build_id.unwrap_or(0), current_build_id.unwrap_or(0)
+            ));
         }
+        entry.verify()?;
+
+        let mut plan = Plan::new(Operation::Restore, self.target, self.install_dir.clone());
+        plan.push(Step::Restore {
+            backup: entry.file.clone(),
+            dest: entry.path.clone(),
+            sha256: Some(entry.sha256.clone()),
+            keep_backup: true
+        });
+        Ok(plan)
+    }
+
+    // Backup files the current install still needs to uninstall cleanly
+    pub fn get_backups_in_use(&self) -> Result<Vec<PathBuf>, Error> {
+        Ok(self.get_receipt()?
+            .map(|r| r.backups.into_iter().map(|b| b.backup).collect())
+            .unwrap_or_default())
+    }
+
+    // Everything in the store except what's in use, including entries with broken metadata
+    fn prune(&self, plan: &mut Plan) -> Result<(), Error> {
+        let store = self.get_backup_store().ok_or(Error::NoInstallDir)?;
+        let in_use = self.get_backups_in_use()?;
+        for dir in store.entry_dirs()? {
+            if !in_use.iter().any(|f| f.starts_with(&dir)) {
+                plan.push(Step::RemoveDir { path: dir, only_if_empty: false });
+            }
+        }
+        if store.dir().is_dir() {
+            plan.push(Step::RemoveDir { path: store.dir().to_owned(), only_if_empty: true });
+        }
+        Ok(())
+    }
+
+    // Only when the install dir is the one Steam knows about, a manifest for another copy means nothing
+    pub fn get_current_steam_build_id(&self) -> Option<u64> {
+        let app_id = match self.target {
+            Target::CriManaVpx => JP_STEAM_ID,
+            Target::CriManaVpxGlobal => GLOBAL_STEAM_ID,
+            _ => return None
+        };
+        let (app_dir, build_id) = get_steam_build_id(app_id)?;
+        let install_dir = self.install_dir.as_ref()?;
+        if std::fs::canonicalize(app_dir).ok()? != std::fs::canonicalize(install_dir).ok()? {
+            return None;
+        }
+        Some(build_id)
+    }
+
+    // Where installers before the backup store kept the original exe
+    pub fn get_legacy_backup_exe_path(&self) -> Option<PathBuf> {
+        Some(self.install_dir.as_ref()?.join("UmamusumePrettyDerby_Jpn.old.exe"))
+    }
+
+    pub fn get_orig_exe_path(&self) -> Option<PathBuf> {
+        Some(self.install_dir.as_ref()?.join("UmamusumePrettyDerby_Jpn.exe"))
     }
 }
 
@@ -511,19 +1373,39 @@ impl Target {
             Self::CriManaVpxKomoe => "cri_mana_vpx.dll"
         }
     }
-}
 
-impl Default for Target {
+    pub fn exe_name(&self) -> &'static str {
+        match self {
+            Self::UnityPlayer => "umamusume.exe",
+            Self::CriManaVpx => "UmamusumePrettyDerby_Jpn.exe",
+            Self::CriManaVpxGlobal => "UmamusumePrettyDerby.exe",
+            Self::CriManaVpxKomoe => "komoeumamusume.exe"
+        }
+    }
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            Self::UnityPlayer => "dmm",
+            Self::CriManaVpx => "steam-jp",
+            Self::CriManaVpxGlobal => "steam-global",
+            Self::CriManaVpxKomoe => "komoe"
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::VALUES.iter().find(|t| t.id() == id).copied()
+    }
+
     // default to whatever target is detected
     // default to dmm, and prioritize jp steam over globe
-    fn default() -> Self {
-        if detect_dmm_install_dir().is_some() {
+    pub fn detect(platform: &Platform) -> Self {
+        if detect_dmm_install_dir(platform).is_some() {
             Self::UnityPlayer
         } else if detect_steam_install_dir(JP_STEAM_ID).is_some() {
             Self::CriManaVpx
         } else if detect_steam_install_dir(GLOBAL_STEAM_ID).is_some() {
             Self::CriManaVpxGlobal
-        } else if detect_komoe_install_dir().is_some() {
+        } else if detect_komoe_install_dir(platform).is_some() {
             Self::CriManaVpxKomoe
         } else {
             Self::UnityPlayer
@@ -548,6 +1430,47 @@ impl From<Target> for TargetType {
     }
 }
 
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum UpdateState {
+    UpToDate,
+    // staged version is newer
+    Upgrade,
+    // staged version is older
+    Downgrade,
+    NotInstalled,
+    // installed version couldn't be compared
+    Unknown
+}
+
+impl UpdateState {
+    pub fn id(&self) -> &'static str {
+        match self {
+            UpdateState::UpToDate => "same",
+            UpdateState::Upgrade => "upgrade",
+            UpdateState::Downgrade => "downgrade",
+            UpdateState::NotInstalled => "not_installed",
+            UpdateState::Unknown => "unknown"
+        }
+    }
+
+    // For check-update, apart from the error codes so a script can tell them apart
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            UpdateState::UpToDate => 0,
+            UpdateState::Upgrade => 100,
+            UpdateState::Downgrade => 101,
+            UpdateState::NotInstalled => 102,
+            UpdateState::Unknown => 103
+        }
+    }
+}
+
+pub struct UpdateCheck {
+    pub installed: Option<String>,
+    pub staged: String,
+    pub state: UpdateState
+}
+
 #[derive(Debug, Default)]
 pub struct TargetVersionInfo {
     pub name: Option<String>,
@@ -561,20 +1484,81 @@ impl TargetVersionInfo {
         }
         false
     }
+
+    pub fn parsed_version(&self) -> Option<Version> {
+        Version::parse(self.version.as_deref()?)
+    }
 }
 
 #[derive(Debug)]
 pub enum Error {
     NoInstallDir,
     IoError(std::io::Error),
-    RegistryValueError(registry::value::Error),
+    RegistryValueError(RegistryError),
     FailedToRestore,
+    InvalidJournal,
+    TransactionPending,
+    InvalidReceipt,
+    FileChanged(PathBuf),
+    UnsupportedGameBuild(String),
+    PatchedBackupSource(PathBuf),
+    UnknownBackupSource(PathBuf),
+    // path, build the backup was made for, current build
+    StaleBackup(PathBuf, u64, u64),
+    BackupNotFound(String),
+    NotHachimi(PathBuf),
+    InvalidAnswersFile(PathBuf, String),
+    // installed, staged
+    Downgrade(String, String),
     #[cfg(feature = "net_install")]
     ReqwestError(reqwest::Error),
     #[cfg(feature = "net_install")]
     DownloadNotStarted,
     #[cfg(feature = "net_install")]
     DownloadFailed,
+    #[cfg(feature = "net_install")]
+    InvalidReleaseInfo(String),
+    #[cfg(feature = "net_install")]
+    InvalidReleaseSource(String),
+    // the download doesn't match the release, it's never installed
+    #[cfg(feature = "net_install")]
+    IntegrityFailed(IntegrityError),
+}
+
+impl Error {
+    // Process exit code for the CLI, each one listed in the README
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::NoInstallDir => 10,
+            Error::IoError(_) => 11,
+            Error::RegistryValueError(_) => 12,
+            Error::FailedToRestore => 13,
+            Error::InvalidJournal => 14,
+            Error::TransactionPending => 15,
+            Error::InvalidReceipt => 16,
+            Error::FileChanged(_) => 17,
+            Error::UnsupportedGameBuild(_) => 18,
+            Error::PatchedBackupSource(_) => 19,
+            Error::StaleBackup(..) => 20,
+            Error::BackupNotFound(_) => 21,
+            Error::NotHachimi(_) => 22,
+            Error::InvalidAnswersFile(..) => 23,
+            Error::Downgrade(..) => 24,
+            Error::UnknownBackupSource(_) => 25,
+            #[cfg(feature = "net_install")]
+            Error::ReqwestError(_) => 30,
+            #[cfg(feature = "net_install")]
+            Error::DownloadNotStarted => 31,
+            #[cfg(feature = "net_install")]
+            Error::DownloadFailed => 32,
+            #[cfg(feature = "net_install")]
+            Error::InvalidReleaseInfo(_) => 33,
+            #[cfg(feature = "net_install")]
+            Error::InvalidReleaseSource(_) => 34,
+            #[cfg(feature = "net_install")]
+            Error::IntegrityFailed(_) => 35,
+        }
+    }
 }
 
 impl std::fmt::Display for Error {
@@ -584,12 +1568,34 @@ impl std::fmt::Display for Error {
             Error::IoError(e) => write!(f, "{}", t!("error.io_error", error = e)),
             Error::RegistryValueError(e) => write!(f, "{}", t!("error.registry_value_error", error = e)),
             Error::FailedToRestore => write!(f, "{}", t!("error.failed_to_restore")),
+            Error::InvalidJournal => write!(f, "{}", t!("error.invalid_journal")),
+            Error::TransactionPending => write!(f, "{}", t!("error.transaction_pending")),
+            Error::InvalidReceipt => write!(f, "{}", t!("error.invalid_receipt")),
+            Error::FileChanged(path) => write!(f, "{}", t!("error.file_changed", path = path.display())),
+            Error::UnsupportedGameBuild(build) => write!(f, "{}", t!("error.unsupported_game_build", build = build)),
+            Error::PatchedBackupSource(path) => write!(f, "{}", t!("error.patched_backup_source", path = path.display())),
+            Error::UnknownBackupSource(path) => write!(f, "{}", t!("error.unknown_backup_source", path = path.display())),
+            Error::StaleBackup(path, backup_build, current_build) => write!(f, "{}", t!(
+                "error.stale_backup", path = path.display(), backup_build = backup_build, current_build = current_build
+            )),
+            Error::BackupNotFound(id) => write!(f, "{}", t!("error.backup_not_found", id = id)),
+            Error::NotHachimi(path) => write!(f, "{}", t!("error.not_hachimi", path = path.display())),
+            Error::InvalidAnswersFile(path, e) => write!(f, "{}", t!("error.invalid_answers_file", path = path.display(), error = e)),
+            Error::Downgrade(installed, staged) => write!(f, "{}", t!("error.downgrade", installed = installed, staged = staged)),
             #[cfg(feature = "net_install")]
             Error::ReqwestError(e) => write!(f, "Download error: {}", e),
             #[cfg(feature = "net_install")]
-            Error::DownloadFailed => write!(f, "Download failed on a previous attempt. Please restart the installer."),
+            Error::DownloadFailed => write!(f, "Download failed on a previous attempt. Try again to retry it."),
             #[cfg(feature = "net_install")]
             Error::DownloadNotStarted => write!(f, "Download has not started."),
+            #[cfg(feature = "net_install")]
+            Error::InvalidReleaseInfo(e) => write!(f, "Invalid release info: {}", e),
+            #[cfg(feature = "net_install")]
+            Error::InvalidReleaseSource(spec) => write!(
+                f, "Invalid release source: {} (expected github[:<owner>/<repo>[@<api base>]], index:<url> or local:<dir>)", spec
+            ),
+            #[cfg(feature = "net_install")]
+            Error::IntegrityFailed(e) => write!(f, "The downloaded {} failed the integrity check: {}", release::DLL_ASSET, e),
         }
     }
 }
@@ -600,8 +1606,8 @@ impl From<std::io::Error> for Error {
     }
 }
 
-impl From<registry::value::Error> for Error {
-    fn from(e: registry::value::Error) -> Self {
+impl From<RegistryError> for Error {
+    fn from(e: RegistryError) -> Self {
         Error::RegistryValueError(e)
     }
 }
@@ -611,4 +1617,381 @@ impl From<reqwest::Error> for Error {
     fn from(e: reqwest::Error) -> Self {
         Error::ReqwestError(e)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{platform::fake::{FakePlatform, RegistryData}, test_utils::TempDir};
+    #[cfg(feature = "net_install")]
+    use crate::test_utils::stage_download;
+
+    const GAME_DLL: &[u8] = b"the game's own cri_mana_vpx.dll";
+    const INSTALL: Operation = Operation::Install { pre_install: true, post_install: true };
+
+    // KOMOE is a direct target without an exe patch, so a plain dir is all it takes
+    struct Setup {
+        tmp: TempDir,
+        fake: FakePlatform,
+        installer: Installer,
+        dll: PathBuf
+    }
+
+    fn setup() -> Setup {
+        let tmp = TempDir::new();
+        let game_dir = tmp.path().join("game");
+        std::fs::create_dir_all(&game_dir).unwrap();
+        std::fs::write(game_dir.join(Target::CriManaVpxKomoe.exe_name()), b"exe").unwrap();
+        std::fs::write(game_dir.join(Target::CriManaVpxKomoe.dll_name()), GAME_DLL).unwrap();
+
+        let fake = FakePlatform::default();
+        let installer = Installer::custom(Rc::new(fake.build()), Some(game_dir.clone()), Target::CriManaVpxKomoe, None);
+        #[cfg(feature = "net_install")]
+        stage_download(&installer);
+
+        let dll = game_dir.join(Target::CriManaVpxKomoe.dll_name());
+        Setup { tmp, fake, installer, dll }
+    }
+
+    fn read(path: &Path) -> Vec<u8> {
+        std::fs::read(path).unwrap()
+    }
+
+    fn assert_nothing_pending(installer: &Installer) {
+        assert!(installer.get_interrupted_transaction().unwrap().is_none());
+    }
+
+    #[test]
+    fn detects_targets() {
+        let tmp = TempDir::new();
+        for target in Target::VALUES {
+            let dir = tmp.path().join(target.id());
+            std::fs::create_dir_all(&dir).unwrap();
+            assert!(detect_target_from_path(&dir).is_none());
+            std::fs::write(dir.join(target.exe_name()), b"exe").unwrap();
+            assert!(detect_target_from_path(&dir) == Some(*target), "{} not detected", target.id());
+        }
+
+        let fake = FakePlatform::default();
+        let platform = fake.build();
+        assert!(detect_dmm_install_dir(&platform).is_none());
+        assert!(detect_komoe_install_dir(&platform).is_none());
+
+        let app_data = tmp.path().join("appdata");
+        let dmm_dir = tmp.path().join(Target::UnityPlayer.id());
+        let dmm_path = JsonValue::String(dmm_dir.to_string_lossy().into_owned()).stringify().unwrap();
+        std::fs::create_dir_all(app_data.join("dmmgameplayer5")).unwrap();
+        std::fs::write(
+            app_data.join("dmmgameplayer5").join("dmmgame.cnf"),
+            format!(r#"{{"contents": [{{"productId": "other"}}, {{"productId": "umamusume", "detail": {{"path": {}}}}}]}}"#, dmm_path)
+        ).unwrap();
+        fake.known_folders.folders.lock().unwrap().insert(KnownFolder::RoamingAppData, app_data);
+        assert_eq!(detect_dmm_install_dir(&platform), Some(dmm_dir.clone()));
+        assert!(Target::detect(&platform) == Target::UnityPlayer);
+
+        let komoe_dir = tmp.path().join(Target::CriManaVpxKomoe.id());
+        fake.registry.set(KOMOE_REG_KEY, "GameInstallPath", RegistryData::String(komoe_dir.to_string_lossy().into_owned()));
+        assert_eq!(Installer::detect_install_dir(&platform, Target::CriManaVpxKomoe), Some(komoe_dir));
+
+        // DMM comes first
+        let installer = Installer::new(Rc::new(platform));
+        assert!(installer.target == Target::UnityPlayer);
+        assert_eq!(installer.install_dir, Some(dmm_dir));
+    }
+
+    #[test]
+    fn install_receipt_uninstall_round_trip() {
+        let s = setup();
+        let payload = s.installer.get_hachimi_dll().unwrap();
+
+        s.installer.run(INSTALL).unwrap();
+        assert_eq!(read(&s.dll), payload);
+        assert!(s.installer.is_current_target_installed());
+        assert_nothing_pending(&s.installer);
+
+        let receipt = s.installer.get_receipt().unwrap().expect("receipt after install");
+        assert!(receipt.target == Target::CriManaVpxKomoe);
+        assert!(receipt.files.iter().any(|f| f.path == s.dll && f.sha256 == utils::sha256_hex(&payload)));
+        // the game's own DLL was kept for uninstall
+        assert_eq!(receipt.backups.len(), 1);
+        assert_eq!(receipt.backups[0].path, s.dll);
+        assert_eq!(read(&receipt.backups[0].backup), GAME_DLL);
+
+        s.installer.run(Operation::Uninstall).unwrap();
+        assert_eq!(read(&s.dll), GAME_DLL);
+        assert!(s.installer.get_receipt().unwrap().is_none());
+        assert!(s.installer.get_backup_store().unwrap().list().unwrap().is_empty());
+        assert_nothing_pending(&s.installer);
+    }
+
+    #[test]
+    fn install_runs_the_dry_run_plan() {
+        let s = setup();
+        let plan = s.installer.plan(INSTALL).unwrap();
+        // net_install writes the release sidecar as well
+        let kinds: Vec<&str> = plan.steps.iter().map(Step::kind).collect();
+        assert_eq!(kinds.first(), Some(&"backup"));
+        assert_eq!(kinds.last(), Some(&"write_receipt"));
+        assert!(kinds[1..kinds.len() - 1].iter().all(|k| *k == "write_file"));
+        // planning alone doesn't touch anything
+        assert_eq!(read(&s.dll), GAME_DLL);
+        assert!(s.installer.get_backup_store().unwrap().list().unwrap().is_empty());
+
+        s.installer.run_plan(&plan).unwrap();
+        for step in &plan.steps {
+            match step {
+                Step::Backup { backup, .. } => assert_eq!(utils::sha256_file(&backup.file).unwrap(), backup.sha256),
+                Step::WriteFile { dest, sha256, .. } => assert_eq!(&utils::sha256_file(dest).unwrap(), sha256),
+                Step::WriteReceipt { dest, .. } => assert!(dest.is_file()),
+                _ => unreachable!()
+            }
+        }
+        assert_nothing_pending(&s.installer);
+    }
+
+    #[test]
+    fn records_dotlocal_once_set_and_restores_it() {
+        let s = setup();
+        let install_with_dotlocal = || {
+            let mut plan = s.installer.plan(INSTALL).unwrap();
+            let write_receipt = plan.steps.pop().unwrap();
+            plan.push(Step::SetRegistryValue {
+                key: DEVOVERRIDE_REG_PATH.to_owned(),
+                value: DEVOVERRIDE_VALUE.to_owned(),
+                data: Some(1)
+            });
+            plan.push(write_receipt);
+            s.installer.run_plan(&plan).unwrap();
+        };
+        let dotlocal = || s.fake.registry.get(DEVOVERRIDE_REG_PATH, DEVOVERRIDE_VALUE);
+
+        // the elevated instance started, but couldn't write the value
+        s.fake.registry.locked_keys.lock().unwrap().push(DEVOVERRIDE_REG_PATH.to_owned());
+        install_with_dotlocal();
+        assert!(dotlocal().is_none());
+        assert!(s.installer.get_receipt().unwrap().unwrap().registry.is_empty());
+
+        // explicitly off before the install, so it's turned off again instead of deleted
+        s.fake.registry.locked_keys.lock().unwrap().clear();
+        s.fake.registry.set(DEVOVERRIDE_REG_PATH, DEVOVERRIDE_VALUE, RegistryData::U32(0));
+        install_with_dotlocal();
+        assert_eq!(dotlocal(), Some(RegistryData::U32(1)));
+        let receipt = s.installer.get_receipt().unwrap().unwrap();
+        assert_eq!(receipt.registry.len(), 1);
+        assert_eq!(receipt.registry[0].previous, Some(0));
+
+        s.installer.run(Operation::Uninstall).unwrap();
+        assert_eq!(dotlocal(), Some(RegistryData::U32(0)));
+        assert_eq!(s.fake.elevation.runs.lock().unwrap().last().map(String::as_str), Some("--restore-dotlocal 0"));
+    }
+
+    #[test]
+    fn patches_exe_only_to_the_expected_output() {
+        let s = setup();
+        let exe = s.dll.with_file_name("patched.exe");
+        std::fs::write(&exe, b"original exe").unwrap();
+        let mut patch = Vec::new();
+        bsdiff::diff(b"original exe", b"patched exe", &mut patch).unwrap();
+        let patch: &'static [u8] = Box::leak(patch.into_boxed_slice());
+
+        let run = |source_sha256: String, sha256: String| {
+            let mut plan = Plan::new(INSTALL, s.installer.target, s.installer.install_dir.clone());
+            plan.push(Step::PatchExe { source: exe.clone(), dest: exe.clone(), source_sha256, patch, sha256 });
+            s.installer.run_plan(&plan)
+        };
+
+        // the patch doesn't produce the exe it's supposed to
+        let res = run(utils::sha256_hex(b"original exe"), utils::sha256_hex(b"something else"));
+        assert!(matches!(res, Err(Error::FileChanged(path)) if path == exe));
+        // the exe changed after the plan was made
+        let res = run(utils::sha256_hex(b"another exe"), utils::sha256_hex(b"patched exe"));
+        assert!(matches!(res, Err(Error::FileChanged(path)) if path == exe));
+        assert_eq!(read(&exe), b"original exe");
+        assert!(!exe.with_extension("exe.tmp").exists());
+        assert_nothing_pending(&s.installer);
+
+        run(utils::sha256_hex(b"original exe"), utils::sha256_hex(b"patched exe")).unwrap();
+        assert_eq!(read(&exe), b"patched exe");
+        assert!(!exe.with_extension("exe.tmp").exists());
+    }
+
+    #[test]
+    fn refuses_unknown_game_builds_without_force() {
+        let mut s = setup();
+        let exe = s.dll.with_file_name(Target::CriManaVpx.exe_name());
+        std::fs::write(&exe, b"some game update").unwrap();
+        s.installer.target = Target::CriManaVpx;
+
+        let label = GameExeInfo::read(&exe).unwrap().label();
+        assert!(matches!(s.installer.plan(INSTALL), Err(Error::UnsupportedGameBuild(build)) if build == label));
+
+        // --force installs anyway, leaving the exe alone
+        s.installer.force = true;
+        let plan = s.installer.plan(INSTALL).unwrap();
+        assert!(plan.notes.contains(&t!("game.exe_not_patched")));
+        assert!(!plan.steps.iter().any(|step| matches!(step, Step::PatchExe { .. })));
+        s.installer.run_plan(&plan).unwrap();
+        assert_eq!(read(&exe), b"some game update");
+    }
+
+    #[test]
+    fn never_backs_up_an_unknown_exe() {
+        let mut s = setup();
+        let exe = s.dll.with_file_name(Target::CriManaVpx.exe_name());
+        std::fs::write(&exe, b"patched by something else").unwrap();
+        s.installer.target = Target::CriManaVpx;
+
+        let mut plan = Plan::new(INSTALL, s.installer.target, s.installer.install_dir.clone());
+        assert!(matches!(s.installer.pre_install(&mut plan), Err(Error::UnknownBackupSource(path)) if path == exe));
+        assert!(plan.steps.is_empty());
+
+        // --force goes ahead without one
+        s.installer.force = true;
+        let plan = s.installer.plan(INSTALL).unwrap();
+        assert!(plan.notes.contains(&t!("game.exe_not_backed_up")));
+        assert!(!plan.steps.iter().any(|step| matches!(step, Step::Backup { source, .. } if *source == exe)));
+    }
+
+    #[test]
+    fn rolls_back_after_failed_step() {
+        let s = setup();
+        let backup = s.tmp.path().join("backup.dll");
+        std::fs::write(&backup, b"backup").unwrap();
+
+        // a backup that doesn't match its hash fails the plan after the DLL and receipt were written
+        let mut plan = s.installer.plan(INSTALL).unwrap();
+        plan.push(Step::Restore {
+            backup: backup.clone(),
+            dest: s.dll.with_file_name("restored.dll"),
+            sha256: Some("0".repeat(64)),
+            keep_backup: true
+        });
+        let result = s.installer.run_plan(&plan);
+        assert!(matches!(result, Err(Error::FileChanged(path)) if path == backup));
+
+        assert_eq!(read(&s.dll), GAME_DLL);
+        assert!(!s.dll.with_file_name("restored.dll").exists());
+        assert!(s.installer.get_receipt().unwrap().is_none());
+        assert!(s.installer.get_backup_store().unwrap().list().unwrap().is_empty());
+        assert_eq!(read(&backup), b"backup");
+        assert_nothing_pending(&s.installer);
+    }
+
+    #[test]
+    fn refuses_to_uninstall_other_dlls() {
+        let mut s = setup();
+
+        // nothing installed, the DLL there is the game's
+        assert!(matches!(s.installer.run(Operation::Uninstall), Err(Error::NotHachimi(path)) if path == s.dll));
+        assert_eq!(read(&s.dll), GAME_DLL);
+
+        // installed, but something else replaced the DLL since
+        std::fs::remove_file(&s.dll).unwrap();
+        s.installer.run(INSTALL).unwrap();
+        std::fs::write(&s.dll, GAME_DLL).unwrap();
+        assert!(matches!(s.installer.run(Operation::Uninstall), Err(Error::NotHachimi(path)) if path == s.dll));
+        assert_eq!(read(&s.dll), GAME_DLL);
+        assert!(s.installer.get_receipt().unwrap().is_some());
+        assert_nothing_pending(&s.installer);
+
+        s.installer.force = true;
+        s.installer.run(Operation::Uninstall).unwrap();
+        assert!(!s.dll.exists());
+        assert!(s.installer.get_receipt().unwrap().is_none());
+    }
+
+    #[test]
+    fn recovers_interrupted_install() {
+        let s = setup();
+        let interrupt = || {
+            let mut tx = s.installer.begin(INSTALL).unwrap();
+            tx.write_file(&s.dll, b"half written").unwrap();
+        };
+
+        interrupt();
+        assert!(matches!(s.installer.run(INSTALL), Err(Error::TransactionPending)));
+
+        // No undoes what the interrupted run did
+        s.fake.prompt.answers.lock().unwrap().push_back(Answer::No);
+        assert!(s.installer.recover_interrupted().unwrap());
+        assert_eq!(read(&s.dll), GAME_DLL);
+        assert_nothing_pending(&s.installer);
+
+        // Yes finishes it
+        interrupt();
+        s.fake.prompt.answers.lock().unwrap().push_back(Answer::Yes);
+        assert!(s.installer.recover_interrupted().unwrap());
+        assert_eq!(read(&s.dll), s.installer.get_hachimi_dll().unwrap());
+        let receipt = s.installer.get_receipt().unwrap().expect("receipt after resuming");
+        // the half written DLL is never taken for the game's
+        assert_eq!(receipt.backups.len(), 1);
+        assert_eq!(read(&receipt.backups[0].backup), GAME_DLL);
+        assert_nothing_pending(&s.installer);
+
+        // Cancel leaves it for later
+        interrupt();
+        s.fake.prompt.answers.lock().unwrap().push_back(Answer::Cancel);
+        assert!(!s.installer.recover_interrupted().unwrap());
+        assert!(s.installer.get_interrupted_transaction().unwrap().is_some());
+
+        let shown = s.fake.prompt.shown.lock().unwrap();
+        assert_eq!(shown.iter().filter(|(id, ..)| id == "interrupted_transaction").count(), 3);
+    }
+
+    #[test]
+    fn refuses_stale_backups_unless_forced() {
+        let mut s = setup();
+        s.installer.run(INSTALL).unwrap();
+        let receipt = s.installer.get_receipt().unwrap().unwrap();
+        let store = s.installer.get_backup_store().unwrap();
+        // as if the backup was made on Steam build 1
+        let mut entry = store.find_by_file(&receipt.backups[0].backup).unwrap().unwrap();
+        entry.steam_build_id = Some(1);
+        std::fs::write(entry.meta_path(), store.meta_json(&entry)).unwrap();
+
+        let uninstall = |installer: &Installer, build_id| {
+            let mut plan = Plan::new(Operation::Uninstall, installer.target, installer.install_dir.clone());
+            let receipt = installer.get_receipt().unwrap().unwrap();
+            installer.uninstall_from_receipt(&mut plan, receipt, build_id).map(|_| plan)
+        };
+        assert!(matches!(uninstall(&s.installer, Some(2)), Err(Error::StaleBackup(_, 1, 2))));
+        assert!(matches!(s.installer.plan_restore(&entry, Some(2)), Err(Error::StaleBackup(_, 1, 2))));
+        assert!(uninstall(&s.installer, Some(1)).unwrap().steps.iter().any(|s| matches!(s, Step::Restore { .. })));
+        assert!(s.installer.plan_restore(&entry, Some(1)).is_ok());
+
+        // forced, the game's current file is left alone instead
+        s.installer.force = true;
+        let plan = uninstall(&s.installer, Some(2)).unwrap();
+        assert!(!plan.steps.iter().any(|step| matches!(step, Step::Restore { .. })));
+        assert!(!plan.steps.iter().any(|step| matches!(step, Step::Remove { path } if *path == s.dll)));
+        assert!(s.installer.plan_restore(&entry, Some(2)).is_ok());
+    }
+
+    #[test]
+    fn prunes_unused_backups_in_a_journal() {
+        let s = setup();
+        s.installer.run(INSTALL).unwrap();
+        let store = s.installer.get_backup_store().unwrap();
+        let in_use = s.installer.get_backups_in_use().unwrap();
+        let unused = store.dir().join("0123456789abcdef");
+        std::fs::create_dir_all(&unused).unwrap();
+        std::fs::write(unused.join("old.dll"), b"old").unwrap();
+
+        // an interrupted prune puts everything back
+        let plan = s.installer.plan(Operation::Prune).unwrap();
+        let mut tx = s.installer.begin(Operation::Prune).unwrap();
+        s.installer.execute_plan(&plan, &mut tx).unwrap();
+        assert!(!unused.exists());
+        tx.rollback().unwrap();
+        assert_eq!(read(&unused.join("old.dll")), b"old");
+
+        s.installer.run(Operation::Prune).unwrap();
+        assert!(!unused.exists());
+        assert!(in_use.iter().all(|f| f.is_file()));
+        assert_nothing_pending(&s.installer);
+
+        // the store goes with the last backup
+        s.installer.run(Operation::Uninstall).unwrap();
+        assert!(!store.dir().exists());
+    }
+}
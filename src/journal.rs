@@ -0,0 +1,476 @@
+use std::{collections::HashMap, fs::File, io::Write, path::{Path, PathBuf}};
+use tinyjson::JsonValue;
+use crate::{installer::{Error, Target}, receipt::Receipt};
+
+const JOURNAL_DIR: &str = "install-journal";
+const JOURNAL_FILE: &str = "journal.json";
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum Operation {
+    Install { pre_install: bool, post_install: bool },
+    Uninstall,
+    Repair,
+    // `update` also replaces an older Hachimi, otherwise any Hachimi will do
+    Ensure { update: bool },
+    // putting a single backup back, only ever planned by Installer::plan_restore
+    Restore,
+    // dropping the backups the current install doesn't need
+    Prune
+}
+
+impl Operation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Install { .. } => "install",
+            Operation::Uninstall => "uninstall",
+            Operation::Repair => "repair",
+            Operation::Ensure { .. } => "ensure",
+            Operation::Restore => "restore",
+            Operation::Prune => "prune"
+        }
+    }
+}
+
+// Every entry is written to disk *before* the change it describes happens,
+// so rollback has to cope with changes that never actually took place.
+enum Entry {
+    // `path` was moved aside to `backup`, or didn't exist (backup = None) and
+    // whatever ends up there belongs to us
+    Stash { path: PathBuf, backup: Option<PathBuf> },
+    Rename { from: PathBuf, to: PathBuf }
+}
+
+pub struct Transaction {
+    dir: PathBuf,
+    pub install_dir: PathBuf,
+    pub operation: Operation,
+    pub target: Target,
+    pub custom_target: Option<String>,
+    // filled in by the install steps as they go, not part of the journal
+    pub receipt: Receipt,
+    entries: Vec<Entry>
+}
+
+impl Transaction {
+    pub fn exists(data_dir: &Path) -> bool {
+        data_dir.join(JOURNAL_DIR).join(JOURNAL_FILE).is_file()
+    }
+
+    pub fn begin(
+        data_dir: &Path, install_dir: PathBuf, operation: Operation, target: Target, custom_target: Option<String>
+    ) -> Result<Transaction, Error> {
+        let dir = data_dir.join(JOURNAL_DIR);
+        if dir.exists() {
+            // leftovers without a journal file, nothing in there can be referenced
+            std::fs::remove_dir_all(&dir)?;
+        }
+        std::fs::create_dir_all(&dir)?;
+
+        let tx = Transaction {
+            dir,
+            install_dir,
+            operation,
+            target,
+            receipt: Receipt::new(target, custom_target.clone()),
+            custom_target,
+            entries: Vec::new()
+        };
+        tx.save()?;
+
+        Ok(tx)
+    }
+
+    pub fn load(data_dir: &Path) -> Result<Option<Transaction>, Error> {
+        let dir = data_dir.join(JOURNAL_DIR);
+        let journal_str = match std::fs::read_to_string(dir.join(JOURNAL_FILE)) {
+            Ok(s) => s,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into())
+        };
+        let JsonValue::Object(journal) = journal_str.parse().map_err(|_| Error::InvalidJournal)? else {
+            return Err(Error::InvalidJournal);
+        };
+
+        let operation = match journal.get("operation") {
+            Some(JsonValue::String(s)) if s == "install" => Operation::Install {
+                pre_install: matches!(journal.get("pre_install"), Some(JsonValue::Boolean(true))),
+                post_install: matches!(journal.get("post_install"), Some(JsonValue::Boolean(true)))
+            },
+            Some(JsonValue::String(s)) if s == "uninstall" => Operation::Uninstall,
+            Some(JsonValue::String(s)) if s == "repair" => Operation::Repair,
+            Some(JsonValue::String(s)) if s == "ensure" => Operation::Ensure {
+                update: matches!(journal.get("update"), Some(JsonValue::Boolean(true)))
+            },
+            Some(JsonValue::String(s)) if s == "restore" => Operation::Restore,
+            Some(JsonValue::String(s)) if s == "prune" => Operation::Prune,
+            _ => return Err(Error::InvalidJournal)
+        };
+        let Some(JsonValue::String(install_dir)) = journal.get("install_dir") else {
+            return Err(Error::InvalidJournal);
+        };
+        let Some(JsonValue::String(target)) = journal.get("target") else {
+            return Err(Error::InvalidJournal);
+        };
+        let target = Target::from_id(target).ok_or(Error::InvalidJournal)?;
+        let custom_target = match journal.get("custom_target") {
+            Some(JsonValue::String(s)) => Some(s.clone()),
+            _ => None
+        };
+
+        let Some(JsonValue::Array(entries_json)) = journal.get("entries") else {
+            return Err(Error::InvalidJournal);
+        };
+        let mut entries = Vec::with_capacity(entries_json.len());
+        for value in entries_json {
+            let JsonValue::Object(entry) = value else {
+                return Err(Error::InvalidJournal);
+            };
+            let get_path = |key: &str| match entry.get(key) {
+                Some(JsonValue::String(s)) => Some(PathBuf::from(s)),
+                _ => None
+            };
+
+            entries.push(if let (Some(from), Some(to)) = (get_path("from"), get_path("to")) {
+                Entry::Rename { from, to }
+            }
+            else {
+                Entry::Stash {
+                    path: get_path("path").ok_or(Error::InvalidJournal)?,
+                    backup: get_path("backup")
+                }
+            });
+        }
+
+        Ok(Some(Transaction {
+            dir,
+            install_dir: install_dir.into(),
+            operation,
+            target,
+            receipt: Receipt::new(target, custom_target.clone()),
+            custom_target,
+            entries
+        }))
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        let path_value = |p: &Path| JsonValue::String(p.to_string_lossy().into_owned());
+
+        let entries = self.entries.iter().map(|entry| {
+            let mut obj = HashMap::new();
+            match entry {
+                Entry::Stash { path, backup } => {
+                    obj.insert("path".to_owned(), path_value(path));
+                    obj.insert("backup".to_owned(), backup.as_deref().map(path_value).unwrap_or(JsonValue::Null));
+                }
+                Entry::Rename { from, to } => {
+                    obj.insert("from".to_owned(), path_value(from));
+                    obj.insert("to".to_owned(), path_value(to));
+                }
+            }
+            JsonValue::Object(obj)
+        }).collect();
+
+        let mut journal = HashMap::new();
+        journal.insert("operation".to_owned(), JsonValue::String(self.operation.name().to_owned()));
+        if let Operation::Install { pre_install, post_install } = self.operation {
+            journal.insert("pre_install".to_owned(), JsonValue::Boolean(pre_install));
+            journal.insert("post_install".to_owned(), JsonValue::Boolean(post_install));
+        }
+        if let Operation::Ensure { update } = self.operation {
+            journal.insert("update".to_owned(), JsonValue::Boolean(update));
+        }
+        journal.insert("install_dir".to_owned(), path_value(&self.install_dir));
+        journal.insert("target".to_owned(), JsonValue::String(self.target.id().to_owned()));
+        journal.insert("custom_target".to_owned(), self.custom_target.clone().map(JsonValue::String).unwrap_or(JsonValue::Null));
+        journal.insert("entries".to_owned(), JsonValue::Array(entries));
+
+        let journal_str = JsonValue::Object(journal).stringify().map_err(|_| Error::InvalidJournal)?;
+
+        // write then rename, a torn journal is worse than a missing entry
+        let journal_path = self.dir.join(JOURNAL_FILE);
+        let tmp_path = journal_path.with_extension("json.tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(journal_str.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, &journal_path)?;
+
+        Ok(())
+    }
+
+    // Move whatever is at `path` out of the way so that it can be put back on rollback
+    fn stash(&mut self, path: &Path) -> Result<(), Error> {
+        let backup = if path.symlink_metadata().is_ok() {
+            Some(self.dir.join(self.entries.len().to_string()))
+        }
+        else {
+            None
+        };
+
+        self.entries.push(Entry::Stash { path: path.to_owned(), backup: backup.clone() });
+        self.save()?;
+
+        if let Some(backup) = backup {
+            std::fs::rename(path, backup)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn create_dir_all(&mut self, path: &Path) -> Result<(), Error> {
+        let mut missing = Vec::new();
+        let mut current = Some(path);
+        while let Some(dir) = current {
+            if dir.exists() {
+                break;
+            }
+            missing.push(dir);
+            current = dir.parent();
+        }
+
+        for dir in missing.into_iter().rev() {
+            self.stash(dir)?;
+            std::fs::create_dir(dir)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_file(&mut self, path: &Path, contents: &[u8]) -> Result<(), Error> {
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(parent)?;
+        }
+        self.stash(path)?;
+
+        let mut file = File::create(path)?;
+        file.write_all(contents)?;
+
+        Ok(())
+    }
+
+    pub fn copy_file(&mut self, from: &Path, to: &Path) -> Result<(), Error> {
+        self.stash(to)?;
+        std::fs::copy(from, to)?;
+        Ok(())
+    }
+
+    pub fn rename(&mut self, from: &Path, to: &Path) -> Result<(), Error> {
+        self.stash(to)?;
+
+        self.entries.push(Entry::Rename { from: from.to_owned(), to: to.to_owned() });
+        self.save()?;
+        std::fs::rename(from, to)?;
+
+        Ok(())
+    }
+
+    pub fn remove_file(&mut self, path: &Path) -> Result<(), Error> {
+        if !path.is_file() {
+            return Err(std::io::Error::from(std::io::ErrorKind::NotFound).into());
+        }
+        self.stash(path)
+    }
+
+    pub fn remove_dir_all(&mut self, path: &Path) -> Result<(), Error> {
+        if !path.is_dir() {
+            return Err(std::io::Error::from(std::io::ErrorKind::NotFound).into());
+        }
+        self.stash(path)
+    }
+
+    // Only removes the dir if it's empty, no error otherwise
+    pub fn remove_empty_dir(&mut self, path: &Path) -> Result<(), Error> {
+        let Ok(mut dir) = std::fs::read_dir(path) else {
+            return Ok(());
+        };
+        if dir.next().is_some() {
+            return Ok(());
+        }
+        self.stash(path)
+    }
+
+    pub fn commit(self) -> Result<(), Error> {
+        std::fs::remove_dir_all(&self.dir)?;
+
+        // don't leave an empty data dir behind just for the journal
+        if let Some(data_dir) = self.dir.parent() {
+            _ = std::fs::remove_dir(data_dir);
+        }
+
+        Ok(())
+    }
+
+    pub fn rollback(mut self) -> Result<(), Error> {
+        while let Some(entry) = self.entries.last() {
+            match entry {
+                Entry::Stash { path, backup } => {
+                    match backup {
+                        // original was never moved, leave it alone
+                        Some(backup) if backup.symlink_metadata().is_err() => {},
+                        Some(backup) => {
+                            remove_path(path)?;
+                            std::fs::rename(backup, path)?;
+                        },
+                        None => remove_path(path)?
+                    }
+                },
+                Entry::Rename { from, to } => {
+                    if to.symlink_metadata().is_ok() && from.symlink_metadata().is_err() {
+                        std::fs::rename(to, from)?;
+                    }
+                }
+            }
+
+            // keep the journal in sync so a failed rollback can be picked up again
+            self.entries.pop();
+            self.save()?;
+        }
+
+        self.commit()
+    }
+}
+
+fn remove_path(path: &Path) -> Result<(), Error> {
+    let Ok(metadata) = path.symlink_metadata() else {
+        return Ok(());
+    };
+
+    if metadata.is_dir() {
+        std::fs::remove_dir_all(path)?;
+    }
+    else {
+        std::fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    struct Setup {
+        _tmp: TempDir,
+        install_dir: PathBuf,
+        data_dir: PathBuf
+    }
+
+    fn setup() -> Setup {
+        let tmp = TempDir::new();
+        let install_dir = tmp.path().join("game");
+        std::fs::create_dir_all(install_dir.join("dir")).unwrap();
+        std::fs::write(install_dir.join("changed.txt"), "original").unwrap();
+        std::fs::write(install_dir.join("removed.txt"), "removed").unwrap();
+        std::fs::write(install_dir.join("moved.txt"), "moved").unwrap();
+        std::fs::write(install_dir.join("dir").join("file.txt"), "in dir").unwrap();
+        let data_dir = install_dir.join("hachimi");
+        Setup { _tmp: tmp, install_dir, data_dir }
+    }
+
+    fn begin(s: &Setup, operation: Operation) -> Transaction {
+        Transaction::begin(&s.data_dir, s.install_dir.clone(), operation, Target::CriManaVpxKomoe, Some("custom.dll".to_owned())).unwrap()
+    }
+
+    // every kind of change there is
+    fn make_changes(s: &Setup, tx: &mut Transaction) {
+        let dir = &s.install_dir;
+        tx.write_file(&dir.join("changed.txt"), b"changed").unwrap();
+        tx.write_file(&dir.join("new").join("sub").join("new.txt"), b"new").unwrap();
+        tx.remove_file(&dir.join("removed.txt")).unwrap();
+        tx.remove_dir_all(&dir.join("dir")).unwrap();
+        tx.rename(&dir.join("moved.txt"), &dir.join("moved_to.txt")).unwrap();
+        tx.copy_file(&dir.join("changed.txt"), &dir.join("copied.txt")).unwrap();
+    }
+
+    fn read(path: PathBuf) -> Option<String> {
+        std::fs::read_to_string(path).ok()
+    }
+
+    fn assert_untouched(s: &Setup) {
+        let dir = &s.install_dir;
+        assert_eq!(read(dir.join("changed.txt")).as_deref(), Some("original"));
+        assert_eq!(read(dir.join("removed.txt")).as_deref(), Some("removed"));
+        assert_eq!(read(dir.join("moved.txt")).as_deref(), Some("moved"));
+        assert_eq!(read(dir.join("dir").join("file.txt")).as_deref(), Some("in dir"));
+        assert!(!dir.join("new").exists());
+        assert!(!dir.join("moved_to.txt").exists());
+        assert!(!dir.join("copied.txt").exists());
+        assert!(!Transaction::exists(&s.data_dir));
+    }
+
+    #[test]
+    fn rollback_undoes_everything() {
+        let s = setup();
+        let mut tx = begin(&s, Operation::Uninstall);
+        make_changes(&s, &mut tx);
+        assert!(Transaction::exists(&s.data_dir));
+
+        tx.rollback().unwrap();
+        assert_untouched(&s);
+        // nothing else was in the data dir, so it goes with the journal
+        assert!(!s.data_dir.exists());
+    }
+
+    #[test]
+    fn commit_keeps_changes() {
+        let s = setup();
+        let mut tx = begin(&s, Operation::Repair);
+        make_changes(&s, &mut tx);
+        tx.commit().unwrap();
+
+        let dir = &s.install_dir;
+        assert_eq!(read(dir.join("changed.txt")).as_deref(), Some("changed"));
+        assert_eq!(read(dir.join("new").join("sub").join("new.txt")).as_deref(), Some("new"));
+        assert_eq!(read(dir.join("moved_to.txt")).as_deref(), Some("moved"));
+        assert_eq!(read(dir.join("copied.txt")).as_deref(), Some("changed"));
+        assert!(!dir.join("removed.txt").exists());
+        assert!(!dir.join("dir").exists());
+        assert!(!Transaction::exists(&s.data_dir));
+    }
+
+    #[test]
+    fn interrupted_transaction_rolls_back_after_load() {
+        let s = setup();
+        let mut tx = begin(&s, Operation::Install { pre_install: true, post_install: false });
+        make_changes(&s, &mut tx);
+        // the process died here
+        drop(tx);
+
+        let tx = Transaction::load(&s.data_dir).unwrap().unwrap();
+        assert!(tx.operation == Operation::Install { pre_install: true, post_install: false });
+        assert!(tx.target == Target::CriManaVpxKomoe);
+        assert_eq!(tx.custom_target.as_deref(), Some("custom.dll"));
+        assert_eq!(tx.install_dir, s.install_dir);
+        tx.rollback().unwrap();
+        assert_untouched(&s);
+    }
+
+    #[test]
+    fn saves_every_operation() {
+        let s = setup();
+        let operations = [
+            Operation::Install { pre_install: false, post_install: true },
+            Operation::Uninstall,
+            Operation::Repair,
+            Operation::Ensure { update: true },
+            Operation::Ensure { update: false },
+            Operation::Restore,
+            Operation::Prune
+        ];
+        for operation in operations {
+            begin(&s, operation);
+            let tx = Transaction::load(&s.data_dir).unwrap().unwrap();
+            assert!(tx.operation == operation, "{} didn't survive a load", operation.name());
+            tx.commit().unwrap();
+        }
+        assert!(Transaction::load(&s.data_dir).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_corrupted_journal() {
+        let s = setup();
+        begin(&s, Operation::Uninstall);
+        std::fs::write(s.data_dir.join(JOURNAL_DIR).join(JOURNAL_FILE), "{\"operation\": \"explode\"}").unwrap();
+        assert!(matches!(Transaction::load(&s.data_dir), Err(Error::InvalidJournal)));
+    }
+}
@@ -0,0 +1,28 @@
+// Install logic, free of any direct Windows calls; see platform for the OS side
+pub mod backup;
+#[cfg(feature = "net_install")]
+pub mod cache;
+#[cfg(feature = "net_install")]
+pub mod download;
+pub mod exe_patch;
+pub mod game;
+pub mod i18n;
+pub mod installer;
+pub mod journal;
+pub mod plan;
+pub mod platform;
+pub mod receipt;
+#[cfg(feature = "net_install")]
+pub mod release;
+pub mod status;
+#[cfg(test)]
+mod test_utils;
+pub mod utils;
+pub mod verify;
+pub mod version;
+
+pub use crate::i18n::_rust_i18n_translate;
+
+#[cfg(feature = "compress_bin")]
+#[macro_use]
+extern crate include_bytes_zstd;
@@ -1,29 +1,44 @@
 #![windows_subsystem = "windows"]
 
-mod i18n;
-mod installer;
+// The frontends are Windows only, everything else lives in the library
+#[cfg(windows)]
+use hachimi_installer::{game, i18n, installer, journal, plan, platform, status, utils, verify};
+#[cfg(all(windows, feature = "net_install"))]
+use hachimi_installer::{cache, download, release};
+
+#[cfg(windows)]
 mod resource;
-mod utils;
+#[cfg(windows)]
+mod dialog;
+#[cfg(windows)]
+mod console;
+#[cfg(windows)]
 mod cli;
+#[cfg(windows)]
 mod gui;
 
-pub use crate::i18n::_rust_i18n_translate;
-
-#[cfg(feature = "compress_bin")]
-#[macro_use]
-extern crate include_bytes_zstd;
+pub use hachimi_installer::_rust_i18n_translate;
 
-fn main() -> Result<(), installer::Error> {
+#[cfg(windows)]
+fn main() {
     // Set language by system language
     i18n::init_locale();
 
     // Command line interface / Unattended mode
-    if cli::run()? { return Ok(()); }
+    match cli::run() {
+        Ok(true) => return,
+        Ok(false) => {},
+        Err(e) => std::process::exit(e.exit_code())
+    }
 
     // GUI mode (no arguments)
     if let Err(e) = gui::run() {
         e.code().unwrap();
     }
+}
 
-    Ok(())
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("Hachimi Installer only runs on Windows");
+    std::process::exit(1);
 }
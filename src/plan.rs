@@ -0,0 +1,241 @@
+use std::{collections::HashMap, path::{Path, PathBuf}};
+use tinyjson::JsonValue;
+use crate::{backup::BackupEntry, installer::Target, journal::Operation};
+
+// Bytes that get written to disk, along with where they came from
+pub struct Source {
+    pub name: String,
+    pub bytes: Vec<u8>
+}
+
+pub enum Step {
+    CreateDir { path: PathBuf },
+    WriteFile { source: Source, dest: PathBuf, sha256: String },
+    // already has the contents we'd write, only goes into the receipt
+    KeepFile { path: PathBuf, sha256: String },
+    // copy into the backup store, an existing backup is never overwritten
+    Backup { source: PathBuf, backup: BackupEntry, exists: bool },
+    PatchExe { source: PathBuf, dest: PathBuf, source_sha256: String, patch: &'static [u8], sha256: String },
+    Remove { path: PathBuf },
+    RemoveDir { path: PathBuf, only_if_empty: bool },
+    // moves the backup back, or copies it if it stays in the store (`keep_backup`)
+    Restore { backup: PathBuf, dest: PathBuf, sha256: Option<String>, keep_backup: bool },
+    // data = None deletes the value. On uninstall it's the value from before the install
+    SetRegistryValue { key: String, value: String, data: Option<u32> },
+    WriteReceipt { dest: PathBuf, payload_version: Option<String> }
+}
+
+impl Step {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Step::CreateDir { .. } => "create_dir",
+            Step::WriteFile { .. } => "write_file",
+            Step::KeepFile { .. } => "keep_file",
+            Step::Backup { .. } => "backup",
+            Step::PatchExe { .. } => "patch_exe",
+            Step::Remove { .. } => "remove",
+            Step::RemoveDir { .. } => "remove_dir",
+            Step::Restore { .. } => "restore",
+            Step::SetRegistryValue { .. } => "set_registry_value",
+            Step::WriteReceipt { .. } => "write_receipt"
+        }
+    }
+
+    fn describe(&self) -> String {
+        let p = |path: &Path| path.display().to_string();
+        let hash = |sha256: &Option<String>| sha256.as_deref().map(|h| format!(" [sha256 {}]", h)).unwrap_or_default();
+
+        match self {
+            Step::CreateDir { path } => format!("create dir   {}", p(path)),
+            Step::WriteFile { source, dest, sha256 } =>
+                format!("write file   {} <- {} [sha256 {}]", p(dest), source.name, sha256),
+            Step::KeepFile { path, sha256 } => format!("keep file    {} [sha256 {}] (already up to date)", p(path), sha256),
+            Step::Backup { source, backup, exists } => format!(
+                "back up      {} -> {} [sha256 {}]{}",
+                p(source), p(&backup.file), backup.sha256, if *exists { " (existing backup kept)" } else { "" }
+            ),
+            Step::PatchExe { source, dest, source_sha256, sha256, .. } =>
+                format!("patch exe    {} [sha256 {}] -> {} [sha256 {}]", p(source), source_sha256, p(dest), sha256),
+            Step::Remove { path } => format!("remove       {}", p(path)),
+            Step::RemoveDir { path, only_if_empty } => format!(
+                "remove dir   {}{}", p(path), if *only_if_empty { " (if empty)" } else { "" }
+            ),
+            Step::Restore { backup, dest, sha256, keep_backup } => format!(
+                "restore      {} <- {}{}{}",
+                p(dest), p(backup), hash(sha256), if *keep_backup { " (backup kept)" } else { "" }
+            ),
+            Step::SetRegistryValue { key, value, data } => match data {
+                Some(data) => format!("set registry {}\\{} = {}", key, value, data),
+                None => format!("del registry {}\\{}", key, value)
+            },
+            Step::WriteReceipt { dest, .. } => format!("write file   {} <- install receipt", p(dest))
+        }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let p = |path: &Path| JsonValue::String(path.to_string_lossy().into_owned());
+        let s = |s: &str| JsonValue::String(s.to_owned());
+        let opt = |s: &Option<String>| s.clone().map(JsonValue::String).unwrap_or(JsonValue::Null);
+
+        let mut fields: Vec<(&str, JsonValue)> = vec![("op", s(self.kind()))];
+        match self {
+            Step::CreateDir { path } => fields.push(("dest", p(path))),
+            Step::WriteFile { source, dest, sha256 } => {
+                fields.push(("source", s(&source.name)));
+                fields.push(("dest", p(dest)));
+                fields.push(("sha256", s(sha256)));
+            },
+            Step::KeepFile { path, sha256 } => {
+                fields.push(("dest", p(path)));
+                fields.push(("sha256", s(sha256)));
+            },
+            Step::Backup { source, backup, exists } => {
+                fields.push(("source", p(source)));
+                fields.push(("dest", p(&backup.file)));
+                fields.push(("sha256", s(&backup.sha256)));
+                fields.push(("exists", JsonValue::Boolean(*exists)));
+                fields.push(("build", opt(&backup.build)));
+            },
+            Step::PatchExe { source, dest, source_sha256, sha256, .. } => {
+                fields.push(("source", p(source)));
+                fields.push(("source_sha256", s(source_sha256)));
+                fields.push(("dest", p(dest)));
+                fields.push(("sha256", s(sha256)));
+            },
+            Step::Remove { path } => fields.push(("dest", p(path))),
+            Step::RemoveDir { path, only_if_empty } => {
+                fields.push(("dest", p(path)));
+                fields.push(("only_if_empty", JsonValue::Boolean(*only_if_empty)));
+            },
+            Step::Restore { backup, dest, sha256, keep_backup } => {
+                fields.push(("source", p(backup)));
+                fields.push(("dest", p(dest)));
+                fields.push(("sha256", opt(sha256)));
+                fields.push(("keep_backup", JsonValue::Boolean(*keep_backup)));
+            },
+            Step::SetRegistryValue { key, value, data } => {
+                fields.push(("dest", JsonValue::String(format!("{}\\{}", key, value))));
+                fields.push(("data", data.map(|d| JsonValue::Number(d as f64)).unwrap_or(JsonValue::Null)));
+            },
+            Step::WriteReceipt { dest, .. } => {
+                fields.push(("source", s("install receipt")));
+                fields.push(("dest", p(dest)));
+            }
+        }
+
+        JsonValue::Object(fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+    }
+}
+
+pub struct Plan {
+    pub operation: Operation,
+    pub target: Target,
+    pub install_dir: Option<PathBuf>,
+    // things worth telling the user that aren't steps themselves
+    pub notes: Vec<String>,
+    pub steps: Vec<Step>
+}
+
+impl Plan {
+    pub fn new(operation: Operation, target: Target, install_dir: Option<PathBuf>) -> Plan {
+        Plan {
+            operation,
+            target,
+            install_dir,
+            notes: Vec::new(),
+            steps: Vec::new()
+        }
+    }
+
+    pub fn push(&mut self, step: Step) {
+        self.steps.push(step);
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "{} {} ({}) in {}\n",
+            self.operation.name(),
+            self.target.id(),
+            self.target.dll_name(),
+            self.install_dir.as_deref().map(|p| p.display().to_string()).unwrap_or_else(|| "?".to_owned())
+        );
+        for note in &self.notes {
+            text.push_str(&format!("  {}\n", note));
+        }
+        if self.steps.is_empty() {
+            text.push_str("  nothing to do\n");
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            text.push_str(&format!("{:>3}. {}\n", i + 1, step.describe()));
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut root = HashMap::new();
+        root.insert("operation".to_owned(), JsonValue::String(self.operation.name().to_owned()));
+        root.insert("target".to_owned(), JsonValue::String(self.target.id().to_owned()));
+        root.insert("install_dir".to_owned(), self.install_dir.as_deref()
+            .map(|p| JsonValue::String(p.to_string_lossy().into_owned()))
+            .unwrap_or(JsonValue::Null)
+        );
+        root.insert("notes".to_owned(), JsonValue::Array(
+            self.notes.iter().map(|n| JsonValue::String(n.clone())).collect()
+        ));
+        root.insert("steps".to_owned(), JsonValue::Array(self.steps.iter().map(Step::to_json).collect()));
+
+        // only strings, numbers and bools in here so this can't fail
+        JsonValue::Object(root).format().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan() -> Plan {
+        let dir = PathBuf::from("game");
+        let mut plan = Plan::new(Operation::Uninstall, Target::CriManaVpxKomoe, Some(dir.clone()));
+        plan.notes.push("a note".to_owned());
+        plan.push(Step::Remove { path: dir.join("cri_mana_vpx.dll") });
+        plan.push(Step::Restore {
+            backup: dir.join("backup.dll"),
+            dest: dir.join("cri_mana_vpx.dll"),
+            sha256: None,
+            keep_backup: false
+        });
+        plan.push(Step::SetRegistryValue { key: "HKCU\\Key".to_owned(), value: "Value".to_owned(), data: Some(1) });
+        plan
+    }
+
+    #[test]
+    fn lists_steps_in_order() {
+        let text = plan().to_text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("uninstall "));
+        assert_eq!(lines[1], "  a note");
+        assert!(lines[2].starts_with("  1. remove "));
+        assert!(lines[3].starts_with("  2. restore "));
+        assert_eq!(lines[4], "  3. set registry HKCU\\Key\\Value = 1");
+
+        let empty = Plan::new(Operation::Repair, Target::UnityPlayer, None);
+        assert!(empty.to_text().ends_with(" in ?\n  nothing to do\n"));
+    }
+
+    #[test]
+    fn json_has_every_step() {
+        let JsonValue::Object(root) = plan().to_json().parse().unwrap() else {
+            panic!("plan json is not an object");
+        };
+        assert_eq!(root["operation"], JsonValue::String("uninstall".to_owned()));
+        let JsonValue::Array(steps) = &root["steps"] else {
+            panic!("no steps");
+        };
+        let ops: Vec<&JsonValue> = steps.iter().map(|s| &s["op"]).collect();
+        assert_eq!(ops, ["remove", "restore", "set_registry_value"].map(|s| JsonValue::String(s.to_owned())).iter().collect::<Vec<_>>());
+        assert_eq!(steps[1]["sha256"], JsonValue::Null);
+        assert_eq!(steps[1]["keep_backup"], JsonValue::Boolean(false));
+        assert_eq!(steps[2]["data"], JsonValue::Number(1.0));
+    }
+}
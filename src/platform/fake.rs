@@ -0,0 +1,175 @@
+// In-memory stand-ins for the platform traits, for running the install logic off Windows
+use std::{collections::{HashMap, VecDeque}, path::PathBuf, sync::{Arc, Mutex}};
+use super::{Answer, Buttons, Elevation, KnownFolder, KnownFolders, Level, Platform, Processes, Prompt, Registry, RegistryError};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RegistryData {
+    U32(u32),
+    String(String)
+}
+
+// Keys are compared case insensitively like the real thing
+#[derive(Clone, Default)]
+pub struct FakeRegistry {
+    pub values: Arc<Mutex<HashMap<(String, String), RegistryData>>>,
+    // keys that fail to open, to simulate missing permissions
+    pub locked_keys: Arc<Mutex<Vec<String>>>
+}
+
+impl FakeRegistry {
+    fn entry(key: &str, value: &str) -> (String, String) {
+        (key.to_ascii_lowercase(), value.to_ascii_lowercase())
+    }
+
+    pub fn set(&self, key: &str, value: &str, data: RegistryData) {
+        self.values.lock().unwrap().insert(Self::entry(key, value), data);
+    }
+
+    pub fn get(&self, key: &str, value: &str) -> Option<RegistryData> {
+        self.values.lock().unwrap().get(&Self::entry(key, value)).cloned()
+    }
+
+    fn check_access(&self, key: &str) -> Result<(), RegistryError> {
+        if self.locked_keys.lock().unwrap().iter().any(|k| k.eq_ignore_ascii_case(key)) {
+            return Err(RegistryError::OpenKey("access denied".to_owned()));
+        }
+        Ok(())
+    }
+}
+
+impl Registry for FakeRegistry {
+    fn get_u32(&self, key: &str, value: &str) -> Option<u32> {
+        match self.get(key, value)? {
+            RegistryData::U32(v) => Some(v),
+            _ => None
+        }
+    }
+
+    fn get_string(&self, key: &str, value: &str) -> Option<String> {
+        match self.get(key, value)? {
+            RegistryData::String(s) => Some(s),
+            _ => None
+        }
+    }
+
+    fn set_u32(&self, key: &str, value: &str, data: u32) -> Result<(), RegistryError> {
+        self.check_access(key)?;
+        self.set(key, value, RegistryData::U32(data));
+        Ok(())
+    }
+
+    fn delete_value(&self, key: &str, value: &str) -> Result<(), RegistryError> {
+        self.check_access(key)?;
+        self.values.lock().unwrap().remove(&Self::entry(key, value))
+            .map(|_| ())
+            .ok_or_else(|| RegistryError::Value("value not found".to_owned()))
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct FakeKnownFolders {
+    pub folders: Arc<Mutex<HashMap<KnownFolder, PathBuf>>>
+}
+
+impl KnownFolders for FakeKnownFolders {
+    fn get(&self, folder: KnownFolder) -> Option<PathBuf> {
+        self.folders.lock().unwrap().get(&folder).cloned()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct FakeProcesses {
+    pub running: Arc<Mutex<Vec<String>>>
+}
+
+impl Processes for FakeProcesses {
+    fn list(&self) -> Vec<String> {
+        self.running.lock().unwrap().clone()
+    }
+}
+
+// Runs the elevated command against the fake registry right away, the way
+// --enable-dotlocal/--disable-dotlocal/--restore-dotlocal would in the elevated process
+#[derive(Clone, Default)]
+pub struct FakeElevation {
+    pub registry: FakeRegistry,
+    // user declined the UAC prompt
+    pub denied: bool,
+    pub runs: Arc<Mutex<Vec<String>>>
+}
+
+impl Elevation for FakeElevation {
+    fn run_elevated(&self, args: &str) -> bool {
+        self.runs.lock().unwrap().push(args.to_owned());
+        if self.denied {
+            return false;
+        }
+
+        let platform = FakePlatform { registry: self.registry.clone(), ..Default::default() }.build();
+        match args.split_once(' ').unwrap_or((args, "")) {
+            ("--enable-dotlocal", _) => crate::installer::enable_dotlocal(&platform),
+            ("--disable-dotlocal", _) => crate::installer::disable_dotlocal(&platform),
+            ("--restore-dotlocal", data) => crate::installer::restore_dotlocal(&platform, data.parse().ok()),
+            _ => {}
+        }
+        true
+    }
+}
+
+// (id, message, level, buttons)
+pub type ShownPrompt = (String, String, Level, Buttons);
+
+// Answers questions from a queue, falling back to `default` once it runs out
+#[derive(Clone)]
+pub struct FakePrompt {
+    pub answers: Arc<Mutex<VecDeque<Answer>>>,
+    pub default: Answer,
+    // everything that was shown
+    pub shown: Arc<Mutex<Vec<ShownPrompt>>>
+}
+
+impl Default for FakePrompt {
+    fn default() -> Self {
+        FakePrompt {
+            answers: Default::default(),
+            default: Answer::Yes,
+            shown: Default::default()
+        }
+    }
+}
+
+impl Prompt for FakePrompt {
+    fn ask(&self, id: &str, message: &str, _title: &str, level: Level, buttons: Buttons) -> Answer {
+        self.shown.lock().unwrap().push((id.to_owned(), message.to_owned(), level, buttons));
+        if buttons == Buttons::Ok {
+            return Answer::Yes;
+        }
+        self.answers.lock().unwrap().pop_front().unwrap_or(self.default).fit(buttons)
+    }
+}
+
+// Keeps handles to the fakes so they can be inspected after being boxed into a Platform
+#[derive(Clone, Default)]
+pub struct FakePlatform {
+    pub registry: FakeRegistry,
+    pub known_folders: FakeKnownFolders,
+    pub processes: FakeProcesses,
+    pub elevation: FakeElevation,
+    pub prompt: FakePrompt
+}
+
+impl FakePlatform {
+    pub fn build(&self) -> Platform {
+        // elevated runs should see the same registry
+        let mut elevation = self.elevation.clone();
+        elevation.registry = self.registry.clone();
+
+        Platform {
+            registry: Box::new(self.registry.clone()),
+            known_folders: Box::new(self.known_folders.clone()),
+            processes: Box::new(self.processes.clone()),
+            elevation: Box::new(elevation),
+            prompt: Box::new(self.prompt.clone())
+        }
+    }
+}
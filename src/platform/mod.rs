@@ -0,0 +1,123 @@
+// Everything the installer needs from the OS goes through here, so the
+// install logic itself builds (and can be exercised with the fakes) anywhere
+use std::path::PathBuf;
+
+#[cfg(test)]
+pub mod fake;
+pub mod prompt;
+#[cfg(windows)]
+pub mod windows;
+
+// Processes that mean the game is running
+pub const GAME_EXE_NAMES: &[&str] = &["umamusume.exe", "UmamusumePrettyDerby_Jpn.exe", "UmamusumePrettyDerby.exe"];
+
+#[derive(Debug)]
+pub enum RegistryError {
+    // the key itself couldn't be opened
+    OpenKey(String),
+    Value(String)
+}
+
+impl std::fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistryError::OpenKey(e) => write!(f, "{}", e),
+            RegistryError::Value(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+// Keys are full paths starting with the hive, e.g. HKLM\SOFTWARE\...
+pub trait Registry {
+    fn get_u32(&self, key: &str, value: &str) -> Option<u32>;
+    fn get_string(&self, key: &str, value: &str) -> Option<String>;
+    fn set_u32(&self, key: &str, value: &str, data: u32) -> Result<(), RegistryError>;
+    fn delete_value(&self, key: &str, value: &str) -> Result<(), RegistryError>;
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub enum KnownFolder {
+    RoamingAppData,
+    LocalAppData
+}
+
+pub trait KnownFolders {
+    fn get(&self, folder: KnownFolder) -> Option<PathBuf>;
+}
+
+pub trait Processes {
+    // exe names of everything that's running
+    fn list(&self) -> Vec<String>;
+}
+
+pub trait Elevation {
+    // Runs this installer again as admin with `args` and waits for it to exit, false if it couldn't be started
+    fn run_elevated(&self, args: &str) -> bool;
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Level {
+    Info,
+    Warning,
+    Error
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Buttons {
+    Ok,
+    OkCancel,
+    YesNo,
+    YesNoCancel
+}
+
+// OK counts as Yes
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Answer {
+    Yes,
+    No,
+    Cancel
+}
+
+impl Answer {
+    pub fn parse(s: &str) -> Option<Answer> {
+        match s.to_ascii_lowercase().as_str() {
+            "yes" | "y" | "ok" | "o" | "true" => Some(Answer::Yes),
+            "no" | "n" | "false" => Some(Answer::No),
+            "cancel" | "c" => Some(Answer::Cancel),
+            _ => None
+        }
+    }
+
+    // Closest answer the buttons actually allow
+    pub fn fit(self, buttons: Buttons) -> Answer {
+        match (buttons, self) {
+            (Buttons::Ok, _) => Answer::Yes,
+            (Buttons::OkCancel, Answer::No) => Answer::Cancel,
+            (Buttons::YesNo, Answer::Cancel) => Answer::No,
+            (_, answer) => answer
+        }
+    }
+}
+
+// Every question and notice has a stable `id`, so unattended runs can answer them ahead of time
+pub trait Prompt {
+    fn ask(&self, id: &str, message: &str, title: &str, level: Level, buttons: Buttons) -> Answer;
+}
+
+pub struct Platform {
+    pub registry: Box<dyn Registry>,
+    pub known_folders: Box<dyn KnownFolders>,
+    pub processes: Box<dyn Processes>,
+    pub elevation: Box<dyn Elevation>,
+    pub prompt: Box<dyn Prompt>
+}
+
+impl Platform {
+    pub fn notify(&self, id: &str, message: &str, title: &str, level: Level) {
+        self.prompt.ask(id, message, title, level, Buttons::Ok);
+    }
+
+    pub fn is_game_running(&self) -> bool {
+        self.processes.list().iter().any(|p| GAME_EXE_NAMES.iter().any(|n| p.eq_ignore_ascii_case(n)))
+    }
+}
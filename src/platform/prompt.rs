@@ -0,0 +1,105 @@
+// Prompt implementations that don't need a window
+use std::{collections::HashMap, io::{BufRead, Write}, path::Path};
+use tinyjson::JsonValue;
+use super::{Answer, Buttons, Level, Prompt};
+
+// Asks on the console, for when there's one attached
+pub struct ConsolePrompt {
+    // only errors get through, questions are still asked
+    pub quiet: bool
+}
+
+impl Prompt for ConsolePrompt {
+    fn ask(&self, _id: &str, message: &str, title: &str, level: Level, buttons: Buttons) -> Answer {
+        if self.quiet && buttons == Buttons::Ok && level != Level::Error {
+            return Answer::Yes;
+        }
+        let prefix = match level {
+            Level::Info => "",
+            Level::Warning => "warning: ",
+            Level::Error => "error: "
+        };
+        eprintln!("{}{}: {}", prefix, title, message);
+
+        let choices = match buttons {
+            Buttons::Ok => return Answer::Yes,
+            Buttons::OkCancel => "[o]k/[c]ancel",
+            Buttons::YesNo => "[y]es/[n]o",
+            Buttons::YesNoCancel => "[y]es/[n]o/[c]ancel"
+        };
+
+        let stdin = std::io::stdin();
+        loop {
+            eprint!("{} ", choices);
+            _ = std::io::stderr().flush();
+
+            let mut line = String::new();
+            // nobody's going to answer, take the safe way out
+            if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+                return Answer::Cancel.fit(buttons);
+            }
+            if let Some(answer) = Answer::parse(line.trim()) {
+                return answer.fit(buttons);
+            }
+        }
+    }
+}
+
+// Answers from --assume-yes/--assume-no and an answers file, anything not
+// covered goes to `fallback`
+pub struct PolicyPrompt {
+    pub answers: HashMap<String, Answer>,
+    pub default: Option<Answer>,
+    pub fallback: Box<dyn Prompt>,
+    // don't log answers, errors are still shown
+    pub quiet: bool
+}
+
+impl PolicyPrompt {
+    // A JSON object mapping prompt IDs to "yes", "no" or "cancel"
+    pub fn load_answers(path: &Path) -> Result<HashMap<String, Answer>, String> {
+        let answers_str = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let JsonValue::Object(obj) = answers_str.parse().map_err(|e: tinyjson::JsonParseError| e.to_string())? else {
+            return Err("expected a JSON object".to_owned());
+        };
+
+        let mut answers = HashMap::new();
+        for (id, value) in obj {
+            let answer = match &value {
+                JsonValue::String(s) => Answer::parse(s),
+                JsonValue::Boolean(b) => Some(if *b { Answer::Yes } else { Answer::No }),
+                _ => None
+            }.ok_or_else(|| format!("invalid answer for {}", id))?;
+            answers.insert(id, answer);
+        }
+
+        Ok(answers)
+    }
+
+    // Scripted runs shouldn't stop on a notice nobody is there to dismiss
+    fn is_unattended(&self) -> bool {
+        self.default.is_some() || !self.answers.is_empty()
+    }
+}
+
+impl Prompt for PolicyPrompt {
+    fn ask(&self, id: &str, message: &str, title: &str, level: Level, buttons: Buttons) -> Answer {
+        let answer = self.answers.get(id).copied().or(self.default);
+        match answer {
+            Some(answer) => {
+                // nobody's there to click OK, leave it in the log instead
+                if !self.quiet || level == Level::Error {
+                    eprintln!("[{}] {}: {} -> {:?}", id, title, message, answer.fit(buttons));
+                }
+                answer.fit(buttons)
+            },
+            None if buttons == Buttons::Ok && self.is_unattended() => {
+                if !self.quiet || level == Level::Error {
+                    eprintln!("[{}] {}: {}", id, title, message);
+                }
+                Answer::Yes
+            },
+            None => self.fallback.ask(id, message, title, level, buttons)
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use std::{env, ffi::CStr, path::PathBuf, sync::{Arc, Mutex}};
+use registry::Hive;
+use windows::{
+    core::{w, GUID, HSTRING, PCWSTR},
+    Win32::{
+        Foundation::{CloseHandle, HWND},
+        System::{
+            Diagnostics::ToolHelp::{
+                CreateToolhelp32Snapshot, Process32First, Process32Next, PROCESSENTRY32, TH32CS_SNAPALL
+            },
+            Threading::{WaitForSingleObject, INFINITE}
+        },
+        UI::{
+            Shell::{
+                FOLDERID_LocalAppData, FOLDERID_RoamingAppData, SHGetKnownFolderPath, ShellExecuteExW, KF_FLAG_DEFAULT,
+                SEE_MASK_NOCLOSEPROCESS, SHELLEXECUTEINFOW
+            },
+            WindowsAndMessaging::{
+                MessageBoxW, IDNO, IDOK, IDYES, MB_ICONERROR, MB_ICONINFORMATION, MB_ICONWARNING, MB_OK,
+                MB_OKCANCEL, MB_YESNO, MB_YESNOCANCEL, SW_SHOWNORMAL
+            }
+        }
+    }
+};
+use super::{Answer, Buttons, Elevation, KnownFolder, KnownFolders, Level, Platform, Processes, Prompt, Registry, RegistryError};
+
+// The window dialogs should be modal to, set once the GUI is up
+#[derive(Clone, Default)]
+pub struct ParentWindow(Arc<Mutex<Option<HWND>>>);
+
+impl ParentWindow {
+    pub fn set(&self, hwnd: HWND) {
+        *self.0.lock().unwrap() = Some(hwnd);
+    }
+
+    pub fn get(&self) -> Option<HWND> {
+        *self.0.lock().unwrap()
+    }
+}
+
+pub struct WindowsRegistry;
+
+impl WindowsRegistry {
+    fn split_key(key: &str) -> Option<(Hive, &str)> {
+        let (hive, path) = key.split_once('\\')?;
+        let hive = match hive {
+            "HKLM" | "HKEY_LOCAL_MACHINE" => Hive::LocalMachine,
+            "HKCU" | "HKEY_CURRENT_USER" => Hive::CurrentUser,
+            _ => return None
+        };
+        Some((hive, path))
+    }
+
+    fn open(key: &str, security: registry::Security) -> Result<registry::RegKey, RegistryError> {
+        let (hive, path) = Self::split_key(key).ok_or_else(|| RegistryError::OpenKey(format!("invalid key: {}", key)))?;
+        hive.open(path, security).map_err(|e| RegistryError::OpenKey(e.to_string()))
+    }
+}
+
+impl Registry for WindowsRegistry {
+    fn get_u32(&self, key: &str, value: &str) -> Option<u32> {
+        match Self::open(key, registry::Security::Read).ok()?.value(value).ok()? {
+            registry::Data::U32(v) => Some(v),
+            _ => None
+        }
+    }
+
+    fn get_string(&self, key: &str, value: &str) -> Option<String> {
+        match Self::open(key, registry::Security::Read).ok()?.value(value).ok()? {
+            // REG_SZ
+            registry::Data::String(v) => Some(v.to_os_string().to_string_lossy().into_owned()),
+            _ => None
+        }
+    }
+
+    fn set_u32(&self, key: &str, value: &str, data: u32) -> Result<(), RegistryError> {
+        Self::open(key, registry::Security::Read | registry::Security::SetValue)?
+            .set_value(value, &registry::Data::U32(data))
+            .map_err(|e| RegistryError::Value(e.to_string()))
+    }
+
+    fn delete_value(&self, key: &str, value: &str) -> Result<(), RegistryError> {
+        Self::open(key, registry::Security::Read | registry::Security::SetValue)?
+            .delete_value(value)
+            .map_err(|e| RegistryError::Value(e.to_string()))
+    }
+}
+
+pub struct WindowsKnownFolders;
+
+impl KnownFolders for WindowsKnownFolders {
+    fn get(&self, folder: KnownFolder) -> Option<PathBuf> {
+        let id: &GUID = match folder {
+            KnownFolder::RoamingAppData => &FOLDERID_RoamingAppData,
+            KnownFolder::LocalAppData => &FOLDERID_LocalAppData
+        };
+        let path_wstr = unsafe { SHGetKnownFolderPath(id, KF_FLAG_DEFAULT, None).ok()? };
+        let path_str = unsafe { path_wstr.to_string().ok()? };
+        Some(PathBuf::from(path_str))
+    }
+}
+
+pub struct WindowsProcesses;
+
+impl Processes for WindowsProcesses {
+    fn list(&self) -> Vec<String> {
+        let mut processes = Vec::new();
+        let Ok(snapshot) = (unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPALL, 0) }) else {
+            return processes;
+        };
+        let mut entry = PROCESSENTRY32 {
+            dwSize: std::mem::size_of::<PROCESSENTRY32>() as u32,
+            ..Default::default()
+        };
+        let mut res = unsafe { Process32First(snapshot, &mut entry) };
+
+        while res.is_ok() {
+            let process_name = unsafe { CStr::from_ptr(entry.szExeFile.as_ptr()) };
+            processes.push(process_name.to_string_lossy().into_owned());
+
+            res = unsafe { Process32Next(snapshot, &mut entry) };
+        }
+
+        processes
+    }
+}
+
+pub struct WindowsElevation {
+    pub parent: ParentWindow,
+    // appended to every elevated run, e.g. the prompt policy; one argument each, quoted as needed
+    pub extra_args: Vec<String>
+}
+
+// So CommandLineToArgvW gives back exactly `arg`
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+        return arg.to_owned();
+    }
+
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // backslashes before a quote are escapes, and so is the one added for the quote
+                quoted.extend(std::iter::repeat_n('\\', backslashes * 2 + 1));
+                backslashes = 0;
+            },
+            _ => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes));
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            quoted.push(c);
+        }
+    }
+    // the closing quote would escape trailing backslashes otherwise
+    quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+impl Elevation for WindowsElevation {
+    fn run_elevated(&self, args: &str) -> bool {
+        let Ok(exe_path) = env::current_exe() else {
+            return false;
+        };
+        let args = std::iter::once(args.to_owned())
+            .chain(self.extra_args.iter().map(|arg| quote_arg(arg)))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let exe_path = HSTRING::from(exe_path.to_string_lossy().as_ref());
+        let args = HSTRING::from(args.as_str());
+        let mut info = SHELLEXECUTEINFOW {
+            cbSize: std::mem::size_of::<SHELLEXECUTEINFOW>() as u32,
+            // keep the process handle to wait on
+            fMask: SEE_MASK_NOCLOSEPROCESS,
+            hwnd: self.parent.get().unwrap_or_default(),
+            lpVerb: w!("runas"),
+            lpFile: PCWSTR(exe_path.as_ptr()),
+            lpParameters: PCWSTR(args.as_ptr()),
+            nShow: SW_SHOWNORMAL.0,
+            ..Default::default()
+        };
+
+        // only having started it says nothing about what it did, the caller checks that
+        unsafe {
+            if ShellExecuteExW(&mut info).is_err() || info.hProcess.is_invalid() {
+                return false;
+            }
+            WaitForSingleObject(info.hProcess, INFINITE);
+            _ = CloseHandle(info.hProcess);
+        }
+        true
+    }
+}
+
+pub struct MessageBoxPrompt {
+    pub parent: ParentWindow
+}
+
+impl Prompt for MessageBoxPrompt {
+    fn ask(&self, _id: &str, message: &str, title: &str, level: Level, buttons: Buttons) -> Answer {
+        let icon = match level {
+            Level::Info => MB_ICONINFORMATION,
+            Level::Warning => MB_ICONWARNING,
+            Level::Error => MB_ICONERROR
+        };
+        let buttons = match buttons {
+            Buttons::Ok => MB_OK,
+            Buttons::OkCancel => MB_OKCANCEL,
+            Buttons::YesNo => MB_YESNO,
+            Buttons::YesNoCancel => MB_YESNOCANCEL
+        };
+
+        let res = unsafe {
+            MessageBoxW(self.parent.get().as_ref(), &HSTRING::from(message), &HSTRING::from(title), icon | buttons)
+        };
+        if res == IDYES || res == IDOK {
+            Answer::Yes
+        }
+        else if res == IDNO {
+            Answer::No
+        }
+        else {
+            Answer::Cancel
+        }
+    }
+}
+
+pub fn platform(parent: ParentWindow) -> Platform {
+    Platform {
+        registry: Box::new(WindowsRegistry),
+        known_folders: Box::new(WindowsKnownFolders),
+        processes: Box::new(WindowsProcesses),
+        elevation: Box::new(WindowsElevation { parent: parent.clone(), extra_args: Vec::new() }),
+        prompt: Box::new(MessageBoxPrompt { parent })
+    }
+}
@@ -0,0 +1,292 @@
+use std::{collections::HashMap, path::{Path, PathBuf}};
+use tinyjson::JsonValue;
+use crate::installer::{Error, Target};
+
+pub const RECEIPT_FILE: &str = "install-receipt.json";
+
+pub struct ReceiptFile {
+    pub path: PathBuf,
+    pub sha256: String
+}
+
+pub struct ReceiptBackup {
+    pub path: PathBuf,
+    pub backup: PathBuf,
+    pub sha256: String
+}
+
+pub struct RegistryChange {
+    pub key: String,
+    pub value: String,
+    pub previous: Option<u32>
+}
+
+// Everything an install put on the system, so uninstall doesn't have to guess
+pub struct Receipt {
+    pub target: Target,
+    pub custom_target: Option<String>,
+    pub payload_version: Option<String>,
+    pub files: Vec<ReceiptFile>,
+    pub dirs: Vec<PathBuf>,
+    pub backups: Vec<ReceiptBackup>,
+    pub registry: Vec<RegistryChange>
+}
+
+impl Receipt {
+    pub fn new(target: Target, custom_target: Option<String>) -> Receipt {
+        Receipt {
+            target,
+            custom_target,
+            payload_version: None,
+            files: Vec::new(),
+            dirs: Vec::new(),
+            backups: Vec::new(),
+            registry: Vec::new()
+        }
+    }
+
+    pub fn add_file(&mut self, path: &Path, contents: &[u8]) {
+        self.add_file_sha256(path, crate::utils::sha256_hex(contents));
+    }
+
+    pub fn add_file_sha256(&mut self, path: &Path, sha256: String) {
+        self.files.retain(|f| f.path != path);
+        self.files.push(ReceiptFile { path: path.to_owned(), sha256 });
+    }
+
+    pub fn add_dir(&mut self, path: &Path) {
+        if !self.dirs.iter().any(|d| d == path) {
+            self.dirs.push(path.to_owned());
+        }
+    }
+
+    pub fn add_backup(&mut self, path: &Path, backup: &Path, sha256: String) {
+        self.backups.retain(|b| b.path != path);
+        self.backups.push(ReceiptBackup { path: path.to_owned(), backup: backup.to_owned(), sha256 });
+    }
+
+    pub fn add_registry_change(&mut self, key: &str, value: &str, previous: Option<u32>) {
+        // keep the oldest previous value, that's the one to go back to
+        if self.registry.iter().any(|r| r.key == key && r.value == value) {
+            return;
+        }
+        self.registry.push(RegistryChange { key: key.to_owned(), value: value.to_owned(), previous });
+    }
+
+    // Layers the changes made by a newer run on top of this receipt
+    pub fn merge(&mut self, newer: Receipt) {
+        if newer.target != self.target {
+            *self = newer;
+            return;
+        }
+
+        self.custom_target = newer.custom_target;
+        if newer.payload_version.is_some() {
+            self.payload_version = newer.payload_version;
+        }
+        for file in newer.files {
+            self.files.retain(|f| f.path != file.path);
+            self.files.push(file);
+        }
+        for dir in newer.dirs {
+            self.add_dir(&dir);
+        }
+        for backup in newer.backups {
+            self.backups.retain(|b| b.path != backup.path);
+            self.backups.push(backup);
+        }
+        for change in newer.registry {
+            self.add_registry_change(&change.key, &change.value, change.previous);
+        }
+    }
+
+    pub fn load(data_dir: &Path) -> Result<Option<Receipt>, Error> {
+        let receipt_str = match std::fs::read_to_string(data_dir.join(RECEIPT_FILE)) {
+            Ok(s) => s,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into())
+        };
+        let JsonValue::Object(receipt) = receipt_str.parse().map_err(|_| Error::InvalidReceipt)? else {
+            return Err(Error::InvalidReceipt);
+        };
+
+        // paths are stored relative to the dir that holds our data dir
+        let root = data_dir.parent().unwrap_or(data_dir);
+        let get_str = |obj: &HashMap<String, JsonValue>, key: &str| match obj.get(key) {
+            Some(JsonValue::String(s)) => Some(s.clone()),
+            _ => None
+        };
+        let get_array = |key: &str| match receipt.get(key) {
+            Some(JsonValue::Array(a)) => Ok(a.as_slice()),
+            None => Ok([].as_slice()),
+            _ => Err(Error::InvalidReceipt)
+        };
+
+        let target = get_str(&receipt, "target")
+            .and_then(|s| Target::from_id(&s))
+            .ok_or(Error::InvalidReceipt)?;
+        let mut result = Receipt::new(target, get_str(&receipt, "custom_target"));
+        result.payload_version = get_str(&receipt, "payload_version");
+
+        for value in get_array("files")? {
+            let JsonValue::Object(obj) = value else {
+                return Err(Error::InvalidReceipt);
+            };
+            result.files.push(ReceiptFile {
+                path: root.join(get_str(obj, "path").ok_or(Error::InvalidReceipt)?),
+                sha256: get_str(obj, "sha256").ok_or(Error::InvalidReceipt)?
+            });
+        }
+
+        for value in get_array("dirs")? {
+            let JsonValue::String(path) = value else {
+                return Err(Error::InvalidReceipt);
+            };
+            result.dirs.push(root.join(path));
+        }
+
+        for value in get_array("backups")? {
+            let JsonValue::Object(obj) = value else {
+                return Err(Error::InvalidReceipt);
+            };
+            result.backups.push(ReceiptBackup {
+                path: root.join(get_str(obj, "path").ok_or(Error::InvalidReceipt)?),
+                backup: root.join(get_str(obj, "backup").ok_or(Error::InvalidReceipt)?),
+                sha256: get_str(obj, "sha256").ok_or(Error::InvalidReceipt)?
+            });
+        }
+
+        for value in get_array("registry")? {
+            let JsonValue::Object(obj) = value else {
+                return Err(Error::InvalidReceipt);
+            };
+            result.registry.push(RegistryChange {
+                key: get_str(obj, "key").ok_or(Error::InvalidReceipt)?,
+                value: get_str(obj, "value").ok_or(Error::InvalidReceipt)?,
+                previous: match obj.get("previous") {
+                    Some(JsonValue::Number(n)) => Some(*n as u32),
+                    _ => None
+                }
+            });
+        }
+
+        Ok(Some(result))
+    }
+
+    pub fn to_json(&self, data_dir: &Path) -> Result<String, Error> {
+        let root = data_dir.parent().unwrap_or(data_dir);
+        let path_value = |p: &Path| JsonValue::String(
+            p.strip_prefix(root).unwrap_or(p).to_string_lossy().into_owned()
+        );
+        let object = |fields: Vec<(&str, JsonValue)>| JsonValue::Object(
+            fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect()
+        );
+
+        let files = self.files.iter().map(|f| object(vec![
+            ("path", path_value(&f.path)),
+            ("sha256", JsonValue::String(f.sha256.clone()))
+        ])).collect();
+        let dirs = self.dirs.iter().map(|d| path_value(d)).collect();
+        let backups = self.backups.iter().map(|b| object(vec![
+            ("path", path_value(&b.path)),
+            ("backup", path_value(&b.backup)),
+            ("sha256", JsonValue::String(b.sha256.clone()))
+        ])).collect();
+        let registry = self.registry.iter().map(|r| object(vec![
+            ("key", JsonValue::String(r.key.clone())),
+            ("value", JsonValue::String(r.value.clone())),
+            ("previous", r.previous.map(|v| JsonValue::Number(v as f64)).unwrap_or(JsonValue::Null))
+        ])).collect();
+
+        let optional_str = |s: &Option<String>| s.clone().map(JsonValue::String).unwrap_or(JsonValue::Null);
+        object(vec![
+            ("installer_version", JsonValue::String(env!("CARGO_PKG_VERSION").to_owned())),
+            ("target", JsonValue::String(self.target.id().to_owned())),
+            ("custom_target", optional_str(&self.custom_target)),
+            ("payload_version", optional_str(&self.payload_version)),
+            ("files", JsonValue::Array(files)),
+            ("dirs", JsonValue::Array(dirs)),
+            ("backups", JsonValue::Array(backups)),
+            ("registry", JsonValue::Array(registry))
+        ]).format().map_err(|_| Error::InvalidReceipt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn round_trips_through_json() {
+        let tmp = TempDir::new();
+        let data_dir = tmp.path().join("game").join("hachimi");
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let game_dir = data_dir.parent().unwrap();
+
+        let mut receipt = Receipt::new(Target::UnityPlayer, Some("version.dll".to_owned()));
+        receipt.payload_version = Some("v1.2.3".to_owned());
+        receipt.add_file(&game_dir.join("version.dll"), b"hachimi");
+        receipt.add_dir(&game_dir.join("cellar"));
+        receipt.add_dir(&game_dir.join("cellar"));
+        receipt.add_backup(&game_dir.join("version.dll"), &data_dir.join("backups/1/version.dll"), "ab".repeat(32));
+        receipt.add_registry_change("HKLM\\Key", "Value", Some(0));
+        receipt.add_registry_change("HKLM\\Key", "Value", Some(1));
+        std::fs::write(data_dir.join(RECEIPT_FILE), receipt.to_json(&data_dir).unwrap()).unwrap();
+
+        // paths are stored relative to the game dir
+        let json = std::fs::read_to_string(data_dir.join(RECEIPT_FILE)).unwrap();
+        assert!(!json.contains(&*tmp.path().to_string_lossy()));
+
+        let loaded = Receipt::load(&data_dir).unwrap().unwrap();
+        assert!(loaded.target == Target::UnityPlayer);
+        assert_eq!(loaded.custom_target.as_deref(), Some("version.dll"));
+        assert_eq!(loaded.payload_version.as_deref(), Some("v1.2.3"));
+        assert_eq!(loaded.files.len(), 1);
+        assert_eq!(loaded.files[0].path, game_dir.join("version.dll"));
+        assert_eq!(loaded.files[0].sha256, crate::utils::sha256_hex(b"hachimi"));
+        assert_eq!(loaded.dirs, [game_dir.join("cellar")]);
+        assert_eq!(loaded.backups.len(), 1);
+        assert_eq!(loaded.backups[0].backup, data_dir.join("backups/1/version.dll"));
+        // the first previous value is the one from before any install
+        assert_eq!(loaded.registry.len(), 1);
+        assert_eq!(loaded.registry[0].previous, Some(0));
+    }
+
+    #[test]
+    fn merges_newer_runs() {
+        let mut receipt = Receipt::new(Target::CriManaVpx, None);
+        receipt.add_file(Path::new("a.dll"), b"old");
+        receipt.add_file(Path::new("b.dll"), b"b");
+        receipt.add_registry_change("HKLM\\Key", "Value", None);
+
+        let mut newer = Receipt::new(Target::CriManaVpx, None);
+        newer.payload_version = Some("v2".to_owned());
+        newer.add_file(Path::new("a.dll"), b"new");
+        newer.add_registry_change("HKLM\\Key", "Value", Some(1));
+        receipt.merge(newer);
+
+        assert_eq!(receipt.payload_version.as_deref(), Some("v2"));
+        assert_eq!(receipt.files.len(), 2);
+        let a = receipt.files.iter().find(|f| f.path == Path::new("a.dll")).unwrap();
+        assert_eq!(a.sha256, crate::utils::sha256_hex(b"new"));
+        assert_eq!(receipt.registry[0].previous, None);
+
+        // another target replaces it all
+        receipt.merge(Receipt::new(Target::UnityPlayer, None));
+        assert!(receipt.target == Target::UnityPlayer);
+        assert!(receipt.files.is_empty());
+        assert!(receipt.registry.is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_receipts() {
+        let tmp = TempDir::new();
+        assert!(Receipt::load(tmp.path()).unwrap().is_none());
+
+        for json in ["[]", "{}", r#"{"target": "nope"}"#, r#"{"target": "dmm", "files": [1]}"#, "{"] {
+            std::fs::write(tmp.path().join(RECEIPT_FILE), json).unwrap();
+            assert!(matches!(Receipt::load(tmp.path()), Err(Error::InvalidReceipt)), "{} accepted", json);
+        }
+    }
+}
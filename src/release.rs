@@ -0,0 +1,669 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::Arc};
+use bytes::Bytes;
+use tinyjson::JsonValue;
+use crate::{download::Downloader, installer::{Error, Installer}, utils};
+
+pub const DLL_ASSET: &str = "hachimi.dll";
+// Optional assets next to the DLL in a release
+pub const SHA256_ASSET: &str = "hachimi.dll.sha256";
+pub const SIGNATURE_ASSET: &str = "hachimi.dll.minisig";
+// Optional in a local release folder, same format as a static index
+pub const RELEASE_FILE: &str = "release.json";
+// Same syntax as --release-source, for when there's no command line (e.g. the GUI)
+pub const SOURCE_ENV: &str = "HACHIMI_RELEASE_SOURCE";
+// Appended to the installed DLL's file name
+const SIDECAR_SUFFIX: &str = ".release.json";
+
+// Downloads have to be signed with this key if the build has one
+const MINISIGN_PUBKEY: Option<&str> = option_env!("HACHIMI_MINISIGN_PUBKEY");
+
+const DEFAULT_GITHUB_API: &str = "https://api.github.com";
+const DEFAULT_GITHUB_OWNER: &str = "kairusds";
+const DEFAULT_GITHUB_REPO: &str = "Hachimi-Edge";
+
+#[derive(Clone)]
+pub enum AssetLocation {
+    Url(String),
+    File(PathBuf)
+}
+
+impl AssetLocation {
+    pub fn fetch(&self, downloader: &Downloader, accept: Option<&str>) -> Result<Bytes, Error> {
+        match self {
+            AssetLocation::Url(url) => downloader.get(url, accept),
+            AssetLocation::File(path) => Ok(Bytes::from(std::fs::read(path)?))
+        }
+    }
+}
+
+impl std::fmt::Display for AssetLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssetLocation::Url(url) => write!(f, "{}", url),
+            AssetLocation::File(path) => write!(f, "{}", path.display())
+        }
+    }
+}
+
+// One file of a release, as much as the source knows about it
+#[derive(Clone)]
+pub struct Asset {
+    pub id: Option<u64>,
+    pub size: Option<u64>,
+    // "sha256:<hex>", like GitHub has it
+    pub digest: Option<String>,
+    pub location: AssetLocation
+}
+
+#[derive(Clone)]
+pub struct Release {
+    pub tag: String,
+    pub dll: Asset,
+    pub sha256: Option<Asset>,
+    pub signature: Option<Asset>
+}
+
+impl Release {
+    // What's known about the downloaded DLL, the release info where it has it
+    pub fn sidecar(&self, bytes: &[u8]) -> Sidecar {
+        Sidecar {
+            tag: self.tag.clone(),
+            asset_id: self.dll.id,
+            size: self.dll.size.unwrap_or(bytes.len() as u64),
+            digest: self.dll.digest.clone().unwrap_or_else(|| format!("sha256:{}", utils::sha256_hex(bytes)))
+        }
+    }
+}
+
+// Written next to the installed DLL, so the exact release it came from can be told later
+#[derive(Clone)]
+pub struct Sidecar {
+    pub tag: String,
+    pub asset_id: Option<u64>,
+    pub size: u64,
+    pub digest: String
+}
+
+impl Sidecar {
+    pub fn path_for(dll_path: &Path) -> PathBuf {
+        let mut name = dll_path.file_name().unwrap_or_default().to_owned();
+        name.push(SIDECAR_SUFFIX);
+        dll_path.with_file_name(name)
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut root = HashMap::new();
+        root.insert("tag".to_owned(), JsonValue::String(self.tag.clone()));
+        root.insert("asset_id".to_owned(), self.asset_id.map(|id| JsonValue::Number(id as f64)).unwrap_or(JsonValue::Null));
+        root.insert("size".to_owned(), JsonValue::Number(self.size as f64));
+        root.insert("digest".to_owned(), JsonValue::String(self.digest.clone()));
+
+        // only strings, numbers and nulls in here so this can't fail
+        JsonValue::Object(root).format().unwrap_or_default()
+    }
+
+    pub fn from_json(text: &str) -> Option<Sidecar> {
+        let JsonValue::Object(obj) = text.parse::<JsonValue>().ok()? else {
+            return None;
+        };
+        let get_str = |key: &str| match obj.get(key) {
+            Some(JsonValue::String(s)) => Some(s.clone()),
+            _ => None
+        };
+        let get_num = |key: &str| match obj.get(key) {
+            Some(JsonValue::Number(n)) => Some(*n as u64),
+            _ => None
+        };
+
+        Some(Sidecar {
+            tag: get_str("tag")?,
+            asset_id: get_num("asset_id"),
+            size: get_num("size")?,
+            digest: get_str("digest")?
+        })
+    }
+}
+
+pub struct Downloaded {
+    pub bytes: Bytes,
+    pub sidecar: Sidecar
+}
+
+// Why a download was thrown away
+#[derive(Clone, Debug)]
+pub enum IntegrityError {
+    Size { expected: u64, actual: u64 },
+    Digest { expected: String, actual: String },
+    // neither the release info nor a .sha256 asset has one
+    NoDigest,
+    InvalidDigest(String),
+    NoSignature,
+    Signature(String)
+}
+
+impl std::fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IntegrityError::Size { expected, actual } => write!(f, "expected {} bytes, got {}", expected, actual),
+            IntegrityError::Digest { expected, actual } => write!(f, "expected SHA-256 {}, got {}", expected, actual),
+            IntegrityError::NoDigest => write!(f, "the release has no SHA-256 digest for {}", DLL_ASSET),
+            IntegrityError::InvalidDigest(digest) => write!(f, "unsupported digest {}", digest),
+            IntegrityError::NoSignature => write!(f, "the release has no {} and this installer requires one", SIGNATURE_ASSET),
+            IntegrityError::Signature(e) => write!(f, "bad signature: {}", e)
+        }
+    }
+}
+
+// "sha256:<hex>" or plain hex, to lowercase hex
+pub(crate) fn parse_digest(digest: &str) -> Result<String, IntegrityError> {
+    let hex = digest.strip_prefix("sha256:").unwrap_or(digest);
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(IntegrityError::InvalidDigest(digest.to_owned()));
+    }
+    Ok(hex.to_ascii_lowercase())
+}
+
+// sha256sum output, "<hex>  hachimi.dll", or just the hex
+fn parse_sha256_file(bytes: &[u8]) -> Result<String, IntegrityError> {
+    let text = String::from_utf8_lossy(bytes);
+    let hex = text.split_whitespace().next().unwrap_or_default();
+    parse_digest(hex)
+}
+
+fn verify_signature(bytes: &[u8], signature: Option<&[u8]>) -> Result<(), IntegrityError> {
+    let Some(public_key) = MINISIGN_PUBKEY else {
+        return Ok(());
+    };
+    let signature = signature.ok_or(IntegrityError::NoSignature)?;
+    let signature = std::str::from_utf8(signature)
+        .map_err(|_| IntegrityError::Signature("not a minisign signature".to_owned()))
+        .and_then(|s| minisign_verify::Signature::decode(s).map_err(|e| IntegrityError::Signature(e.to_string())))?;
+    minisign_verify::PublicKey::from_base64(public_key)
+        .and_then(|key| key.verify(bytes, &signature, false))
+        .map_err(|e| IntegrityError::Signature(e.to_string()))
+}
+
+// Size, digest and signature, whichever the release and the build have
+fn check_integrity(release: &Release, bytes: &[u8], digest: Option<&str>, signature: Option<&[u8]>) -> Result<(), IntegrityError> {
+    if let Some(expected) = release.dll.size {
+        if expected != bytes.len() as u64 {
+            return Err(IntegrityError::Size { expected, actual: bytes.len() as u64 });
+        }
+    }
+
+    match digest {
+        Some(digest) => {
+            let expected = parse_digest(digest)?;
+            let actual = utils::sha256_hex(bytes);
+            if expected != actual {
+                return Err(IntegrityError::Digest { expected, actual });
+            }
+        },
+        // a file that's already on disk didn't go through the network
+        None if matches!(release.dll.location, AssetLocation::File(_)) => {},
+        None => return Err(IntegrityError::NoDigest)
+    }
+
+    verify_signature(bytes, signature)
+}
+
+// Where releases come from; the latest one is always what gets installed
+pub trait ReleaseSource: Send + Sync {
+    fn describe(&self) -> String;
+    fn latest(&self, downloader: &Downloader) -> Result<Release, Error>;
+
+    fn download(&self, downloader: &Downloader, asset: &Asset) -> Result<Bytes, Error> {
+        asset.location.fetch(downloader, None)
+    }
+
+    // Downloads the DLL and checks it, nothing that fails the check gets returned
+    fn fetch(&self, downloader: &Downloader, release: &Release) -> Result<Downloaded, Error> {
+        let bytes = self.download(downloader, &release.dll)?;
+
+        let digest = match (&release.dll.digest, &release.sha256) {
+            (Some(digest), _) => Some(digest.clone()),
+            (None, Some(asset)) => Some(parse_sha256_file(&self.download(downloader, asset)?).map_err(Error::IntegrityFailed)?),
+            (None, None) => None
+        };
+        let signature = match &release.signature {
+            Some(asset) if MINISIGN_PUBKEY.is_some() => Some(self.download(downloader, asset)?),
+            _ => None
+        };
+        check_integrity(release, &bytes, digest.as_deref(), signature.as_deref()).map_err(Error::IntegrityFailed)?;
+
+        Ok(Downloaded { sidecar: release.sidecar(&bytes), bytes })
+    }
+}
+
+// An entry of a release's assets array, nothing in it but the name is required
+struct ReleaseAsset {
+    name: String,
+    id: Option<u64>,
+    size: Option<u64>,
+    digest: Option<String>,
+    // the API URL on GitHub, needs Accept: application/octet-stream
+    url: Option<String>,
+    browser_download_url: Option<String>
+}
+
+impl ReleaseAsset {
+    fn into_asset(self, location: AssetLocation) -> Asset {
+        Asset { id: self.id, size: self.size, digest: self.digest, location }
+    }
+}
+
+struct ParsedRelease {
+    tag: String,
+    dll: ReleaseAsset,
+    sha256: Option<ReleaseAsset>,
+    signature: Option<ReleaseAsset>
+}
+
+impl ParsedRelease {
+    // Each source knows best where its assets are
+    fn resolve(self, locate: impl Fn(&ReleaseAsset) -> Result<AssetLocation, Error>) -> Result<Release, Error> {
+        let resolve_asset = |asset: ReleaseAsset| -> Result<Asset, Error> {
+            let location = locate(&asset)?;
+            Ok(asset.into_asset(location))
+        };
+        Ok(Release {
+            sha256: self.sha256.map(resolve_asset).transpose()?,
+            signature: self.signature.map(resolve_asset).transpose()?,
+            dll: resolve_asset(self.dll)?,
+            tag: self.tag
+        })
+    }
+}
+
+// GitHub's release object, which a static index follows too:
+// {"tag_name": "v1.2.3", "assets": [{"id": 1, "name": "hachimi.dll", "size": 123, "digest": "sha256:...",
+//   "url": "...", "browser_download_url": "..."}]}
+fn parse_release(text: &str) -> Result<ParsedRelease, Error> {
+    let json: JsonValue = text.parse()
+        .map_err(|_| Error::InvalidReleaseInfo("release info is not valid JSON".to_owned()))?;
+    let JsonValue::Object(root_obj) = json else {
+        return Err(Error::InvalidReleaseInfo("release info is not a JSON object".to_owned()));
+    };
+
+    let Some(JsonValue::String(tag_name)) = root_obj.get("tag_name") else {
+        return Err(Error::InvalidReleaseInfo("tag_name field not found or not a string".to_owned()));
+    };
+    let Some(JsonValue::Array(assets)) = root_obj.get("assets") else {
+        return Err(Error::InvalidReleaseInfo("assets field not found or not an array".to_owned()));
+    };
+
+    let find_asset = |wanted: &str| assets.iter().find_map(|asset| {
+        let JsonValue::Object(asset) = asset else {
+            return None;
+        };
+        if !matches!(asset.get("name"), Some(JsonValue::String(name)) if name == wanted) {
+            return None;
+        }
+
+        let string = |key: &str| match asset.get(key) {
+            Some(JsonValue::String(s)) => Some(s.clone()),
+            _ => None
+        };
+        let number = |key: &str| match asset.get(key) {
+            Some(JsonValue::Number(n)) if *n >= 0.0 => Some(*n as u64),
+            _ => None
+        };
+        Some(ReleaseAsset {
+            name: wanted.to_owned(),
+            id: number("id"),
+            size: number("size"),
+            digest: string("digest"),
+            url: string("url"),
+            browser_download_url: string("browser_download_url")
+        })
+    });
+
+    let dll = find_asset(DLL_ASSET)
+        .ok_or_else(|| Error::InvalidReleaseInfo(format!("release {} has no {} asset", tag_name, DLL_ASSET)))?;
+    Ok(ParsedRelease {
+        tag: tag_name.clone(),
+        dll,
+        sha256: find_asset(SHA256_ASSET),
+        signature: find_asset(SIGNATURE_ASSET)
+    })
+}
+
+// Relative URLs in an index are relative to the index itself, so a whole mirror can be copied around
+fn resolve_url(base: &str, url: &str) -> Result<String, Error> {
+    let base = reqwest::Url::parse(base)
+        .map_err(|e| Error::InvalidReleaseInfo(format!("invalid URL {}: {}", base, e)))?;
+    let url = base.join(url)
+        .map_err(|e| Error::InvalidReleaseInfo(format!("invalid URL {}: {}", url, e)))?;
+    Ok(url.into())
+}
+
+fn no_url(asset: &ReleaseAsset) -> Error {
+    Error::InvalidReleaseInfo(format!("{} asset has no URL", asset.name))
+}
+
+// The releases API of a GitHub repo, or anything that serves the same API
+pub struct GitHubSource {
+    pub api_base: String,
+    pub owner: String,
+    pub repo: String
+}
+
+impl Default for GitHubSource {
+    fn default() -> Self {
+        GitHubSource {
+            api_base: DEFAULT_GITHUB_API.to_owned(),
+            owner: DEFAULT_GITHUB_OWNER.to_owned(),
+            repo: DEFAULT_GITHUB_REPO.to_owned()
+        }
+    }
+}
+
+impl ReleaseSource for GitHubSource {
+    fn describe(&self) -> String {
+        format!("GitHub ({}/{})", self.owner, self.repo)
+    }
+
+    fn latest(&self, downloader: &Downloader) -> Result<Release, Error> {
+        let url = format!("{}/repos/{}/{}/releases/latest", self.api_base.trim_end_matches('/'), self.owner, self.repo);
+        // the assets of this exact release, /releases/latest/download could already be a newer one
+        parse_release(&downloader.get_text(&url)?)?.resolve(|asset| {
+            let asset_url = asset.url.as_ref().or(asset.browser_download_url.as_ref()).ok_or_else(|| no_url(asset))?;
+            Ok(AssetLocation::Url(resolve_url(&url, asset_url)?))
+        })
+    }
+
+    fn download(&self, downloader: &Downloader, asset: &Asset) -> Result<Bytes, Error> {
+        // the API URL only serves the file itself when asked to
+        asset.location.fetch(downloader, Some("application/octet-stream"))
+    }
+}
+
+// A release JSON on any HTTP server
+pub struct IndexSource {
+    pub url: String
+}
+
+impl ReleaseSource for IndexSource {
+    fn describe(&self) -> String {
+        self.url.clone()
+    }
+
+    fn latest(&self, downloader: &Downloader) -> Result<Release, Error> {
+        parse_release(&downloader.get_text(&self.url)?)?.resolve(|asset| {
+            let asset_url = asset.browser_download_url.as_ref().or(asset.url.as_ref()).ok_or_else(|| no_url(asset))?;
+            Ok(AssetLocation::Url(resolve_url(&self.url, asset_url)?))
+        })
+    }
+}
+
+// A folder with hachimi.dll in it, and optionally a release.json saying which release it is
+pub struct LocalSource {
+    pub dir: PathBuf
+}
+
+impl ReleaseSource for LocalSource {
+    fn describe(&self) -> String {
+        self.dir.display().to_string()
+    }
+
+    fn latest(&self, _downloader: &Downloader) -> Result<Release, Error> {
+        let index_path = self.dir.join(RELEASE_FILE);
+        if index_path.is_file() {
+            // paths are relative to the folder, the asset name if there's none
+            return parse_release(&std::fs::read_to_string(&index_path)?)?.resolve(|asset| {
+                let path = asset.browser_download_url.as_ref().unwrap_or(&asset.name);
+                Ok(AssetLocation::File(self.dir.join(path)))
+            });
+        }
+
+        // without one, the DLL itself has to say which version it is
+        let dll_path = self.dir.join(DLL_ASSET);
+        if !dll_path.is_file() {
+            return Err(Error::InvalidReleaseInfo(format!("{} not found", dll_path.display())));
+        }
+        let tag = Installer::read_target_version_info(&dll_path)
+            .and_then(|v| v.version)
+            .ok_or_else(|| Error::InvalidReleaseInfo(format!("{} has no version info", dll_path.display())))?;
+        let optional_asset = |name: &str| {
+            let path = self.dir.join(name);
+            path.is_file().then_some(Asset { id: None, size: None, digest: None, location: AssetLocation::File(path) })
+        };
+        Ok(Release {
+            tag,
+            dll: Asset { id: None, size: None, digest: None, location: AssetLocation::File(dll_path) },
+            sha256: optional_asset(SHA256_ASSET),
+            signature: optional_asset(SIGNATURE_ASSET)
+        })
+    }
+}
+
+// github[:<owner>/<repo>[@<api base>]], index:<url> or local:<dir>
+pub fn from_spec(spec: &str) -> Option<Arc<dyn ReleaseSource>> {
+    let (kind, value) = match spec.split_once(':') {
+        Some((kind, value)) => (kind, Some(value)),
+        None => (spec, None)
+    };
+
+    match (kind, value) {
+        ("github", None) => Some(Arc::new(GitHubSource::default())),
+        ("github", Some(value)) => {
+            let (repo, api_base) = match value.split_once('@') {
+                Some((repo, api_base)) => (repo, api_base),
+                None => (value, DEFAULT_GITHUB_API)
+            };
+            let (owner, repo) = repo.split_once('/')?;
+            if owner.is_empty() || repo.is_empty() || api_base.is_empty() {
+                return None;
+            }
+            Some(Arc::new(GitHubSource {
+                api_base: api_base.to_owned(),
+                owner: owner.to_owned(),
+                repo: repo.to_owned()
+            }))
+        },
+        ("index", Some(url)) if !url.is_empty() => Some(Arc::new(IndexSource { url: url.to_owned() })),
+        ("local", Some(dir)) if !dir.is_empty() => Some(Arc::new(LocalSource { dir: dir.into() })),
+        _ => None
+    }
+}
+
+// From HACHIMI_RELEASE_SOURCE if set, GitHub otherwise
+pub fn configured() -> Result<Arc<dyn ReleaseSource>, Error> {
+    match std::env::var(SOURCE_ENV) {
+        Ok(spec) if !spec.is_empty() => from_spec(&spec).ok_or(Error::InvalidReleaseSource(spec)),
+        _ => Ok(Arc::new(GitHubSource::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{serve, TempDir};
+
+    const DLL: &[u8] = b"not really a dll";
+
+    fn downloader(dir: &TempDir) -> Downloader {
+        Downloader { retries: 0, partial_dir: dir.path().join("partial"), ..Default::default() }
+    }
+
+    fn dll_digest() -> String {
+        format!("sha256:{}", utils::sha256_hex(DLL))
+    }
+
+    fn url_of(location: &AssetLocation) -> &str {
+        match location {
+            AssetLocation::Url(url) => url,
+            AssetLocation::File(path) => panic!("expected a URL, got {}", path.display())
+        }
+    }
+
+    #[test]
+    fn parses_source_specs() {
+        let describe = |spec: &str| from_spec(spec).map(|s| s.describe());
+        assert_eq!(describe("github").as_deref(), Some("GitHub (kairusds/Hachimi-Edge)"));
+        assert_eq!(describe("github:owner/repo").as_deref(), Some("GitHub (owner/repo)"));
+        assert_eq!(describe("github:owner/repo@http://localhost:8080").as_deref(), Some("GitHub (owner/repo)"));
+        assert_eq!(describe("index:https://example.com/release.json").as_deref(), Some("https://example.com/release.json"));
+        assert!(describe("local:some/dir").is_some());
+
+        for spec in ["", "github:", "github:owner", "github:/repo", "github:owner/", "github:owner/repo@", "index", "index:", "local:", "ftp:host"] {
+            assert!(from_spec(spec).is_none(), "{:?} should be rejected", spec);
+        }
+    }
+
+    #[test]
+    fn github_uses_api_urls_of_the_dll() {
+        let dir = TempDir::new();
+        let release_json = format!(r#"{{
+            "tag_name": "v1.2.3",
+            "assets": [
+                {{ "id": 1, "name": "hachimi.zip", "size": 1, "url": "/assets/1" }},
+                {{ "id": 2, "name": "hachimi.dll", "size": {}, "digest": "{}", "url": "/assets/2",
+                   "browser_download_url": "/download/hachimi.dll" }}
+            ]
+        }}"#, DLL.len(), dll_digest());
+        let base = serve(vec![
+            ("/repos/owner/repo/releases/latest".to_owned(), release_json.into_bytes()),
+            ("/assets/2".to_owned(), DLL.to_vec())
+        ]);
+
+        let source = from_spec(&format!("github:owner/repo@{}", base)).unwrap();
+        let downloader = downloader(&dir);
+        let release = source.latest(&downloader).unwrap();
+        assert_eq!(release.tag, "v1.2.3");
+        assert_eq!(release.dll.id, Some(2));
+        assert_eq!(release.dll.size, Some(DLL.len() as u64));
+        assert_eq!(url_of(&release.dll.location), format!("{}/assets/2", base));
+        assert!(release.sha256.is_none() && release.signature.is_none());
+
+        let downloaded = source.fetch(&downloader, &release).unwrap();
+        assert_eq!(&downloaded.bytes[..], DLL);
+        assert_eq!(downloaded.sidecar.tag, "v1.2.3");
+        assert_eq!(downloaded.sidecar.asset_id, Some(2));
+        assert_eq!(downloaded.sidecar.digest, dll_digest());
+    }
+
+    #[test]
+    fn index_resolves_relative_urls_and_sha256_asset() {
+        let dir = TempDir::new();
+        let release_json = r#"{
+            "tag_name": "v2.0.0",
+            "assets": [
+                { "name": "hachimi.dll", "browser_download_url": "files/hachimi.dll" },
+                { "name": "hachimi.dll.sha256", "browser_download_url": "files/hachimi.dll.sha256" }
+            ]
+        }"#;
+        let sha256_file = format!("{}  hachimi.dll\n", utils::sha256_hex(DLL).to_ascii_uppercase());
+        let base = serve(vec![
+            ("/mirror/release.json".to_owned(), release_json.as_bytes().to_vec()),
+            ("/mirror/files/hachimi.dll".to_owned(), DLL.to_vec()),
+            ("/mirror/files/hachimi.dll.sha256".to_owned(), sha256_file.into_bytes())
+        ]);
+
+        let source = from_spec(&format!("index:{}/mirror/release.json", base)).unwrap();
+        let downloader = downloader(&dir);
+        let release = source.latest(&downloader).unwrap();
+        assert_eq!(url_of(&release.dll.location), format!("{}/mirror/files/hachimi.dll", base));
+        assert!(release.dll.digest.is_none());
+        assert!(release.sha256.is_some());
+
+        let downloaded = source.fetch(&downloader, &release).unwrap();
+        assert_eq!(&downloaded.bytes[..], DLL);
+        assert_eq!(downloaded.sidecar.digest, dll_digest());
+    }
+
+    #[test]
+    fn rejects_downloads_that_fail_the_checks() {
+        let dir = TempDir::new();
+        let downloader = downloader(&dir);
+        let wrong_digest = format!("sha256:{}", "0".repeat(64));
+        let cases = [
+            (r#"{ "name": "hachimi.dll", "size": 1, "browser_download_url": "/hachimi.dll" }"#.to_owned(), "size"),
+            (format!(r#"{{ "name": "hachimi.dll", "digest": "{}", "browser_download_url": "/hachimi.dll" }}"#, wrong_digest), "digest"),
+            (r#"{ "name": "hachimi.dll", "digest": "md5:abc", "browser_download_url": "/hachimi.dll" }"#.to_owned(), "invalid digest"),
+            (r#"{ "name": "hachimi.dll", "browser_download_url": "/hachimi.dll" }"#.to_owned(), "no digest")
+        ];
+
+        for (asset, case) in cases {
+            let base = serve(vec![
+                ("/release.json".to_owned(), format!(r#"{{ "tag_name": "v1", "assets": [{}] }}"#, asset).into_bytes()),
+                ("/hachimi.dll".to_owned(), DLL.to_vec())
+            ]);
+            let source = IndexSource { url: format!("{}/release.json", base) };
+            let release = source.latest(&downloader).unwrap();
+            let Err(Error::IntegrityFailed(e)) = source.fetch(&downloader, &release) else {
+                panic!("{} should fail the integrity check", case);
+            };
+            let matched = match case {
+                "size" => matches!(e, IntegrityError::Size { expected: 1, .. }),
+                "digest" => matches!(e, IntegrityError::Digest { .. }),
+                "invalid digest" => matches!(e, IntegrityError::InvalidDigest(_)),
+                _ => matches!(e, IntegrityError::NoDigest)
+            };
+            assert!(matched, "{}: wrong error {}", case, e);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_release_info() {
+        let dir = TempDir::new();
+        let downloader = downloader(&dir);
+        let base = serve(vec![
+            ("/no_dll.json".to_owned(), br#"{ "tag_name": "v1", "assets": [{ "name": "other.dll", "url": "/x" }] }"#.to_vec()),
+            ("/no_tag.json".to_owned(), br#"{ "assets": [] }"#.to_vec()),
+            ("/no_url.json".to_owned(), br#"{ "tag_name": "v1", "assets": [{ "name": "hachimi.dll" }] }"#.to_vec()),
+            ("/not_json".to_owned(), b"<html>".to_vec())
+        ]);
+
+        for path in ["/no_dll.json", "/no_tag.json", "/no_url.json", "/not_json"] {
+            let source = IndexSource { url: format!("{}{}", base, path) };
+            assert!(matches!(source.latest(&downloader), Err(Error::InvalidReleaseInfo(_))), "{}", path);
+        }
+        // a 404 is a download error, not bad release info
+        let source = IndexSource { url: format!("{}/missing.json", base) };
+        assert!(matches!(source.latest(&downloader), Err(Error::ReqwestError(_))));
+    }
+
+    #[test]
+    fn local_source_reads_release_file() {
+        let dir = TempDir::new();
+        let release_dir = dir.path().join("release");
+        std::fs::create_dir_all(release_dir.join("builds")).unwrap();
+        std::fs::write(release_dir.join("builds").join("hachimi-v3.dll"), DLL).unwrap();
+        std::fs::write(release_dir.join(RELEASE_FILE), format!(r#"{{
+            "tag_name": "v3.0.0",
+            "assets": [{{ "name": "hachimi.dll", "size": {}, "browser_download_url": "builds/hachimi-v3.dll" }}]
+        }}"#, DLL.len())).unwrap();
+
+        let source = from_spec(&format!("local:{}", release_dir.display())).unwrap();
+        let downloader = downloader(&dir);
+        let release = source.latest(&downloader).unwrap();
+        assert_eq!(release.tag, "v3.0.0");
+        assert!(matches!(&release.dll.location, AssetLocation::File(path) if *path == release_dir.join("builds").join("hachimi-v3.dll")));
+
+        // a file on disk doesn't need a digest, but one that's there still has to match
+        let downloaded = source.fetch(&downloader, &release).unwrap();
+        assert_eq!(&downloaded.bytes[..], DLL);
+        std::fs::write(release_dir.join(SHA256_ASSET), "0".repeat(64)).unwrap();
+        let mut with_sha256 = release.clone();
+        with_sha256.sha256 = Some(Asset { id: None, size: None, digest: None, location: AssetLocation::File(release_dir.join(SHA256_ASSET)) });
+        assert!(matches!(source.fetch(&downloader, &with_sha256), Err(Error::IntegrityFailed(IntegrityError::Digest { .. }))));
+    }
+
+    #[test]
+    fn local_source_without_dll() {
+        let dir = TempDir::new();
+        let source = LocalSource { dir: dir.path().to_owned() };
+        assert!(matches!(source.latest(&downloader(&dir)), Err(Error::InvalidReleaseInfo(_))));
+    }
+
+    #[test]
+    fn parses_digests() {
+        let hex = utils::sha256_hex(DLL);
+        assert_eq!(parse_digest(&format!("sha256:{}", hex.to_ascii_uppercase())).unwrap(), hex);
+        assert_eq!(parse_digest(&hex).unwrap(), hex);
+        assert!(parse_digest("sha256:abc").is_err());
+        assert!(parse_digest(&format!("sha512:{}", hex)).is_err());
+        assert_eq!(parse_sha256_file(format!("{} *hachimi.dll\n", hex).as_bytes()).unwrap(), hex);
+    }
+}
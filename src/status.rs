@@ -0,0 +1,270 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, rc::Rc};
+use tinyjson::JsonValue;
+use crate::{
+    game::GameExeInfo,
+    installer::{self, Installer, Target, TargetVersionInfo},
+    platform::Platform
+};
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum DetectionSource {
+    DmmConfig,
+    SteamLibrary,
+    KomoeRegistry,
+    Manual
+}
+
+impl DetectionSource {
+    pub fn of(target: Target) -> DetectionSource {
+        match target {
+            Target::UnityPlayer => DetectionSource::DmmConfig,
+            Target::CriManaVpx | Target::CriManaVpxGlobal => DetectionSource::SteamLibrary,
+            Target::CriManaVpxKomoe => DetectionSource::KomoeRegistry
+        }
+    }
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            DetectionSource::DmmConfig => "dmm_cnf",
+            DetectionSource::SteamLibrary => "steam_library",
+            DetectionSource::KomoeRegistry => "komoe_registry",
+            DetectionSource::Manual => "manual"
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            DetectionSource::DmmConfig => "DMM cnf",
+            DetectionSource::SteamLibrary => "Steam library",
+            DetectionSource::KomoeRegistry => "KOMOE registry",
+            DetectionSource::Manual => "manual"
+        }
+    }
+}
+
+// Everything we can tell about one target without changing anything
+pub struct TargetStatus {
+    pub target: Target,
+    pub install_dir: Option<PathBuf>,
+    pub source: Option<DetectionSource>,
+    pub dll_path: Option<PathBuf>,
+    // None if there's no file at all, it may not be Hachimi
+    pub dll: Option<TargetVersionInfo>,
+    // JP Steam only
+    pub exe: Option<GameExeInfo>,
+    pub exe_backup: Option<PathBuf>,
+    // DMM only
+    pub cellar: Option<bool>
+}
+
+impl TargetStatus {
+    pub fn read(platform: &Rc<Platform>, target: Target, manual_dir: Option<&Path>) -> TargetStatus {
+        let (install_dir, source) = match manual_dir {
+            Some(dir) => (Some(dir.to_owned()), Some(DetectionSource::Manual)),
+            None => match Installer::detect_install_dir(platform, target) {
+                Some(dir) => (Some(dir), Some(DetectionSource::of(target))),
+                None => (None, None)
+            }
+        };
+        let installer = Installer::custom(platform.clone(), install_dir.clone(), target, None);
+
+        let dll_path = installer.get_target_path(target);
+        let dll = installer.get_target_version_info(target);
+
+        let exe = installer.get_game_exe_info().ok().flatten();
+        let exe_backup = if target == Target::CriManaVpx {
+            installer.get_backup_store()
+                .zip(installer.get_orig_exe_path())
+                .and_then(|(store, exe_path)| store.list().ok()?.into_iter().rev().find(|e| e.path == exe_path))
+                .map(|e| e.file)
+                .or_else(|| installer.get_legacy_backup_exe_path().filter(|p| p.is_file()))
+        }
+        else {
+            None
+        };
+
+        let cellar = (target == Target::UnityPlayer)
+            .then(|| installer.get_cellar_path().map(|p| p.is_file()))
+            .flatten();
+
+        TargetStatus { target, install_dir, source, dll_path, dll, exe, exe_backup, cellar }
+    }
+
+    pub fn is_hachimi_installed(&self) -> bool {
+        self.dll.as_ref().is_some_and(|v| v.is_hachimi())
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let path_value = |p: Option<&Path>| p
+            .map(|p| JsonValue::String(p.to_string_lossy().into_owned()))
+            .unwrap_or(JsonValue::Null);
+        let str_value = |s: Option<&str>| s
+            .map(|s| JsonValue::String(s.to_owned()))
+            .unwrap_or(JsonValue::Null);
+
+        let dll = self.dll.as_ref().map(|v| {
+            let mut obj = HashMap::new();
+            obj.insert("hachimi".to_owned(), JsonValue::Boolean(v.is_hachimi()));
+            obj.insert("name".to_owned(), str_value(v.name.as_deref()));
+            obj.insert("version".to_owned(), str_value(v.version.as_deref()));
+            JsonValue::Object(obj)
+        }).unwrap_or(JsonValue::Null);
+
+        let exe = self.exe.as_ref().map(|info| {
+            let mut obj = HashMap::new();
+            obj.insert("build".to_owned(), JsonValue::String(info.label()));
+            obj.insert("supported".to_owned(), JsonValue::Boolean(info.is_supported()));
+            obj.insert("state".to_owned(), JsonValue::String(info.state().id().to_owned()));
+            obj.insert("sha256".to_owned(), JsonValue::String(info.sha256.clone()));
+            obj.insert("backup".to_owned(), path_value(self.exe_backup.as_deref()));
+            JsonValue::Object(obj)
+        }).unwrap_or(JsonValue::Null);
+
+        let fields = vec![
+            ("target", JsonValue::String(self.target.id().to_owned())),
+            ("dll_name", JsonValue::String(self.target.dll_name().to_owned())),
+            ("install_dir", path_value(self.install_dir.as_deref())),
+            ("source", str_value(self.source.map(|s| s.id()))),
+            ("dll_path", path_value(self.dll_path.as_deref())),
+            ("dll", dll),
+            ("exe", exe),
+            ("cellar", self.cellar.map(JsonValue::Boolean).unwrap_or(JsonValue::Null))
+        ];
+        JsonValue::Object(fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = format!("{} ({})\n", self.target.id(), self.target.dll_name());
+        let Some(install_dir) = &self.install_dir else {
+            text.push_str("  not found\n");
+            return text;
+        };
+        text.push_str(&format!(
+            "  install dir: {} ({})\n",
+            install_dir.display(),
+            self.source.map(|s| s.label()).unwrap_or("?")
+        ));
+
+        let dll = match &self.dll {
+            Some(v) if v.is_hachimi() => format!("installed, {}", v.version.as_deref().unwrap_or("unknown version")),
+            Some(v) => format!(
+                "not installed, other DLL present ({} {})",
+                v.name.as_deref().unwrap_or("?"),
+                v.version.as_deref().unwrap_or("?")
+            ),
+            None => "not installed".to_owned()
+        };
+        text.push_str(&format!("  hachimi: {}\n", dll));
+
+        if let Some(info) = &self.exe {
+            text.push_str(&format!("  game exe: {}, {}\n", info.label(), info.state().id()));
+            text.push_str(&format!(
+                "  exe backup: {}\n",
+                self.exe_backup.as_deref().map(|p| p.display().to_string()).unwrap_or_else(|| "none".to_owned())
+            ));
+        }
+        if let Some(cellar) = self.cellar {
+            text.push_str(&format!("  cellar: {}\n", if cellar { "present" } else { "not present" }));
+        }
+        text
+    }
+}
+
+pub struct Status {
+    pub dotlocal: bool,
+    pub targets: Vec<TargetStatus>
+}
+
+impl Status {
+    // `manual` overrides detection for one target
+    pub fn read(platform: &Rc<Platform>, manual: Option<(Target, &Path)>) -> Status {
+        let targets = Target::VALUES.iter().map(|t| {
+            let manual_dir = manual.filter(|(target, _)| target == t).map(|(_, dir)| dir);
+            TargetStatus::read(platform, *t, manual_dir)
+        }).collect();
+
+        Status {
+            dotlocal: installer::is_dotlocal_enabled(platform),
+            targets
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("DotLocal: {}\n", if self.dotlocal { "enabled" } else { "disabled" });
+        for target in &self.targets {
+            text.push_str(&target.to_text());
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut root = HashMap::new();
+        root.insert("dotlocal".to_owned(), JsonValue::Boolean(self.dotlocal));
+        root.insert("targets".to_owned(), JsonValue::Array(self.targets.iter().map(TargetStatus::to_json).collect()));
+
+        // only strings, bools and nulls in here so this can't fail
+        JsonValue::Object(root).format().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{installer::{DEVOVERRIDE_REG_PATH, DEVOVERRIDE_VALUE, KOMOE_REG_KEY}, platform::fake::{FakePlatform, RegistryData}, test_utils::TempDir};
+
+    fn target(status: &Status, target: Target) -> &TargetStatus {
+        status.targets.iter().find(|t| t.target == target).unwrap()
+    }
+
+    #[test]
+    fn reports_where_each_target_was_found() {
+        let tmp = TempDir::new();
+        let komoe_dir = tmp.path().join("komoe");
+        std::fs::create_dir_all(&komoe_dir).unwrap();
+        std::fs::write(komoe_dir.join(Target::CriManaVpxKomoe.dll_name()), b"not a PE file").unwrap();
+        let dmm_dir = tmp.path().join("dmm");
+        std::fs::create_dir_all(&dmm_dir).unwrap();
+
+        let fake = FakePlatform::default();
+        fake.registry.set(KOMOE_REG_KEY, "GameInstallPath", RegistryData::String(komoe_dir.to_string_lossy().into_owned()));
+        fake.registry.set(DEVOVERRIDE_REG_PATH, DEVOVERRIDE_VALUE, RegistryData::U32(1));
+        let status = Status::read(&Rc::new(fake.build()), Some((Target::UnityPlayer, &dmm_dir)));
+        assert!(status.dotlocal);
+        assert_eq!(status.targets.len(), Target::VALUES.len());
+
+        let komoe = target(&status, Target::CriManaVpxKomoe);
+        assert_eq!(komoe.install_dir.as_deref(), Some(komoe_dir.as_path()));
+        assert!(komoe.source == Some(DetectionSource::KomoeRegistry));
+        // there's a DLL, just not ours
+        assert!(komoe.dll.is_some() && !komoe.is_hachimi_installed());
+        assert!(komoe.to_text().contains("other DLL present"));
+        assert!(komoe.cellar.is_none() && komoe.exe.is_none());
+
+        let dmm = target(&status, Target::UnityPlayer);
+        assert!(dmm.source == Some(DetectionSource::Manual));
+        assert!(dmm.dll.is_none());
+        assert_eq!(dmm.cellar, Some(false));
+    }
+
+    #[test]
+    fn json_has_every_target() {
+        let status = Status::read(&Rc::new(FakePlatform::default().build()), None);
+        assert!(!status.dotlocal);
+        let JsonValue::Object(root) = status.to_json().parse::<JsonValue>().unwrap() else {
+            panic!("not an object");
+        };
+        assert_eq!(root.get("dotlocal"), Some(&JsonValue::Boolean(false)));
+        let Some(JsonValue::Array(targets)) = root.get("targets") else {
+            panic!("no targets");
+        };
+        for (json, target) in targets.iter().zip(Target::VALUES) {
+            let JsonValue::Object(obj) = json else {
+                panic!("target isn't an object");
+            };
+            assert_eq!(obj.get("target"), Some(&JsonValue::String(target.id().to_owned())));
+            for key in ["dll_name", "install_dir", "source", "dll_path", "dll", "exe", "cellar"] {
+                assert!(obj.contains_key(key), "{} has no {}", target.id(), key);
+            }
+        }
+    }
+}
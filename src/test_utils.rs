@@ -0,0 +1,115 @@
+// Shared by the unit tests: a scratch dir that cleans up after itself, a tiny HTTP server and a staged download
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering}
+};
+#[cfg(feature = "net_install")]
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener
+};
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TempDir {
+    path: PathBuf
+}
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        let path = std::env::temp_dir().join(format!(
+            "hachimi_installer_test_{}_{}", std::process::id(), NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+// What the test server was asked for
+#[cfg(feature = "net_install")]
+pub struct Request {
+    pub path: String,
+    // from `Range: bytes=<start>-`
+    pub range_start: Option<u64>
+}
+
+// A raw HTTP response, Content-Length is the length of `body`
+#[cfg(feature = "net_install")]
+pub fn response(status: &str, headers: &[String], body: &[u8]) -> Vec<u8> {
+    let mut head = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n", status, body.len());
+    for header in headers {
+        head.push_str(header);
+        head.push_str("\r\n");
+    }
+    head.push_str("\r\n");
+    [head.into_bytes(), body.to_vec()].concat()
+}
+
+// Serves whatever raw response `handler` comes up with on localhost until the test process exits,
+// it can be cut short to simulate a dropped connection. Returns the base URL, e.g. "http://127.0.0.1:12345".
+#[cfg(feature = "net_install")]
+pub fn serve_with(handler: impl Fn(&Request) -> Vec<u8> + Send + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).is_err() {
+                continue;
+            }
+            // Range is the only header anything here cares about
+            let mut range_start = None;
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok_and(|n| n > 0) && line != "\r\n" {
+                if let Some(range) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                    range_start = range.split('-').next().and_then(|start| start.parse().ok());
+                }
+                line.clear();
+            }
+
+            let path = request_line.split_whitespace().nth(1).unwrap_or_default().to_owned();
+            _ = stream.write_all(&handler(&Request { path, range_start }));
+        }
+    });
+    base
+}
+
+// Serves `routes` (path, body), 404 for anything else
+#[cfg(feature = "net_install")]
+pub fn serve(routes: Vec<(String, Vec<u8>)>) -> String {
+    serve_with(move |request| match routes.iter().find(|(p, _)| *p == request.path) {
+        Some((_, body)) => response("200 OK", &[], body),
+        None => response("404 Not Found", &[], &[])
+    })
+}
+
+// An installer that already has its Hachimi download, so nothing goes out to the network
+#[cfg(feature = "net_install")]
+pub fn stage_download(installer: &crate::installer::Installer) {
+    use crate::release::{Downloaded, Sidecar};
+
+    let bytes = bytes::Bytes::from_static(b"downloaded hachimi.dll");
+    let sidecar = Sidecar {
+        tag: "v1.0.0".to_owned(),
+        asset_id: None,
+        size: bytes.len() as u64,
+        digest: format!("sha256:{}", crate::utils::sha256_hex(&bytes))
+    };
+    *installer.hachimi_version.lock().unwrap() = Some(sidecar.tag.clone());
+    *installer.hachimi_dll.lock().unwrap() = Some(Ok(Downloaded { bytes, sidecar }));
+}
@@ -1,53 +1,6 @@
-use std::{ffi::CStr, path::{Path, PathBuf}};
-use crate::i18n::{t};
+use std::path::Path;
 use pelite::resources::version_info::VersionInfo;
-use windows::{
-    core::HSTRING,
-    Win32::{
-        Foundation::{HWND, RECT},
-        System::{
-            Com::{CoCreateInstance, CLSCTX_INPROC_SERVER},
-            Diagnostics::ToolHelp::{
-                CreateToolhelp32Snapshot, Process32First, Process32Next, PROCESSENTRY32,
-                TH32CS_SNAPALL,
-            },
-        },
-        UI::{
-            Shell::{
-                FileOpenDialog, IFileOpenDialog, IShellItem, SHCreateItemFromParsingName,
-                FOS_FILEMUSTEXIST, FOS_PICKFOLDERS, SIGDN_FILESYSPATH,
-            },
-            WindowsAndMessaging::{GetDesktopWindow, GetWindowRect, SetWindowPos, SWP_NOSIZE},
-        },
-    },
-};
-
-pub trait RECTExt {
-    fn dimensions(&self) -> (i32, i32);
-}
-
-impl RECTExt for RECT {
-    fn dimensions(&self) -> (i32, i32) {
-        (self.right - self.left, self.bottom - self.top)
-    }
-}
-
-pub fn center_window(window: HWND) -> Result<(), windows::core::Error> {
-    let screen = unsafe { GetDesktopWindow() };
-    let mut screen_rect = RECT::default();
-    unsafe { GetWindowRect(screen, &mut screen_rect)? };
-    let (screen_width, screen_height) = screen_rect.dimensions();
-
-    let mut window_rect = RECT::default();
-    unsafe { GetWindowRect(window, &mut window_rect)? };
-    let (window_width, window_height) = window_rect.dimensions();
-
-    let x = screen_rect.left + (screen_width - window_width) / 2;
-    let y = screen_rect.top + (screen_height - window_height) / 2;
-    unsafe { SetWindowPos(window, None, x, y, 0, 0, SWP_NOSIZE)? };
-
-    Ok(())
-}
+use sha2::{Digest, Sha256};
 
 pub fn read_pe_version_info<'a>(image: &'a [u8]) -> Option<VersionInfo<'a>> {
     pelite::PeFile::from_bytes(image)
@@ -58,51 +11,28 @@ pub fn read_pe_version_info<'a>(image: &'a [u8]) -> Option<VersionInfo<'a>> {
         .ok()
 }
 
-pub fn open_select_folder_dialog<P: AsRef<Path>>(
-    owner: HWND,
-    default_folder: Option<P>,
-) -> Option<PathBuf> {
-    let dialog: IFileOpenDialog =
-        unsafe { CoCreateInstance(&FileOpenDialog, None, CLSCTX_INPROC_SERVER).ok()? };
-
-    unsafe {
-        dialog.SetTitle(&HSTRING::from(t!("util.select_folder"))).ok()?;
-        dialog
-            .SetOptions(FOS_FILEMUSTEXIST | FOS_PICKFOLDERS)
-            .ok()?;
-
-        if let Some(path) = default_folder {
-            let default_folder_item: IShellItem =
-                SHCreateItemFromParsingName(&HSTRING::from(path.as_ref().to_str().unwrap()), None)
-                    .ok()?;
-            dialog.SetDefaultFolder(&default_folder_item).ok()?;
-        }
-
-        dialog.Show(owner).ok()?
-    }
-
-    let result = unsafe { dialog.GetResult().ok()? };
-    let path = unsafe { result.GetDisplayName(SIGDN_FILESYSPATH).ok()? };
-    let path_str = unsafe { path.to_string().unwrap() };
-    Some(path_str.into())
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
 }
 
-pub fn is_game_running() -> bool {
-    let Ok(snapshot) = (unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPALL, 0) }) else {
-        return false;
-    };
-    let mut entry = PROCESSENTRY32::default();
-    entry.dwSize = std::mem::size_of::<PROCESSENTRY32>() as u32;
-    let mut res = unsafe { Process32First(snapshot, &mut entry) };
-
-    while res.is_ok() {
-        let process_name = unsafe { CStr::from_ptr(entry.szExeFile.as_ptr()) };
-        if process_name == c"umamusume.exe" || process_name == c"UmamusumePrettyDerby_Jpn.exe" || process_name == c"UmamusumePrettyDerby.exe" {
-            return true;
-        }
-
-        res = unsafe { Process32Next(snapshot, &mut entry) };
-    }
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
 
-    false
-}
\ No newline at end of file
+// YYYY-MM-DD (UTC), for PE timestamps
+pub fn format_unix_date(timestamp: u32) -> String {
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = (timestamp / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
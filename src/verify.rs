@@ -0,0 +1,315 @@
+use std::{collections::HashMap, path::{Path, PathBuf}};
+use tinyjson::JsonValue;
+use crate::{
+    backup::BackupEntry,
+    game::GameExeInfo,
+    installer::{Error, Installer, Target},
+    utils
+};
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum FileKind {
+    HachimiDll,
+    Cellar,
+    GameExe,
+    ExeBackup
+}
+
+impl FileKind {
+    pub fn id(&self) -> &'static str {
+        match self {
+            FileKind::HachimiDll => "hachimi_dll",
+            FileKind::Cellar => "cellar",
+            FileKind::GameExe => "game_exe",
+            FileKind::ExeBackup => "exe_backup"
+        }
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum FileState {
+    Ok,
+    Missing,
+    // ours, but not what we put there (or the game put its own version back)
+    Modified,
+    // something else entirely
+    Foreign
+}
+
+impl FileState {
+    pub fn id(&self) -> &'static str {
+        match self {
+            FileState::Ok => "ok",
+            FileState::Missing => "missing",
+            FileState::Modified => "modified",
+            FileState::Foreign => "foreign"
+        }
+    }
+}
+
+pub struct FileCheck {
+    pub kind: FileKind,
+    pub path: PathBuf,
+    pub state: FileState,
+    pub expected_sha256: Option<String>,
+    pub sha256: Option<String>
+}
+
+impl FileCheck {
+    // Plain hash comparison, for files we can't tell apart any other way
+    fn compare(kind: FileKind, path: PathBuf, expected_sha256: String) -> Result<FileCheck, Error> {
+        let sha256 = if path.is_file() { Some(utils::sha256_file(&path)?) } else { None };
+        let state = match &sha256 {
+            None => FileState::Missing,
+            Some(sha256) if *sha256 == expected_sha256 => FileState::Ok,
+            Some(_) => FileState::Modified
+        };
+        Ok(FileCheck { kind, path, state, expected_sha256: Some(expected_sha256), sha256 })
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let opt = |s: &Option<String>| s.clone().map(JsonValue::String).unwrap_or(JsonValue::Null);
+        let fields = vec![
+            ("kind", JsonValue::String(self.kind.id().to_owned())),
+            ("path", JsonValue::String(self.path.to_string_lossy().into_owned())),
+            ("state", JsonValue::String(self.state.id().to_owned())),
+            ("expected_sha256", opt(&self.expected_sha256)),
+            ("sha256", opt(&self.sha256))
+        ];
+        JsonValue::Object(fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+    }
+}
+
+pub struct VerifyReport {
+    pub target: Target,
+    pub install_dir: PathBuf,
+    pub files: Vec<FileCheck>
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.files.iter().all(|f| f.state == FileState::Ok)
+    }
+
+    pub fn get(&self, kind: FileKind) -> Option<&FileCheck> {
+        self.files.iter().find(|f| f.kind == kind)
+    }
+
+    // For the verify command, apart from the error codes
+    pub fn exit_code(&self) -> i32 {
+        if self.is_ok() { 0 } else { 110 }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "{} ({}) in {}\n",
+            self.target.id(), self.target.dll_name(), self.install_dir.display()
+        );
+        for file in &self.files {
+            text.push_str(&format!("  {:<9} {:<12} {}\n", file.state.id(), file.kind.id(), file.path.display()));
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut root = HashMap::new();
+        root.insert("target".to_owned(), JsonValue::String(self.target.id().to_owned()));
+        root.insert("install_dir".to_owned(), JsonValue::String(self.install_dir.to_string_lossy().into_owned()));
+        root.insert("ok".to_owned(), JsonValue::Boolean(self.is_ok()));
+        root.insert("files".to_owned(), JsonValue::Array(self.files.iter().map(FileCheck::to_json).collect()));
+
+        // only strings, bools and nulls in here so this can't fail
+        JsonValue::Object(root).format().unwrap_or_default()
+    }
+}
+
+fn check_dll(installer: &Installer) -> Result<FileCheck, Error> {
+    let path = installer.get_current_target_path().ok_or(Error::NoInstallDir)?;
+    let expected_sha256 = utils::sha256_hex(&installer.get_hachimi_dll()?);
+    let mut check = FileCheck::compare(FileKind::HachimiDll, path, expected_sha256)?;
+
+    // a different Hachimi is still ours, anything else isn't
+    if check.state == FileState::Modified {
+        let is_hachimi = Installer::read_target_version_info(&check.path).is_some_and(|v| v.is_hachimi());
+        if !is_hachimi {
+            check.state = FileState::Foreign;
+        }
+    }
+    Ok(check)
+}
+
+fn check_exe(path: &Path, info: Option<&GameExeInfo>) -> FileCheck {
+    let (state, expected_sha256) = match info {
+        None => (FileState::Missing, None),
+        Some(info) => match (info.patched, info.build) {
+            (Some(patched), _) => (FileState::Ok, Some(patched.sha256.to_owned())),
+            // known build, but the patch is gone, e.g. Steam restored the exe
+            (None, Some(build)) => (FileState::Modified, Some(build.sha256.to_owned())),
+            (None, None) => (FileState::Foreign, None)
+        }
+    };
+    FileCheck {
+        kind: FileKind::GameExe,
+        path: path.to_owned(),
+        state,
+        expected_sha256,
+        sha256: info.map(|i| i.sha256.clone())
+    }
+}
+
+fn check_exe_backup(installer: &Installer, exe_path: &Path, info: Option<&GameExeInfo>) -> Result<FileCheck, Error> {
+    let store = installer.get_backup_store().ok_or(Error::NoInstallDir)?;
+    // the original the current exe is (or was patched from)
+    let expected_sha256 = info.and_then(|i| i.source_sha256()).map(str::to_owned);
+
+    let check_entry = |entry: BackupEntry, state_if_valid: FileState| -> FileCheck {
+        let state = if entry.verify().is_ok() { state_if_valid } else { FileState::Modified };
+        FileCheck {
+            kind: FileKind::ExeBackup,
+            sha256: utils::sha256_file(&entry.file).ok(),
+            path: entry.file,
+            state,
+            expected_sha256: expected_sha256.clone()
+        }
+    };
+
+    if let Some(expected) = &expected_sha256 {
+        if let Some(entry) = store.find_by_hash(exe_path, expected)? {
+            return Ok(check_entry(entry, FileState::Ok));
+        }
+
+        let legacy_exe = installer.get_legacy_backup_exe_path().ok_or(Error::NoInstallDir)?;
+        if legacy_exe.is_file() {
+            let mut check = FileCheck::compare(FileKind::ExeBackup, legacy_exe, expected.clone())?;
+            if check.state == FileState::Modified {
+                check.state = FileState::Foreign;
+            }
+            return Ok(check);
+        }
+    }
+
+    // no idea which original we'd need, or there's only a backup of some other exe
+    let latest = store.list()?.into_iter().rev().find(|e| e.path == exe_path);
+    Ok(match latest {
+        Some(entry) => {
+            let state = if expected_sha256.is_some() { FileState::Foreign } else { FileState::Ok };
+            check_entry(entry, state)
+        },
+        None => FileCheck {
+            kind: FileKind::ExeBackup,
+            path: store.dir().to_owned(),
+            state: FileState::Missing,
+            expected_sha256,
+            sha256: None
+        }
+    })
+}
+
+// Checks everything that belongs to the installer's current target against what we'd install
+pub fn verify(installer: &Installer) -> Result<VerifyReport, Error> {
+    let install_dir = installer.install_dir.clone().ok_or(Error::NoInstallDir)?;
+    let mut files = vec![check_dll(installer)?];
+
+    match installer.target {
+        Target::UnityPlayer => {
+            let path = installer.get_cellar_path().ok_or(Error::NoInstallDir)?;
+            let expected_sha256 = utils::sha256_hex(&Installer::get_cellar_dll());
+            files.push(FileCheck::compare(FileKind::Cellar, path, expected_sha256)?);
+        },
+        Target::CriManaVpx => {
+            let exe_path = installer.get_orig_exe_path().ok_or(Error::NoInstallDir)?;
+            let info = if exe_path.is_file() { Some(GameExeInfo::read(&exe_path)?) } else { None };
+            files.push(check_exe(&exe_path, info.as_ref()));
+            files.push(check_exe_backup(installer, &exe_path, info.as_ref())?);
+        },
+        _ => {}
+    }
+
+    Ok(VerifyReport { target: installer.target, install_dir, files })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use super::*;
+    use crate::{exe_patch::{ExePatch, PatchedExe}, platform::fake::FakePlatform, test_utils::TempDir};
+    #[cfg(feature = "net_install")]
+    use crate::test_utils::stage_download;
+
+    fn installer(tmp: &TempDir, target: Target) -> Installer {
+        let game_dir = tmp.path().join("game");
+        std::fs::create_dir_all(&game_dir).unwrap();
+        let installer = Installer::custom(Rc::new(FakePlatform::default().build()), Some(game_dir), target, None);
+        #[cfg(feature = "net_install")]
+        stage_download(&installer);
+        installer
+    }
+
+    #[test]
+    fn checks_the_dll_against_the_payload() {
+        let tmp = TempDir::new();
+        let installer = installer(&tmp, Target::CriManaVpxKomoe);
+        let dll = installer.get_current_target_path().unwrap();
+        let state = || {
+            let report = verify(&installer).unwrap();
+            assert_eq!(report.exit_code(), if report.is_ok() { 0 } else { 110 });
+            report.get(FileKind::HachimiDll).unwrap().state
+        };
+
+        assert!(state() == FileState::Missing);
+        std::fs::write(&dll, b"the game's own DLL").unwrap();
+        assert!(state() == FileState::Foreign);
+        std::fs::write(&dll, installer.get_hachimi_dll().unwrap()).unwrap();
+        assert!(state() == FileState::Ok);
+        assert_eq!(verify(&installer).unwrap().files.len(), 1);
+    }
+
+    static BUILD: ExePatch = ExePatch {
+        build: "1.0.0",
+        product_version: None,
+        timestamp: None,
+        source_sha256: "original",
+        sha256: "patched",
+        patch: &[]
+    };
+    static PATCHED: PatchedExe = PatchedExe { build: "1.0.0", source_sha256: "original", sha256: "patched" };
+
+    fn info(sha256: &str, build: Option<&'static ExePatch>, patched: Option<&'static PatchedExe>) -> GameExeInfo {
+        GameExeInfo { sha256: sha256.to_owned(), product_version: None, timestamp: None, build, patched }
+    }
+
+    #[test]
+    fn tells_exe_states_apart() {
+        let path = Path::new("game.exe");
+        let state = |info: Option<&GameExeInfo>| check_exe(path, info).state;
+        assert!(state(None) == FileState::Missing);
+        assert!(state(Some(&info("patched", Some(&BUILD), Some(&PATCHED)))) == FileState::Ok);
+        // Steam put the original back, it should be the patched one
+        let vanilla = check_exe(path, Some(&info("original", Some(&BUILD), None)));
+        assert!(vanilla.state == FileState::Modified);
+        assert_eq!(vanilla.expected_sha256.as_deref(), Some("patched"));
+        assert!(state(Some(&info("something else", None, None))) == FileState::Foreign);
+    }
+
+    #[test]
+    fn checks_the_exe_backup() {
+        let tmp = TempDir::new();
+        let installer = installer(&tmp, Target::CriManaVpx);
+        let exe = installer.get_orig_exe_path().unwrap();
+        let store = installer.get_backup_store().unwrap();
+        let state = |info: Option<&GameExeInfo>| check_exe_backup(&installer, &exe, info).unwrap().state;
+        assert!(state(None) == FileState::Missing);
+
+        let bytes = b"original exe";
+        let entry = store.prepare(&exe, utils::sha256_hex(bytes), bytes.len() as u64, None, None);
+        std::fs::create_dir_all(entry.dir()).unwrap();
+        std::fs::write(&entry.file, bytes).unwrap();
+        std::fs::write(entry.meta_path(), store.meta_json(&entry)).unwrap();
+        assert!(state(None) == FileState::Ok);
+        // a backup of some other original than the one the exe was patched from
+        assert!(state(Some(&info("patched", Some(&BUILD), Some(&PATCHED)))) == FileState::Foreign);
+
+        std::fs::write(&entry.file, b"damaged").unwrap();
+        assert!(state(None) == FileState::Modified);
+    }
+}
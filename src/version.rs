@@ -0,0 +1,159 @@
+use std::cmp::Ordering;
+
+// ProductVersion strings and release tags: "1.2.3", "v1.2.3", "1.2.3.0", "v1.2.3-beta.1"
+#[derive(Clone, Debug)]
+pub struct Version {
+    pub numbers: Vec<u64>,
+    // anything after the first '-', a version with one sorts before the same version without
+    pub pre: Option<String>
+}
+
+impl Version {
+    pub fn parse(s: &str) -> Option<Version> {
+        let s = s.trim();
+        let s = s.strip_prefix(['v', 'V']).unwrap_or(s);
+        // build metadata doesn't take part in ordering
+        let s = s.split_once('+').map(|(v, _)| v).unwrap_or(s);
+        let (numbers, pre) = match s.split_once('-') {
+            Some((numbers, pre)) if !pre.is_empty() => (numbers, Some(pre.to_owned())),
+            Some(_) => return None,
+            None => (s, None)
+        };
+
+        let numbers = numbers.split('.').map(|n| n.parse().ok()).collect::<Option<Vec<u64>>>()?;
+        Some(Version { numbers, pre })
+    }
+
+    fn number(&self, i: usize) -> u64 {
+        self.numbers.get(i).copied().unwrap_or(0)
+    }
+}
+
+// Dot separated identifiers, numeric ones compared as numbers and before any text
+fn compare_pre(a: &str, b: &str) -> Ordering {
+    let mut a_parts = a.split('.');
+    let mut b_parts = b.split('.');
+    loop {
+        let ord = match (a_parts.next(), b_parts.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) => match (a.parse::<u64>(), b.parse::<u64>()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                (Ok(_), Err(_)) => Ordering::Less,
+                (Err(_), Ok(_)) => Ordering::Greater,
+                (Err(_), Err(_)) => a.cmp(b)
+            }
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // missing numbers count as 0, so 1.2 == 1.2.0.0
+        let len = self.numbers.len().max(other.numbers.len());
+        for i in 0..len {
+            match self.number(i).cmp(&other.number(i)) {
+                Ordering::Equal => continue,
+                ord => return ord
+            }
+        }
+
+        match (&self.pre, &other.pre) {
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(_), None) => Ordering::Less,
+            (Some(a), Some(b)) => compare_pre(a, b)
+        }
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let numbers: Vec<String> = self.numbers.iter().map(|n| n.to_string()).collect();
+        write!(f, "{}", numbers.join("."))?;
+        if let Some(pre) = &self.pre {
+            write!(f, "-{}", pre)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> Version {
+        Version::parse(s).unwrap()
+    }
+
+    #[test]
+    fn parses_prefixed_tags() {
+        assert_eq!(v("v1.2.3").numbers, [1, 2, 3]);
+        assert_eq!(v("V1.2.3").numbers, [1, 2, 3]);
+        assert_eq!(v(" 1.2.3.0 ").numbers, [1, 2, 3, 0]);
+        assert_eq!(v("v1.2.3"), v("1.2.3"));
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(Version::parse("").is_none());
+        assert!(Version::parse("v").is_none());
+        assert!(Version::parse("1..2").is_none());
+        assert!(Version::parse("1.2.x").is_none());
+        assert!(Version::parse("1.2.3-").is_none());
+        assert!(Version::parse("latest").is_none());
+    }
+
+    #[test]
+    fn missing_components_are_zero() {
+        assert_eq!(v("1.2"), v("1.2.0.0"));
+        assert!(v("1.2") < v("1.2.0.1"));
+        assert!(v("1.10") > v("1.9.9"));
+        assert!(v("2") > v("1.99.99"));
+    }
+
+    #[test]
+    fn pre_release_sorts_first() {
+        assert_eq!(v("1.2.3-beta.1").pre.as_deref(), Some("beta.1"));
+        assert!(v("1.2.3-beta.1") < v("1.2.3"));
+        assert!(v("1.2.3-beta.1") > v("1.2.2"));
+        assert!(v("1.2.3-alpha") < v("1.2.3-beta"));
+        assert!(v("1.2.3-beta.2") < v("1.2.3-beta.10"));
+        assert!(v("1.2.3-1") < v("1.2.3-alpha"));
+        assert!(v("1.2.3-beta") < v("1.2.3-beta.1"));
+        // the pre-release part is only compared once the numbers are equal
+        assert_eq!(v("1.2-rc.1"), v("1.2.0-rc.1"));
+    }
+
+    #[test]
+    fn build_metadata_is_ignored() {
+        assert_eq!(v("1.2.3+abc"), v("1.2.3"));
+        assert_eq!(v("1.2.3+abc"), v("1.2.3+def"));
+        assert_eq!(v("1.2.3-rc.1+abc").pre.as_deref(), Some("rc.1"));
+        assert!(v("1.2.3-rc.1+abc") < v("1.2.3+abc"));
+    }
+
+    #[test]
+    fn displays_without_prefix_or_build() {
+        assert_eq!(v("v1.2.3").to_string(), "1.2.3");
+        assert_eq!(v("v1.2.3-beta.1+abc").to_string(), "1.2.3-beta.1");
+    }
+}